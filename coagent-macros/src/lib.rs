@@ -0,0 +1,222 @@
+//! Derive macros for coagent. See `coagent::agent::Agent`.
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, Attribute, Data, DeriveInput, Expr, Fields, GenericArgument, Ident, PathArguments, Type};
+
+/// Generates the builder of an agent. The builder has the setters shared by
+/// every agent (`name`, `description`, `sub_agents`, `sub_agent_spec`,
+/// `supervision` and the callbacks), one setter per field of the struct,
+/// and `build`, `arguments`, `spawn` and `spec`. The author implements
+/// `coagent::agent::Agent` for the struct.
+///
+/// Setters of `Option<T>` fields take a `T`. Fields start from
+/// `Default::default()` unless marked:
+///
+/// - `#[agent(required)]`: the field becomes a parameter of `builder()`.
+/// - `#[agent(default = expr)]`: the field starts from `expr`.
+#[proc_macro_derive(Agent, attributes(agent))]
+pub fn derive_agent(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input).unwrap_or_else(|error| error.to_compile_error()).into()
+}
+
+enum Init {
+    Required,
+    Default,
+    Expr(Expr),
+}
+
+struct Field {
+    ident: Ident,
+    ty: Type,
+    docs: Vec<Attribute>,
+    init: Init,
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(&input.generics, "#[derive(Agent)] does not support generic agents"));
+    }
+    let data = match &input.data {
+        Data::Struct(data) => data,
+        _ => return Err(syn::Error::new(Span::call_site(), "#[derive(Agent)] only supports structs")),
+    };
+    let (fields, unit) = match &data.fields {
+        Fields::Named(named) => (named.named.iter().map(parse_field).collect::<syn::Result<Vec<_>>>()?, false),
+        Fields::Unit => (Vec::new(), true),
+        Fields::Unnamed(unnamed) => {
+            return Err(syn::Error::new_spanned(unnamed, "#[derive(Agent)] needs named fields"));
+        }
+    };
+
+    let agent = &input.ident;
+    let vis = &input.vis;
+    let builder = format_ident!("{}Builder", agent);
+    let builder_doc = format!("Builder for [`{}`].", agent);
+
+    let required: Vec<&Field> = fields.iter().filter(|field| matches!(field.init, Init::Required)).collect();
+    let builder_params = required.iter().map(|Field { ident, ty, .. }| quote! { #ident: #ty });
+    let builder_fields = fields.iter().map(|Field { ident, ty, .. }| quote! { #ident: #ty });
+    let builder_inits = fields.iter().map(|Field { ident, init, .. }| match init {
+        Init::Required => quote! { #ident },
+        Init::Default => quote! { #ident: ::std::default::Default::default() },
+        Init::Expr(expr) => quote! { #ident: #expr },
+    });
+    let setters = fields.iter().filter(|field| !matches!(field.init, Init::Required)).map(|field| {
+        let Field { ident, ty, docs, .. } = field;
+        match option_inner(ty) {
+            Some(inner) => quote! {
+                #(#docs)*
+                pub fn #ident(mut self, #ident: #inner) -> Self {
+                    self.#ident = ::std::option::Option::Some(#ident);
+                    self
+                }
+            },
+            None => quote! {
+                #(#docs)*
+                pub fn #ident(mut self, #ident: #ty) -> Self {
+                    self.#ident = #ident;
+                    self
+                }
+            },
+        }
+    });
+    let field_idents: Vec<&Ident> = fields.iter().map(|field| &field.ident).collect();
+    let build = if unit {
+        quote! { #agent }
+    } else {
+        quote! { #agent { #(#field_idents: self.#field_idents),* } }
+    };
+
+    Ok(quote! {
+        impl #agent {
+            #vis fn builder(#(#builder_params),*) -> #builder {
+                #builder {
+                    arguments: ::std::default::Default::default(),
+                    #(#builder_inits,)*
+                }
+            }
+        }
+
+        #[doc = #builder_doc]
+        #[derive(Clone)]
+        #vis struct #builder {
+            arguments: ::coagent::base_agent::BaseAgentArguments,
+            #(#builder_fields,)*
+        }
+
+        impl #builder {
+            pub fn name(mut self, name: ::std::string::String) -> Self {
+                self.arguments.name = name;
+                self
+            }
+
+            pub fn description(mut self, description: ::std::string::String) -> Self {
+                self.arguments.description = description;
+                self
+            }
+
+            pub fn sub_agents(mut self, sub_agents: ::std::vec::Vec<::std::sync::Arc<::coagent::ractor::ActorCell>>) -> Self {
+                self.arguments.sub_agents = sub_agents;
+                self
+            }
+
+            /// Adds a sub-agent that this agent spawns when it starts and
+            /// restarts when it fails. These run after the ones given to
+            /// `sub_agents`.
+            pub fn sub_agent_spec(mut self, spec: ::coagent::supervision::ChildSpec) -> Self {
+                self.arguments.child_specs.push(spec);
+                self
+            }
+
+            pub fn supervision(mut self, supervision: ::coagent::supervision::SupervisionConfig) -> Self {
+                self.arguments.supervision = supervision;
+                self
+            }
+
+            pub fn before_agent_callback(mut self, callback: ::coagent::base_agent::BeforeAgentCallback) -> Self {
+                self.arguments.before_agent_callback.get_or_insert_with(::std::vec::Vec::new).push(callback);
+                self
+            }
+
+            pub fn after_agent_callback(mut self, callback: ::coagent::base_agent::AfterAgentCallback) -> Self {
+                self.arguments.after_agent_callback.get_or_insert_with(::std::vec::Vec::new).push(callback);
+                self
+            }
+
+            pub fn before_tool_callback(mut self, callback: ::coagent::base_agent::BeforeToolCallback) -> Self {
+                self.arguments.before_tool_callback.get_or_insert_with(::std::vec::Vec::new).push(callback);
+                self
+            }
+
+            pub fn after_tool_callback(mut self, callback: ::coagent::base_agent::AfterToolCallback) -> Self {
+                self.arguments.after_tool_callback.get_or_insert_with(::std::vec::Vec::new).push(callback);
+                self
+            }
+
+            #(#setters)*
+
+            pub fn build(self) -> #agent {
+                #build
+            }
+
+            /// The arguments to spawn the built agent with.
+            pub fn arguments(&self) -> ::coagent::base_agent::BaseAgentArguments {
+                self.arguments.clone()
+            }
+
+            pub async fn spawn(
+                self,
+            ) -> ::std::result::Result<::coagent::ractor::ActorRef<::coagent::base_agent::BaseAgentMessage>, ::coagent::common::AgentError> {
+                let arguments = self.arguments();
+                ::coagent::base_agent::BaseAgent::spawn(::coagent::agent::AgentActor::new(self.build()), arguments).await
+            }
+
+            /// A spec for a parent to spawn and supervise the agent with.
+            pub fn spec(self) -> ::coagent::supervision::ChildSpec {
+                let arguments = self.arguments();
+                ::coagent::supervision::ChildSpec::new(::coagent::agent::AgentActor::new(self.build()), arguments)
+            }
+        }
+    })
+}
+
+fn parse_field(field: &syn::Field) -> syn::Result<Field> {
+    let mut init = Init::Default;
+    for attr in field.attrs.iter().filter(|attr| attr.path().is_ident("agent")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("required") {
+                init = Init::Required;
+                Ok(())
+            } else if meta.path.is_ident("default") {
+                init = Init::Expr(meta.value()?.parse()?);
+                Ok(())
+            } else {
+                Err(meta.error("expected `required` or `default = ...`"))
+            }
+        })?;
+    }
+    Ok(Field {
+        ident: field.ident.clone().expect("named field"),
+        ty: field.ty.clone(),
+        docs: field.attrs.iter().filter(|attr| attr.path().is_ident("doc")).cloned().collect(),
+        init,
+    })
+}
+
+fn option_inner(ty: &Type) -> Option<&Type> {
+    let Type::Path(path) = ty else { return None };
+    let segment = path.path.segments.last()?;
+    if segment.ident != "Option" {
+        return None;
+    }
+    match &segment.arguments {
+        PathArguments::AngleBracketed(args) if args.args.len() == 1 => match args.args.first()? {
+            GenericArgument::Type(inner) => Some(inner),
+            _ => None,
+        },
+        _ => None,
+    }
+}
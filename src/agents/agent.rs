@@ -0,0 +1,106 @@
+//! The extension point for agents. A type implementing `Agent` supplies only
+//! its own logic; wrapped in an `AgentActor` it becomes a full member of an
+//! agent tree, with callbacks, spans, branches and supervised sub-agents
+//! handled the same way for every agent. `#[derive(Agent)]` generates the
+//! agent's builder:
+//!
+//! ```ignore
+//! #[derive(Clone, Agent)]
+//! pub struct Echo {
+//!     #[agent(default = "echo: ".to_string())]
+//!     prefix: String,
+//! }
+//!
+//! #[async_trait]
+//! impl Agent for Echo {
+//!     async fn run_async_impl(&self, state: &BaseAgentState, context: &InvocationContext) -> Result<Vec<Event>, AgentError> {
+//!         // ...
+//!     }
+//! }
+//!
+//! let echo = Echo::builder().name("echo".to_string()).prefix("> ".to_string()).spawn().await?;
+//! ```
+
+use crate::base_agent::{BaseAgentArguments, BaseAgentMessage, BaseAgentState};
+use crate::common::{AgentError, Event};
+use crate::invocation_context::InvocationContext;
+use async_trait::async_trait;
+use ractor::{Actor, ActorProcessingErr, ActorRef, SupervisionEvent};
+
+pub use coagent_macros::Agent;
+
+#[async_trait]
+pub trait Agent: Clone + Send + Sync + 'static {
+    /// Runs one invocation. `state` gives the agent's configuration in
+    /// `state.base` and its sub-agents in `state.supervisor`.
+    async fn run_async_impl(&self, state: &BaseAgentState, context: &InvocationContext) -> Result<Vec<Event>, AgentError>;
+
+    /// Serves a live session. By default every user turn is run like an
+    /// invocation of `run_async_impl`.
+    async fn run_live_impl(&self, state: &BaseAgentState, context: &InvocationContext) -> Result<Vec<Event>, AgentError> {
+        state
+            .base
+            .run_live_turns(context, |context| async move { state.run_async(self, context).await })
+            .await
+    }
+}
+
+/// The actor running an `Agent`.
+#[derive(Clone)]
+pub struct AgentActor<A: Agent> {
+    agent: A,
+}
+
+impl<A: Agent> AgentActor<A> {
+    pub fn new(agent: A) -> Self {
+        AgentActor { agent }
+    }
+
+    pub fn agent(&self) -> &A {
+        &self.agent
+    }
+}
+
+#[async_trait]
+impl<A: Agent> Actor for AgentActor<A> {
+    type Msg = BaseAgentMessage;
+    type State = BaseAgentState;
+    type Arguments = BaseAgentArguments;
+
+    async fn pre_start(&self, this_actor: ActorRef<Self::Msg>, args: Self::Arguments) -> Result<Self::State, ActorProcessingErr> {
+        BaseAgentState::start(this_actor, args).await
+    }
+
+    async fn handle(
+        &self,
+        this_actor: ActorRef<Self::Msg>,
+        message: Self::Msg,
+        state: &mut Self::State,
+    ) -> Result<(), ActorProcessingErr> {
+        match message {
+            BaseAgentMessage::RunAsync { context, sender } => {
+                let (agent, run_state) = (self.agent.clone(), state.clone());
+                state.spawn_run(this_actor, context, sender, move |context| async move {
+                    run_state.run_async(&agent, context).await
+                });
+            }
+            BaseAgentMessage::RunLive { context, sender } => {
+                let (agent, run_state) = (self.agent.clone(), state.clone());
+                state.spawn_run(this_actor, context, sender, move |context| async move {
+                    agent.run_live_impl(&run_state, &context).await
+                });
+            }
+            message => state.handle_query(message)?,
+        }
+        Ok(())
+    }
+
+    async fn handle_supervisor_evt(
+        &self,
+        _this_actor: ActorRef<Self::Msg>,
+        event: SupervisionEvent,
+        state: &mut Self::State,
+    ) -> Result<(), ActorProcessingErr> {
+        state.supervisor.handle_supervisor_evt(event).await
+    }
+}
@@ -0,0 +1,836 @@
+//! Declarative agent trees. A YAML or JSON file describes the tree, tools,
+//! callbacks and models are referenced by the names they were registered
+//! under in an `AgentRegistry`, and `load_agent_tree` spawns the actors and
+//! returns the root. Every validation error carries the line it refers to.
+//!
+//! ```yaml
+//! kind: sequential
+//! name: pipeline
+//! sub_agents:
+//!   - kind: llm
+//!     name: researcher
+//!     model: gpt-4o-mini
+//!     instruction: Research {topic}.
+//!     tools: [load_memory]
+//!     output_key: findings
+//!   - kind: llm
+//!     name: writer
+//!     model: gpt-4o-mini
+//!     instruction: "Write a summary of: {findings}"
+//! ```
+
+use crate::base_agent::{AfterAgentCallback, AfterToolCallback, BeforeAgentCallback, BeforeToolCallback};
+use crate::base_tool::BaseTool;
+use crate::common::AgentError;
+use crate::llm_agent::LlmAgent;
+use crate::loop_agent::LoopAgent;
+use crate::models::BaseLlm;
+use crate::parallel_agent::ParallelAgent;
+use crate::sequential_agent::SequentialAgent;
+use ractor::ActorCell;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use yaml_rust2::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust2::scanner::{Marker, TScalarStyle};
+use yaml_rust2::Yaml;
+
+pub type ModelProvider = Arc<dyn Fn(&str) -> Option<Arc<dyn BaseLlm>> + Send + Sync>;
+
+/// Named tools, callbacks and models that agent configs can refer to.
+#[derive(Clone, Default)]
+pub struct AgentRegistry {
+    tools: HashMap<String, Arc<dyn BaseTool>>,
+    models: HashMap<String, Arc<dyn BaseLlm>>,
+    model_provider: Option<ModelProvider>,
+    default_model: Option<String>,
+    before_agent_callbacks: HashMap<String, BeforeAgentCallback>,
+    after_agent_callbacks: HashMap<String, AfterAgentCallback>,
+    before_tool_callbacks: HashMap<String, BeforeToolCallback>,
+    after_tool_callbacks: HashMap<String, AfterToolCallback>,
+}
+
+impl AgentRegistry {
+    pub fn new() -> Self {
+        AgentRegistry::default()
+    }
+
+    /// Registers a tool under its own name.
+    pub fn tool(mut self, tool: Arc<dyn BaseTool>) -> Self {
+        self.tools.insert(tool.name().to_string(), tool);
+        self
+    }
+
+    pub fn model(mut self, name: String, model: Arc<dyn BaseLlm>) -> Self {
+        self.models.insert(name, model);
+        self
+    }
+
+    /// Creates models for names that were not registered with `model`, e.g.
+    /// a client for an OpenAI-compatible endpoint that serves many models.
+    pub fn model_provider(mut self, model_provider: ModelProvider) -> Self {
+        self.model_provider = Some(model_provider);
+        self
+    }
+
+    /// Model used by LLM agents whose config does not name one.
+    pub fn default_model(mut self, name: String) -> Self {
+        self.default_model = Some(name);
+        self
+    }
+
+    pub fn before_agent_callback(mut self, name: String, callback: BeforeAgentCallback) -> Self {
+        self.before_agent_callbacks.insert(name, callback);
+        self
+    }
+
+    pub fn after_agent_callback(mut self, name: String, callback: AfterAgentCallback) -> Self {
+        self.after_agent_callbacks.insert(name, callback);
+        self
+    }
+
+    pub fn before_tool_callback(mut self, name: String, callback: BeforeToolCallback) -> Self {
+        self.before_tool_callbacks.insert(name, callback);
+        self
+    }
+
+    pub fn after_tool_callback(mut self, name: String, callback: AfterToolCallback) -> Self {
+        self.after_tool_callbacks.insert(name, callback);
+        self
+    }
+
+    pub fn get_tool(&self, name: &str) -> Option<Arc<dyn BaseTool>> {
+        self.tools.get(name).cloned()
+    }
+
+    pub fn get_model(&self, name: &str) -> Option<Arc<dyn BaseLlm>> {
+        self.models
+            .get(name)
+            .cloned()
+            .or_else(|| self.model_provider.as_ref().and_then(|provider| provider(name)))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AgentKind {
+    Llm,
+    Sequential,
+    Parallel,
+    Loop,
+}
+
+impl AgentKind {
+    fn parse(kind: &str) -> Option<Self> {
+        match kind {
+            "llm" => Some(AgentKind::Llm),
+            "sequential" => Some(AgentKind::Sequential),
+            "parallel" => Some(AgentKind::Parallel),
+            "loop" => Some(AgentKind::Loop),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AgentKind::Llm => "llm",
+            AgentKind::Sequential => "sequential",
+            AgentKind::Parallel => "parallel",
+            AgentKind::Loop => "loop",
+        }
+    }
+}
+
+/// One agent of a declarative tree, with the line it starts on.
+#[derive(Clone, Debug)]
+pub struct AgentConfig {
+    pub kind: AgentKind,
+    pub name: String,
+    pub description: String,
+    pub model: Option<String>,
+    pub instruction: Option<String>,
+    pub tools: Vec<String>,
+    pub sub_agents: Vec<AgentConfig>,
+    pub max_iterations: Option<i32>,
+    pub output_key: Option<String>,
+    pub output_schema: Option<serde_json::Value>,
+    pub before_agent_callbacks: Vec<String>,
+    pub after_agent_callbacks: Vec<String>,
+    pub before_tool_callbacks: Vec<String>,
+    pub after_tool_callbacks: Vec<String>,
+    pub line: usize,
+    field_lines: HashMap<String, usize>,
+}
+
+#[derive(Clone, Debug)]
+enum NodeValue {
+    Scalar(serde_json::Value),
+    Sequence(Vec<Node>),
+    Mapping(Vec<(String, Node)>),
+}
+
+#[derive(Clone, Debug)]
+struct Node {
+    line: usize,
+    value: NodeValue,
+}
+
+impl Node {
+    fn type_name(&self) -> &'static str {
+        match &self.value {
+            NodeValue::Scalar(serde_json::Value::String(_)) => "a string",
+            NodeValue::Scalar(serde_json::Value::Null) => "null",
+            NodeValue::Scalar(_) => "a scalar",
+            NodeValue::Sequence(_) => "a list",
+            NodeValue::Mapping(_) => "a mapping",
+        }
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        match &self.value {
+            NodeValue::Scalar(value) => value.clone(),
+            NodeValue::Sequence(items) => serde_json::Value::Array(items.iter().map(Node::to_json).collect()),
+            NodeValue::Mapping(entries) => serde_json::Value::Object(
+                entries.iter().map(|(key, value)| (key.clone(), value.to_json())).collect(),
+            ),
+        }
+    }
+}
+
+/// Builds a `Node` tree from the parser events, keeping the line of every
+/// node.
+#[derive(Default)]
+struct NodeBuilder {
+    stack: Vec<(Node, usize, Option<String>)>,
+    anchors: HashMap<usize, Node>,
+    documents: Vec<Node>,
+    errors: Vec<String>,
+}
+
+impl NodeBuilder {
+    fn scalar(value: String, style: TScalarStyle) -> serde_json::Value {
+        if style != TScalarStyle::Plain {
+            return serde_json::Value::String(value);
+        }
+        match Yaml::from_str(&value) {
+            Yaml::Integer(number) => serde_json::Value::from(number),
+            Yaml::Real(number) => number
+                .parse::<f64>()
+                .ok()
+                .and_then(serde_json::Number::from_f64)
+                .map(serde_json::Value::Number)
+                .unwrap_or(serde_json::Value::String(value)),
+            Yaml::Boolean(boolean) => serde_json::Value::Bool(boolean),
+            Yaml::Null => serde_json::Value::Null,
+            _ => serde_json::Value::String(value),
+        }
+    }
+
+    fn insert(&mut self, node: Node, anchor: usize) {
+        if anchor > 0 {
+            self.anchors.insert(anchor, node.clone());
+        }
+        match self.stack.last_mut() {
+            None => self.documents.push(node),
+            Some((Node { value: NodeValue::Sequence(items), .. }, _, _)) => items.push(node),
+            Some((Node { value: NodeValue::Mapping(entries), .. }, _, pending_key)) => match pending_key.take() {
+                Some(key) => entries.push((key, node)),
+                None => match node.value {
+                    NodeValue::Scalar(serde_json::Value::String(key)) => *pending_key = Some(key),
+                    NodeValue::Scalar(key) => *pending_key = Some(key.to_string()),
+                    _ => {
+                        self.errors.push(format!("line {}: mapping keys must be scalars", node.line));
+                        *pending_key = Some(String::new());
+                    }
+                },
+            },
+            Some(_) => {}
+        }
+    }
+}
+
+impl MarkedEventReceiver for NodeBuilder {
+    fn on_event(&mut self, event: Event, mark: Marker) {
+        let line = mark.line();
+        match event {
+            Event::Scalar(value, style, anchor, _) => {
+                let node = Node {
+                    line,
+                    value: NodeValue::Scalar(Self::scalar(value, style)),
+                };
+                self.insert(node, anchor);
+            }
+            Event::SequenceStart(anchor, _) => {
+                let node = Node { line, value: NodeValue::Sequence(Vec::new()) };
+                self.stack.push((node, anchor, None));
+            }
+            Event::MappingStart(anchor, _) => {
+                let node = Node { line, value: NodeValue::Mapping(Vec::new()) };
+                self.stack.push((node, anchor, None));
+            }
+            Event::SequenceEnd | Event::MappingEnd => {
+                if let Some((node, anchor, _)) = self.stack.pop() {
+                    self.insert(node, anchor);
+                }
+            }
+            Event::Alias(anchor) => match self.anchors.get(&anchor).cloned() {
+                Some(node) => self.insert(node, 0),
+                None => self.errors.push(format!("line {}: unknown alias", line)),
+            },
+            _ => {}
+        }
+    }
+}
+
+fn parse_document(source: &str) -> Result<Node, AgentError> {
+    let mut builder = NodeBuilder::default();
+    Parser::new_from_str(source)
+        .load(&mut builder, false)
+        .map_err(|e| AgentError::ConfigError(format!("line {}: {}", e.marker().line(), e.info())))?;
+    if !builder.errors.is_empty() {
+        return Err(AgentError::ConfigError(builder.errors.join("\n")));
+    }
+    builder
+        .documents
+        .pop()
+        .ok_or_else(|| AgentError::ConfigError("line 1: the agent config is empty".to_string()))
+}
+
+const FIELDS: &[&str] = &[
+    "kind",
+    "name",
+    "description",
+    "model",
+    "instruction",
+    "tools",
+    "sub_agents",
+    "max_iterations",
+    "output_key",
+    "output_schema",
+    "before_agent_callbacks",
+    "after_agent_callbacks",
+    "before_tool_callbacks",
+    "after_tool_callbacks",
+];
+
+const LLM_FIELDS: &[&str] = &["model", "instruction", "tools", "output_key", "output_schema"];
+
+fn string_field(node: &Node, field: &str, errors: &mut Vec<String>) -> Option<String> {
+    match &node.value {
+        NodeValue::Scalar(serde_json::Value::String(value)) => Some(value.clone()),
+        _ => {
+            errors.push(format!("line {}: '{}' must be a string, found {}", node.line, field, node.type_name()));
+            None
+        }
+    }
+}
+
+fn string_list_field(node: &Node, field: &str, errors: &mut Vec<String>) -> Vec<String> {
+    match &node.value {
+        NodeValue::Sequence(items) => items
+            .iter()
+            .filter_map(|item| string_field(item, &format!("{} entry", field), errors))
+            .collect(),
+        _ => {
+            errors.push(format!("line {}: '{}' must be a list of names, found {}", node.line, field, node.type_name()));
+            Vec::new()
+        }
+    }
+}
+
+impl AgentConfig {
+    pub fn from_file(path: &Path) -> Result<Self, AgentError> {
+        let source = std::fs::read_to_string(path)
+            .map_err(|e| AgentError::ConfigError(format!("Failed to read {}: {}", path.display(), e)))?;
+        Self::parse(&source)
+    }
+
+    /// Parses a YAML or JSON agent tree and checks its structure. Names of
+    /// tools, callbacks and models are checked by `validate`.
+    pub fn parse(source: &str) -> Result<Self, AgentError> {
+        let document = parse_document(source)?;
+        let mut errors = Vec::new();
+        let config = Self::from_node(&document, &mut errors);
+        if let Some(config) = &config {
+            let mut names = HashMap::new();
+            config.check_unique_names(&mut names, &mut errors);
+        }
+        match config {
+            Some(config) if errors.is_empty() => Ok(config),
+            _ => Err(AgentError::ConfigError(errors.join("\n"))),
+        }
+    }
+
+    fn from_node(node: &Node, errors: &mut Vec<String>) -> Option<Self> {
+        let entries = match &node.value {
+            NodeValue::Mapping(entries) => entries,
+            _ => {
+                errors.push(format!("line {}: an agent must be a mapping, found {}", node.line, node.type_name()));
+                return None;
+            }
+        };
+
+        let mut config = AgentConfig {
+            kind: AgentKind::Llm,
+            name: String::new(),
+            description: String::new(),
+            model: None,
+            instruction: None,
+            tools: Vec::new(),
+            sub_agents: Vec::new(),
+            max_iterations: None,
+            output_key: None,
+            output_schema: None,
+            before_agent_callbacks: Vec::new(),
+            after_agent_callbacks: Vec::new(),
+            before_tool_callbacks: Vec::new(),
+            after_tool_callbacks: Vec::new(),
+            line: node.line,
+            field_lines: HashMap::new(),
+        };
+        let mut kind = None;
+        let mut name = None;
+        let mut sub_agent_count = 0;
+        let errors_before = errors.len();
+
+        for (key, value) in entries {
+            if config.field_lines.insert(key.clone(), value.line).is_some() {
+                errors.push(format!("line {}: duplicate field '{}'", value.line, key));
+                continue;
+            }
+            match key.as_str() {
+                "kind" => {
+                    if let Some(value_kind) = string_field(value, key, errors) {
+                        kind = AgentKind::parse(&value_kind);
+                        if kind.is_none() {
+                            errors.push(format!(
+                                "line {}: unknown agent kind '{}', expected llm, sequential, parallel or loop",
+                                value.line, value_kind
+                            ));
+                        }
+                    }
+                }
+                "name" => name = string_field(value, key, errors),
+                "description" => config.description = string_field(value, key, errors).unwrap_or_default(),
+                "model" => config.model = string_field(value, key, errors),
+                "instruction" => config.instruction = string_field(value, key, errors),
+                "output_key" => config.output_key = string_field(value, key, errors),
+                "output_schema" => match &value.value {
+                    NodeValue::Mapping(_) => config.output_schema = Some(value.to_json()),
+                    _ => errors.push(format!(
+                        "line {}: 'output_schema' must be a JSON Schema mapping, found {}",
+                        value.line,
+                        value.type_name()
+                    )),
+                },
+                "tools" => config.tools = string_list_field(value, key, errors),
+                "before_agent_callbacks" => config.before_agent_callbacks = string_list_field(value, key, errors),
+                "after_agent_callbacks" => config.after_agent_callbacks = string_list_field(value, key, errors),
+                "before_tool_callbacks" => config.before_tool_callbacks = string_list_field(value, key, errors),
+                "after_tool_callbacks" => config.after_tool_callbacks = string_list_field(value, key, errors),
+                "max_iterations" => match &value.value {
+                    NodeValue::Scalar(serde_json::Value::Number(number))
+                        if number.as_i64().is_some_and(|number| number > 0 && number <= i32::MAX as i64) =>
+                    {
+                        config.max_iterations = number.as_i64().map(|number| number as i32);
+                    }
+                    _ => errors.push(format!("line {}: 'max_iterations' must be a positive integer", value.line)),
+                },
+                "sub_agents" => match &value.value {
+                    NodeValue::Sequence(items) => {
+                        sub_agent_count = items.len();
+                        config.sub_agents = items.iter().filter_map(|item| Self::from_node(item, errors)).collect();
+                    }
+                    _ => errors.push(format!(
+                        "line {}: 'sub_agents' must be a list of agents, found {}",
+                        value.line,
+                        value.type_name()
+                    )),
+                },
+                _ => errors.push(format!(
+                    "line {}: unknown field '{}', expected one of {}",
+                    value.line,
+                    key,
+                    FIELDS.join(", ")
+                )),
+            }
+        }
+
+        match kind {
+            Some(kind) => config.kind = kind,
+            None if !config.field_lines.contains_key("kind") => {
+                errors.push(format!("line {}: agent is missing the 'kind' field", node.line));
+            }
+            None => {}
+        }
+        match name.filter(|name| !name.is_empty()) {
+            Some(name) => config.name = name,
+            None => errors.push(format!("line {}: agent is missing a non-empty 'name' field", node.line)),
+        }
+
+        if let Some(kind) = kind {
+            if kind != AgentKind::Llm {
+                for field in LLM_FIELDS.iter().filter(|field| config.field_lines.contains_key(**field)) {
+                    errors.push(format!(
+                        "line {}: '{}' is only allowed on llm agents, not on {} agents",
+                        config.field_lines[*field],
+                        field,
+                        kind.as_str()
+                    ));
+                }
+                if sub_agent_count == 0 {
+                    errors.push(format!("line {}: {} agent '{}' needs at least one sub-agent", node.line, kind.as_str(), config.name));
+                }
+            }
+            if kind != AgentKind::Loop && config.field_lines.contains_key("max_iterations") {
+                errors.push(format!(
+                    "line {}: 'max_iterations' is only allowed on loop agents",
+                    config.field_lines["max_iterations"]
+                ));
+            }
+            if kind == AgentKind::Llm && config.output_key.is_none() && config.output_schema.is_some() {
+                errors.push(format!(
+                    "line {}: 'output_schema' needs an 'output_key' to store the result under",
+                    config.field_lines["output_schema"]
+                ));
+            }
+        }
+
+        (errors.len() == errors_before).then_some(config)
+    }
+
+    fn check_unique_names(&self, names: &mut HashMap<String, usize>, errors: &mut Vec<String>) {
+        if let Some(line) = names.insert(self.name.clone(), self.line) {
+            errors.push(format!(
+                "line {}: agent name '{}' is already used on line {}",
+                self.line, self.name, line
+            ));
+        }
+        for sub_agent in &self.sub_agents {
+            sub_agent.check_unique_names(names, errors);
+        }
+    }
+
+    fn field_line(&self, field: &str) -> usize {
+        self.field_lines.get(field).copied().unwrap_or(self.line)
+    }
+
+    /// Checks that every tool, callback and model name resolves in the
+    /// registry.
+    pub fn validate(&self, registry: &AgentRegistry) -> Result<(), AgentError> {
+        let mut errors = Vec::new();
+        self.validate_into(registry, &mut errors);
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(AgentError::ConfigError(errors.join("\n")))
+        }
+    }
+
+    fn validate_into(&self, registry: &AgentRegistry, errors: &mut Vec<String>) {
+        if self.kind == AgentKind::Llm {
+            match self.model.as_ref().or(registry.default_model.as_ref()) {
+                Some(model) if registry.get_model(model).is_none() => errors.push(format!(
+                    "line {}: agent '{}' uses unknown model '{}'",
+                    self.field_line("model"),
+                    self.name,
+                    model
+                )),
+                Some(_) => {}
+                None => errors.push(format!(
+                    "line {}: llm agent '{}' needs a 'model' and the registry has no default model",
+                    self.line, self.name
+                )),
+            }
+            if !self.sub_agents.is_empty() {
+                errors.push(format!(
+                    "line {}: llm agent '{}' cannot have sub_agents, it never runs them",
+                    self.field_line("sub_agents"),
+                    self.name
+                ));
+            }
+        }
+        let mut seen = HashSet::new();
+        for tool in &self.tools {
+            if registry.get_tool(tool).is_none() {
+                errors.push(format!("line {}: unknown tool '{}'", self.field_line("tools"), tool));
+            } else if !seen.insert(tool) {
+                errors.push(format!("line {}: tool '{}' is listed twice", self.field_line("tools"), tool));
+            }
+        }
+        let callbacks = [
+            ("before_agent_callbacks", &self.before_agent_callbacks, registry.before_agent_callbacks.keys().collect::<HashSet<_>>()),
+            ("after_agent_callbacks", &self.after_agent_callbacks, registry.after_agent_callbacks.keys().collect()),
+            ("before_tool_callbacks", &self.before_tool_callbacks, registry.before_tool_callbacks.keys().collect()),
+            ("after_tool_callbacks", &self.after_tool_callbacks, registry.after_tool_callbacks.keys().collect()),
+        ];
+        for (field, names, registered) in callbacks {
+            for name in names.iter().filter(|name| !registered.contains(name)) {
+                errors.push(format!("line {}: unknown callback '{}' in '{}'", self.field_line(field), name, field));
+            }
+        }
+        for sub_agent in &self.sub_agents {
+            sub_agent.validate_into(registry, errors);
+        }
+    }
+
+    fn resolve<T: Clone>(names: &[String], registered: &HashMap<String, T>) -> Option<Vec<T>> {
+        let callbacks: Vec<T> = names.iter().filter_map(|name| registered.get(name).cloned()).collect();
+        (!callbacks.is_empty()).then_some(callbacks)
+    }
+
+    /// Validates the tree against the registry, spawns every agent as an
+    /// actor registered under its name and returns the root. When an agent
+    /// fails to spawn, the ones already spawned are stopped so that their
+    /// names are free for the next attempt.
+    pub async fn spawn(&self, registry: &AgentRegistry) -> Result<ActorCell, AgentError> {
+        self.validate(registry)?;
+        let mut spawned = Vec::new();
+        let result = self.spawn_tree(registry, &mut spawned).await;
+        if result.is_err() {
+            for actor in spawned.into_iter().rev() {
+                let _ = actor.stop_and_wait(None, None).await;
+            }
+        }
+        result
+    }
+
+    fn spawn_tree<'a>(
+        &'a self,
+        registry: &'a AgentRegistry,
+        spawned: &'a mut Vec<ActorCell>,
+    ) -> Pin<Box<dyn Future<Output = Result<ActorCell, AgentError>> + Send + 'a>> {
+        Box::pin(async move {
+            let mut sub_agents = Vec::new();
+            for sub_agent in &self.sub_agents {
+                sub_agents.push(Arc::new(sub_agent.spawn_tree(registry, spawned).await?));
+            }
+
+            let before_agent_callback = Self::resolve(&self.before_agent_callbacks, &registry.before_agent_callbacks);
+            let after_agent_callback = Self::resolve(&self.after_agent_callbacks, &registry.after_agent_callbacks);
+            let before_tool_callback = Self::resolve(&self.before_tool_callbacks, &registry.before_tool_callbacks);
+            let after_tool_callback = Self::resolve(&self.after_tool_callbacks, &registry.after_tool_callbacks);
+            macro_rules! with_callbacks {
+                ($builder:expr) => {{
+                    let mut builder = $builder;
+                    for callback in before_agent_callback.into_iter().flatten() {
+                        builder = builder.before_agent_callback(callback);
+                    }
+                    for callback in after_agent_callback.into_iter().flatten() {
+                        builder = builder.after_agent_callback(callback);
+                    }
+                    for callback in before_tool_callback.into_iter().flatten() {
+                        builder = builder.before_tool_callback(callback);
+                    }
+                    for callback in after_tool_callback.into_iter().flatten() {
+                        builder = builder.after_tool_callback(callback);
+                    }
+                    builder.spawn().await
+                }};
+            }
+
+            let result = match self.kind {
+                AgentKind::Llm => {
+                    let model = self
+                        .model
+                        .as_ref()
+                        .or(registry.default_model.as_ref())
+                        .and_then(|model| registry.get_model(model))
+                        .ok_or_else(|| AgentError::ConfigError(format!("line {}: no model for agent '{}'", self.line, self.name)))?;
+                    let mut builder = LlmAgent::builder(model)
+                        .name(self.name.clone())
+                        .description(self.description.clone())
+                        .instruction(self.instruction.clone().unwrap_or_default())
+                        .tools(self.tools.iter().filter_map(|tool| registry.get_tool(tool)).collect())
+                        .sub_agents(sub_agents);
+                    if let Some(output_key) = &self.output_key {
+                        builder = builder.output_key(output_key.clone());
+                    }
+                    if let Some(output_schema) = &self.output_schema {
+                        builder = builder.output_schema(output_schema.clone());
+                    }
+                    with_callbacks!(builder)
+                }
+                AgentKind::Sequential => {
+                    let builder = SequentialAgent::builder()
+                        .name(self.name.clone())
+                        .description(self.description.clone())
+                        .sub_agents(sub_agents);
+                    with_callbacks!(builder)
+                }
+                AgentKind::Parallel => {
+                    let builder = ParallelAgent::builder()
+                        .name(self.name.clone())
+                        .description(self.description.clone())
+                        .sub_agents(sub_agents);
+                    with_callbacks!(builder)
+                }
+                AgentKind::Loop => {
+                    let mut builder = LoopAgent::builder()
+                        .name(self.name.clone())
+                        .description(self.description.clone())
+                        .sub_agents(sub_agents);
+                    if let Some(max_iterations) = self.max_iterations {
+                        builder = builder.max_iterations(max_iterations);
+                    }
+                    with_callbacks!(builder)
+                }
+            };
+            let actor = result?.get_cell();
+            spawned.push(actor.clone());
+            Ok(actor)
+        })
+    }
+}
+
+/// Parses, validates and spawns the agent tree in `path`.
+pub async fn load_agent_tree(path: &Path, registry: &AgentRegistry) -> Result<ActorCell, AgentError> {
+    AgentConfig::from_file(path)?.spawn(registry).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{unique_name, Reply, ScriptedLlm};
+    use serde_json::json;
+
+    const PIPELINE: &str = "\
+kind: sequential
+name: pipeline
+sub_agents:
+  - kind: llm
+    name: researcher
+    model: scripted
+    instruction: Research {topic}.
+    output_key: findings
+    output_schema:
+      type: object
+      required: [summary]
+  - kind: loop
+    name: editor
+    max_iterations: 3
+    sub_agents:
+      - kind: llm
+        name: writer
+";
+
+    fn errors(source: &str) -> Vec<String> {
+        match AgentConfig::parse(source) {
+            Err(AgentError::ConfigError(message)) => message.lines().map(str::to_string).collect(),
+            other => panic!("expected config errors, got {:?}", other.map(|config| config.name)),
+        }
+    }
+
+    #[test]
+    fn trees_keep_their_fields_and_lines() {
+        let config = AgentConfig::parse(PIPELINE).unwrap();
+        assert_eq!((config.kind, config.name.as_str(), config.line), (AgentKind::Sequential, "pipeline", 1));
+        let researcher = &config.sub_agents[0];
+        assert_eq!((researcher.name.as_str(), researcher.line), ("researcher", 4));
+        assert_eq!(researcher.instruction.as_deref(), Some("Research {topic}."));
+        assert_eq!(researcher.output_schema, Some(json!({ "type": "object", "required": ["summary"] })));
+        let editor = &config.sub_agents[1];
+        assert_eq!((editor.kind, editor.max_iterations, editor.line), (AgentKind::Loop, Some(3), 12));
+        assert_eq!((editor.sub_agents[0].name.as_str(), editor.sub_agents[0].line), ("writer", 16));
+    }
+
+    #[test]
+    fn json_trees_parse_like_yaml_ones() {
+        let config = AgentConfig::parse(r#"{"kind": "parallel", "name": "fan", "sub_agents": [{"kind": "llm", "name": "a"}]}"#).unwrap();
+        assert_eq!(config.kind, AgentKind::Parallel);
+        assert_eq!(config.sub_agents[0].name, "a");
+    }
+
+    #[test]
+    fn structural_errors_name_their_line() {
+        let source = "\
+kind: sequential
+name: pipeline
+model: scripted
+sub_agents:
+  - kind: robot
+    name: a
+  - kind: llm
+    name: pipeline
+    max_iterations: 0
+    colour: red
+  - kind: loop
+    name: empty
+";
+        assert_eq!(
+            errors(source),
+            [
+                "line 5: unknown agent kind 'robot', expected llm, sequential, parallel or loop",
+                "line 9: 'max_iterations' must be a positive integer",
+                &format!("line 10: unknown field 'colour', expected one of {}", FIELDS.join(", ")),
+                "line 9: 'max_iterations' is only allowed on loop agents",
+                "line 11: loop agent 'empty' needs at least one sub-agent",
+                "line 3: 'model' is only allowed on llm agents, not on sequential agents",
+            ]
+        );
+        assert_eq!(
+            errors("kind: llm\nname: a\nsub_agents:\n  - kind: llm\n    name: a\n"),
+            ["line 4: agent name 'a' is already used on line 1"]
+        );
+        assert_eq!(errors("kind: llm\nkind: llm\nname: a\n"), ["line 2: duplicate field 'kind'"]);
+        assert_eq!(
+            errors("kind: llm\nname: [a]\n"),
+            ["line 2: 'name' must be a string, found a list", "line 1: agent is missing a non-empty 'name' field"]
+        );
+    }
+
+    #[test]
+    fn yaml_syntax_errors_name_their_line() {
+        let errors = errors("kind: llm\nname: a\ntools: [one\n");
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("line 4: "), "{}", errors[0]);
+    }
+
+    #[test]
+    fn unknown_registry_names_name_their_line() {
+        let config = AgentConfig::parse(PIPELINE).unwrap();
+        let Err(AgentError::ConfigError(message)) = config.validate(&AgentRegistry::new()) else {
+            panic!("a config with unknown models was accepted");
+        };
+        assert_eq!(
+            message.lines().collect::<Vec<_>>(),
+            [
+                "line 6: agent 'researcher' uses unknown model 'scripted'",
+                "line 16: llm agent 'writer' needs a 'model' and the registry has no default model",
+            ]
+        );
+        let registry = AgentRegistry::new()
+            .model("scripted".to_string(), ScriptedLlm::new(Vec::new()))
+            .default_model("scripted".to_string());
+        assert!(config.validate(&registry).is_ok());
+
+        let config = AgentConfig::parse("kind: llm\nname: boss\nsub_agents:\n  - kind: llm\n    name: helper\n").unwrap();
+        let Err(AgentError::ConfigError(message)) = config.validate(&registry) else {
+            panic!("an llm agent with sub-agents was accepted");
+        };
+        assert_eq!(message, "line 4: llm agent 'boss' cannot have sub_agents, it never runs them");
+    }
+
+    #[tokio::test]
+    async fn failed_spawns_stop_the_agents_already_spawned() {
+        let registry = AgentRegistry::new().model("scripted".to_string(), ScriptedLlm::new(Vec::new()));
+        let (first, taken) = (unique_name("first"), unique_name("taken"));
+        let config = AgentConfig::parse(&format!(
+            "kind: sequential\nname: {}\nsub_agents:\n  - kind: llm\n    name: {}\n    model: scripted\n  - kind: llm\n    name: {}\n    model: scripted\n",
+            unique_name("pipeline"),
+            first,
+            taken,
+        ))
+        .unwrap();
+        let (holder, _) = Reply::spawn_with(Reply::builder("taken".to_string()).name(taken.clone())).await;
+
+        assert!(config.spawn(&registry).await.is_err());
+        assert!(ractor::registry::where_is(first.clone()).is_none());
+
+        holder.stop_and_wait(None, None).await.unwrap();
+        let root = config.spawn(&registry).await.unwrap();
+        assert!(ractor::registry::where_is(first).is_some());
+        root.stop(None);
+    }
+}
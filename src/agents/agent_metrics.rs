@@ -0,0 +1,109 @@
+//! Operational metrics recorded through the `metrics` facade. Nothing is
+//! collected until the application installs a recorder, so the calls cost
+//! next to nothing when metrics are not wanted.
+
+use crate::models::UsageMetadata;
+use metrics::{counter, histogram};
+use std::time::Duration;
+
+pub const AGENT_RUNS_TOTAL: &str = "coagent_agent_runs_total";
+pub const AGENT_RUN_DURATION_SECONDS: &str = "coagent_agent_run_duration_seconds";
+pub const MODEL_CALLS_TOTAL: &str = "coagent_model_calls_total";
+pub const MODEL_CALL_DURATION_SECONDS: &str = "coagent_model_call_duration_seconds";
+pub const MODEL_TOKENS_TOTAL: &str = "coagent_model_tokens_total";
+pub const TOOL_CALLS_TOTAL: &str = "coagent_tool_calls_total";
+pub const TOOL_CALL_DURATION_SECONDS: &str = "coagent_tool_call_duration_seconds";
+pub const RETRIES_TOTAL: &str = "coagent_retries_total";
+pub const ESCALATIONS_TOTAL: &str = "coagent_escalations_total";
+pub const TRANSFERS_TOTAL: &str = "coagent_transfers_total";
+pub const RESTARTS_TOTAL: &str = "coagent_restarts_total";
+
+fn status(success: bool) -> &'static str {
+    if success {
+        "ok"
+    } else {
+        "error"
+    }
+}
+
+pub fn record_agent_run(agent_name: &str, duration: Duration, success: bool) {
+    counter!(AGENT_RUNS_TOTAL, "agent" => agent_name.to_string(), "status" => status(success)).increment(1);
+    histogram!(AGENT_RUN_DURATION_SECONDS, "agent" => agent_name.to_string()).record(duration.as_secs_f64());
+}
+
+pub fn record_model_call(
+    agent_name: &str,
+    model: &str,
+    duration: Duration,
+    usage: Option<&UsageMetadata>,
+    success: bool,
+) {
+    counter!(
+        MODEL_CALLS_TOTAL,
+        "agent" => agent_name.to_string(),
+        "model" => model.to_string(),
+        "status" => status(success)
+    )
+    .increment(1);
+    histogram!(MODEL_CALL_DURATION_SECONDS, "agent" => agent_name.to_string(), "model" => model.to_string())
+        .record(duration.as_secs_f64());
+    if let Some(usage) = usage {
+        counter!(
+            MODEL_TOKENS_TOTAL,
+            "agent" => agent_name.to_string(),
+            "model" => model.to_string(),
+            "type" => "input"
+        )
+        .increment(usage.prompt_token_count.max(0) as u64);
+        counter!(
+            MODEL_TOKENS_TOTAL,
+            "agent" => agent_name.to_string(),
+            "model" => model.to_string(),
+            "type" => "output"
+        )
+        .increment(usage.candidates_token_count.max(0) as u64);
+    }
+}
+
+pub fn record_tool_call(agent_name: &str, tool_name: &str, duration: Duration, success: bool) {
+    counter!(
+        TOOL_CALLS_TOTAL,
+        "agent" => agent_name.to_string(),
+        "tool" => tool_name.to_string(),
+        "status" => status(success)
+    )
+    .increment(1);
+    histogram!(TOOL_CALL_DURATION_SECONDS, "agent" => agent_name.to_string(), "tool" => tool_name.to_string())
+        .record(duration.as_secs_f64());
+}
+
+pub fn record_retry(agent_name: &str, reason: &str) {
+    counter!(RETRIES_TOTAL, "agent" => agent_name.to_string(), "reason" => reason.to_string()).increment(1);
+}
+
+pub fn record_escalation(agent_name: &str) {
+    counter!(ESCALATIONS_TOTAL, "agent" => agent_name.to_string()).increment(1);
+}
+
+pub fn record_transfer(from_agent: &str, to_agent: &str) {
+    counter!(TRANSFERS_TOTAL, "from_agent" => from_agent.to_string(), "to_agent" => to_agent.to_string()).increment(1);
+}
+
+pub fn record_restart(supervisor_name: &str, agent_name: &str) {
+    counter!(RESTARTS_TOTAL, "supervisor" => supervisor_name.to_string(), "agent" => agent_name.to_string()).increment(1);
+}
+
+/// Installs a global Prometheus recorder. Serve `PrometheusHandle::render`
+/// from whatever endpoint the application exposes.
+#[cfg(feature = "prometheus")]
+pub fn install_prometheus_recorder() -> Result<metrics_exporter_prometheus::PrometheusHandle, crate::common::AgentError> {
+    use crate::common::AgentError;
+    use metrics_exporter_prometheus::{Matcher, PrometheusBuilder};
+
+    const LATENCY_BUCKETS: &[f64] = &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0];
+
+    PrometheusBuilder::new()
+        .set_buckets_for_metric(Matcher::Suffix("_duration_seconds".to_string()), LATENCY_BUCKETS)
+        .and_then(|builder| builder.install_recorder())
+        .map_err(|e| AgentError::TelemetryError(e.to_string()))
+}
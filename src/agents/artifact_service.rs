@@ -0,0 +1,125 @@
+use crate::common::{AgentError, Part};
+use crate::state::USER_PREFIX;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Versioned storage for files produced or consumed by agents. Filenames
+/// starting with `user:` are shared by all sessions of the user, the rest
+/// belong to a single session.
+#[async_trait]
+pub trait BaseArtifactService: Send + Sync + std::fmt::Debug {
+    /// Stores a new version of the artifact and returns its version number,
+    /// starting at 0.
+    async fn save_artifact(
+        &self,
+        app_name: &str,
+        user_id: &str,
+        session_id: &str,
+        filename: &str,
+        artifact: Part,
+    ) -> Result<i32, AgentError>;
+
+    /// Loads the given version of the artifact, or the latest when `version`
+    /// is `None`.
+    async fn load_artifact(
+        &self,
+        app_name: &str,
+        user_id: &str,
+        session_id: &str,
+        filename: &str,
+        version: Option<i32>,
+    ) -> Result<Option<Part>, AgentError>;
+
+    async fn list_artifact_keys(&self, app_name: &str, user_id: &str, session_id: &str) -> Result<Vec<String>, AgentError>;
+
+    async fn delete_artifact(&self, app_name: &str, user_id: &str, session_id: &str, filename: &str) -> Result<(), AgentError>;
+
+    async fn list_versions(&self, app_name: &str, user_id: &str, session_id: &str, filename: &str) -> Result<Vec<i32>, AgentError>;
+}
+
+type ArtifactKey = (String, String, Option<String>, String);
+
+#[derive(Debug, Default)]
+pub struct InMemoryArtifactService {
+    artifacts: Mutex<HashMap<ArtifactKey, Vec<Part>>>,
+}
+
+impl InMemoryArtifactService {
+    pub fn new() -> Self {
+        InMemoryArtifactService::default()
+    }
+
+    fn key(app_name: &str, user_id: &str, session_id: &str, filename: &str) -> ArtifactKey {
+        let session_id = if filename.starts_with(USER_PREFIX) {
+            None
+        } else {
+            Some(session_id.to_string())
+        };
+        (app_name.to_string(), user_id.to_string(), session_id, filename.to_string())
+    }
+}
+
+#[async_trait]
+impl BaseArtifactService for InMemoryArtifactService {
+    async fn save_artifact(
+        &self,
+        app_name: &str,
+        user_id: &str,
+        session_id: &str,
+        filename: &str,
+        artifact: Part,
+    ) -> Result<i32, AgentError> {
+        let mut artifacts = self.artifacts.lock().unwrap();
+        let versions = artifacts.entry(Self::key(app_name, user_id, session_id, filename)).or_default();
+        versions.push(artifact);
+        Ok(versions.len() as i32 - 1)
+    }
+
+    async fn load_artifact(
+        &self,
+        app_name: &str,
+        user_id: &str,
+        session_id: &str,
+        filename: &str,
+        version: Option<i32>,
+    ) -> Result<Option<Part>, AgentError> {
+        let artifacts = self.artifacts.lock().unwrap();
+        let versions = match artifacts.get(&Self::key(app_name, user_id, session_id, filename)) {
+            Some(versions) => versions,
+            None => return Ok(None),
+        };
+        let artifact = match version {
+            Some(version) => usize::try_from(version).ok().and_then(|version| versions.get(version)),
+            None => versions.last(),
+        };
+        Ok(artifact.cloned())
+    }
+
+    async fn list_artifact_keys(&self, app_name: &str, user_id: &str, session_id: &str) -> Result<Vec<String>, AgentError> {
+        let artifacts = self.artifacts.lock().unwrap();
+        let mut keys: Vec<String> = artifacts
+            .keys()
+            .filter(|(app, user, session, _)| {
+                app == app_name && user == user_id && session.as_deref().is_none_or(|session| session == session_id)
+            })
+            .map(|(_, _, _, filename)| filename.clone())
+            .collect();
+        keys.sort();
+        Ok(keys)
+    }
+
+    async fn delete_artifact(&self, app_name: &str, user_id: &str, session_id: &str, filename: &str) -> Result<(), AgentError> {
+        let mut artifacts = self.artifacts.lock().unwrap();
+        artifacts.remove(&Self::key(app_name, user_id, session_id, filename));
+        Ok(())
+    }
+
+    async fn list_versions(&self, app_name: &str, user_id: &str, session_id: &str, filename: &str) -> Result<Vec<i32>, AgentError> {
+        let artifacts = self.artifacts.lock().unwrap();
+        Ok(artifacts
+            .get(&Self::key(app_name, user_id, session_id, filename))
+            .map(|versions| (0..versions.len() as i32).collect())
+            .unwrap_or_default())
+    }
+}
@@ -1,214 +1,527 @@
-use crate::common::{AgentError, Content, Event, EventActions};
+use crate::common::{now, AgentError, Content, Event, LiveRequest, Part};
 use crate::invocation_context::InvocationContext;
 use crate::callback_context::CallbackContext;
-use ractor::{Actor, ActorRef, ActorCell, ActorProcessingErr};
+use crate::tool_context::ToolContext;
+use crate::base_tool::BaseTool;
+use crate::models::{BaseLlm, LlmRequest, LlmResponse};
+use crate::telemetry;
+use crate::agent_metrics;
+use crate::agent::Agent;
+use crate::supervision::{ChildSpec, SupervisionConfig, Supervisor};
+use ractor::{Actor, ActorRef, ActorCell, ActorProcessingErr, RpcReplyPort};
 use tokio::sync::mpsc;
-use std::sync::Arc;
-use async_trait::async_trait;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::Instrument;
 
 pub type BeforeAgentCallback = Arc<dyn Fn(CallbackContext) -> tokio::sync::oneshot::Receiver<Option<Content>> + Send + Sync>;
 pub type AfterAgentCallback = Arc<dyn Fn(CallbackContext) -> tokio::sync::oneshot::Receiver<Option<Content>> + Send + Sync>;
+/// Runs in order before a tool, after the plugins. Each callback sees the
+/// args the previous one continued with; the first to respond skips the tool
+/// and the remaining callbacks. Not run when a plugin's `before_tool`
+/// already answered the call.
+pub type BeforeToolCallback = Arc<dyn Fn(Arc<dyn BaseTool>, serde_json::Value, ToolContext) -> tokio::sync::oneshot::Receiver<BeforeToolResult> + Send + Sync>;
+/// Runs in order after a tool, after the plugins. The first callback to
+/// return a value replaces the result and the remaining callbacks are
+/// skipped. Not run when a plugin's `after_tool` already replaced the result.
+pub type AfterToolCallback = Arc<dyn Fn(Arc<dyn BaseTool>, serde_json::Value, ToolContext, serde_json::Value) -> tokio::sync::oneshot::Receiver<Option<serde_json::Value>> + Send + Sync>;
 
+/// Outcome of a before-tool callback: run the tool with (possibly rewritten)
+/// args, or skip the tool and use the given value as its result.
 #[derive(Clone, Debug)]
-pub struct BaseAgent {
+pub enum BeforeToolResult {
+    Continue(serde_json::Value),
+    Respond(serde_json::Value),
+}
+
+/// Configuration shared by every agent, passed when the agent's actor is
+/// spawned. Builders produce it with `arguments()`.
+#[derive(Clone, Default)]
+pub struct BaseAgentArguments {
     pub name: String,
     pub description: String,
     pub parent_agent: Option<Arc<ActorCell>>,
     pub sub_agents: Vec<Arc<ActorCell>>,
+    /// Sub-agents the agent spawns and supervises, run after `sub_agents`.
+    pub child_specs: Vec<ChildSpec>,
+    pub supervision: SupervisionConfig,
     pub before_agent_callback: Option<Vec<BeforeAgentCallback>>,
     pub after_agent_callback: Option<Vec<AfterAgentCallback>>,
+    pub before_tool_callback: Option<Vec<BeforeToolCallback>>,
+    pub after_tool_callback: Option<Vec<AfterToolCallback>>,
 }
 
-#[async_trait]
-impl Actor for BaseAgent {
-    type Msg = BaseAgentMessage;
-    type State = BaseAgentState;
-    type Arguments = BaseAgentArguments;
-
-    async fn pre_start(&self, _this_actor: ActorRef<Self>, _args: Self::Arguments) -> Result<Self::State, ActorProcessingErr> {
-        Ok(BaseAgentState {
-            name: self.name.clone(),
-            description: self.description.clone(),
-            parent_agent: self.parent_agent.clone(),
-            sub_agents: self.sub_agents.clone(),
-            before_agent_callback: self.before_agent_callback.clone(),
-            after_agent_callback: self.after_agent_callback.clone(),
-        })
-    }
-
-    async fn handle(
-        &self,
-        _this_actor: ActorRef<Self>,
-        message: Self::Msg,
-        state: &mut Self::State,
-    ) -> Result<(), ActorProcessingErr> {
-        match message {
-            BaseAgentMessage::RunAsync { context, sender } => {
-                let events = self.run_async_impl(&context).await?;
-                sender.send(events).map_err(|_| ActorProcessingErr::new("Failed to send events"))?;
-            }
-            BaseAgentMessage::RunLive { context, sender } => {
-                let events = self.run_live_impl(&context).await?;
-                sender.send(events).map_err(|_| ActorProcessingErr::new("Failed to send events"))?;
-            }
-        }
-        Ok(())
-    }
+/// The behaviour shared by every agent, driven by the arguments the actor
+/// was started with. Agents find it in their state.
+#[derive(Clone, Default)]
+pub struct BaseAgent {
+    arguments: BaseAgentArguments,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Debug)]
 pub enum BaseAgentMessage {
     RunAsync {
         context: InvocationContext,
-        sender: mpsc::Sender<Vec<Event>>,
+        sender: mpsc::Sender<Result<Vec<Event>, AgentError>>,
     },
     RunLive {
         context: InvocationContext,
-        sender: mpsc::Sender<Vec<Event>>,
+        sender: mpsc::Sender<Result<Vec<Event>, AgentError>>,
     },
+    GetInfo(RpcReplyPort<AgentInfo>),
+    /// Replies with the live sub-agents, restarted ones included.
+    GetChildren(RpcReplyPort<Vec<ActorCell>>),
+    /// Fails the agent with the given reason, handing it to its supervisor.
+    Fail(String),
 }
 
+// With ractor's `cluster` feature every message type opts in explicitly.
+#[cfg(feature = "cluster")]
+impl ractor::Message for BaseAgentMessage {}
+
+/// What an agent reports about itself at runtime.
 #[derive(Clone, Debug)]
-pub struct BaseAgentState {
+pub struct AgentInfo {
     pub name: String,
     pub description: String,
-    pub parent_agent: Option<Arc<ActorCell>>,
-    pub sub_agents: Vec<Arc<ActorCell>>,
-    pub before_agent_callback: Option<Vec<BeforeAgentCallback>>,
-    pub after_agent_callback: Option<Vec<AfterAgentCallback>>,
+    pub parent_agent: Option<String>,
+    pub sub_agents: Vec<String>,
+    /// Invocations the agent is running now.
+    pub current_invocations: Vec<String>,
+    pub metrics: AgentRunMetrics,
 }
 
-#[derive(Clone, Debug)]
-pub struct BaseAgentArguments {
-    pub name: String,
-    pub description: String,
-    pub sub_agents: Vec<Arc<ActorCell>>,
-    pub before_agent_callback: Option<Vec<BeforeAgentCallback>>,
-    pub after_agent_callback: Option<Vec<AfterAgentCallback>>,
+/// Runs of one agent actor since it started.
+#[derive(Clone, Debug, Default)]
+pub struct AgentRunMetrics {
+    pub runs: u64,
+    pub failed_runs: u64,
+    pub total_run_time: Duration,
+    /// End of the last run in seconds since the Unix epoch.
+    pub last_run_at: Option<f64>,
 }
 
-impl BaseAgent {
-    pub fn new(
-        name: String,
-        description: String,
-        sub_agents: Vec<Arc<ActorCell>>,
-        before_agent_callback: Option<Vec<BeforeAgentCallback>>,
-        after_agent_callback: Option<Vec<AfterAgentCallback>>,
-    ) -> Self {
-        let parent_agent = None;
-        BaseAgent {
-            name,
-            description,
-            parent_agent,
-            sub_agents,
-            before_agent_callback,
-            after_agent_callback,
+#[derive(Default)]
+struct AgentRuns {
+    current_invocations: Vec<String>,
+    metrics: AgentRunMetrics,
+}
+
+/// Ends the bookkeeping of a run even when its task panics.
+struct RunGuard {
+    runs: Arc<Mutex<AgentRuns>>,
+    invocation_id: String,
+    started_at: Instant,
+    success: bool,
+}
+
+impl Drop for RunGuard {
+    fn drop(&mut self) {
+        let mut runs = self.runs.lock().unwrap();
+        if let Some(index) = runs.current_invocations.iter().position(|id| *id == self.invocation_id) {
+            runs.current_invocations.remove(index);
         }
+        runs.metrics.runs += 1;
+        if !self.success {
+            runs.metrics.failed_runs += 1;
+        }
+        runs.metrics.total_run_time += self.started_at.elapsed();
+        runs.metrics.last_run_at = Some(now());
+    }
+}
+
+/// Runtime state of an agent actor: its configuration, its live children,
+/// and the invocations it is running with their metrics.
+#[derive(Clone)]
+pub struct BaseAgentState {
+    pub base: BaseAgent,
+    pub supervisor: Supervisor,
+    runs: Arc<Mutex<AgentRuns>>,
+}
+
+impl BaseAgentState {
+    /// Builds the state from the arguments and spawns the supervised
+    /// sub-agents.
+    pub async fn start(this_actor: ActorRef<BaseAgentMessage>, args: BaseAgentArguments) -> Result<Self, ActorProcessingErr> {
+        let supervisor = Supervisor::start(
+            args.name.clone(),
+            this_actor.get_cell(),
+            args.supervision.clone(),
+            &args.sub_agents,
+            &args.child_specs,
+        )
+        .await
+        .map_err(ActorProcessingErr::from)?;
+        Ok(BaseAgentState {
+            base: BaseAgent::new(args),
+            supervisor,
+            runs: Arc::new(Mutex::new(AgentRuns::default())),
+        })
+    }
+
+    /// Runs an invocation in its own task, so the agent keeps answering
+    /// queries and can serve several invocations at once, and sends the
+    /// result to `sender`.
+    pub fn spawn_run<R, F>(
+        &self,
+        this_actor: ActorRef<BaseAgentMessage>,
+        context: InvocationContext,
+        sender: mpsc::Sender<Result<Vec<Event>, AgentError>>,
+        run: R,
+    ) where
+        R: FnOnce(InvocationContext) -> F,
+        F: Future<Output = Result<Vec<Event>, AgentError>> + Send + 'static,
+    {
+        let invocation_id = context.invocation_id().to_string();
+        self.runs.lock().unwrap().current_invocations.push(invocation_id.clone());
+        let mut guard = RunGuard {
+            runs: self.runs.clone(),
+            invocation_id,
+            started_at: Instant::now(),
+            success: false,
+        };
+        let supervisor = self.supervisor.clone();
+        let cancellation = context.cancellation().clone();
+        let name = self.base.name().to_string();
+        let run = run(context);
+        tokio::spawn(async move {
+            let events = tokio::select! {
+                events = run => events,
+                _ = cancellation.cancelled() => Err(AgentError::AgentFailed(format!("The run of {} was cancelled", name))),
+            };
+            guard.success = events.is_ok();
+            drop(guard);
+            let _ = sender.send(events).await;
+            if let Err(error) = supervisor.check_escalation() {
+                let _ = this_actor.send_message(BaseAgentMessage::Fail(error.to_string()));
+            }
+        });
+    }
+
+    pub fn info(&self) -> AgentInfo {
+        let runs = self.runs.lock().unwrap();
+        AgentInfo {
+            name: self.base.name().to_string(),
+            description: self.base.description().to_string(),
+            parent_agent: self.base.parent_agent().and_then(|parent| parent.get_name()),
+            sub_agents: self
+                .supervisor
+                .children()
+                .iter()
+                .map(|child| child.get_name().unwrap_or_default())
+                .collect(),
+            current_invocations: runs.current_invocations.clone(),
+            metrics: runs.metrics.clone(),
+        }
+    }
+
+    /// Answers the messages that are not runs, the same way for every agent.
+    pub fn handle_query(&self, message: BaseAgentMessage) -> Result<(), ActorProcessingErr> {
+        match message {
+            BaseAgentMessage::GetInfo(reply) => {
+                let _ = reply.send(self.info());
+            }
+            BaseAgentMessage::GetChildren(reply) => {
+                let _ = reply.send(self.supervisor.children().iter().map(|child| child.as_ref().clone()).collect());
+            }
+            BaseAgentMessage::Fail(reason) => return Err(ActorProcessingErr::from(reason)),
+            BaseAgentMessage::RunAsync { .. } | BaseAgentMessage::RunLive { .. } => {}
+        }
+        Ok(())
+    }
+
+    /// Runs `agent` as this actor for one invocation, inside its span and
+    /// callbacks.
+    pub async fn run_async<A: Agent>(&self, agent: &A, parent_context: InvocationContext) -> Result<Vec<Event>, AgentError> {
+        self.base
+            .run_async(parent_context, |context| async move { agent.run_async_impl(self, &context).await })
+            .await
+    }
+}
+
+impl BaseAgent {
+    pub fn new(arguments: BaseAgentArguments) -> Self {
+        BaseAgent { arguments }
+    }
+
+    /// Spawns `agent` as an actor registered under the name in `arguments`.
+    pub async fn spawn<A>(agent: A, arguments: BaseAgentArguments) -> Result<ActorRef<BaseAgentMessage>, AgentError>
+    where
+        A: Actor<Msg = BaseAgentMessage, Arguments = BaseAgentArguments>,
+    {
+        let name = arguments.name.clone();
+        Actor::spawn(Some(name.clone()), agent, arguments)
+            .await
+            .map(|(actor, _)| actor)
+            .map_err(|e| AgentError::AgentFailed(format!("Failed to spawn agent '{}': {}", name, e)))
+    }
+
+    pub fn arguments(&self) -> &BaseAgentArguments {
+        &self.arguments
     }
 
     pub fn name(&self) -> &str {
-        &self.name
+        &self.arguments.name
     }
 
     pub fn description(&self) -> &str {
-        &self.description
+        &self.arguments.description
     }
 
     pub fn parent_agent(&self) -> Option<Arc<ActorCell>> {
-        self.parent_agent.clone()
+        self.arguments.parent_agent.clone()
     }
 
-    pub fn set_parent_agent(&mut self, parent: Arc<ActorCell>) {
-        self.parent_agent = Some(parent);
+    pub fn root_agent(&self) -> Option<Arc<ActorCell>> {
+        match &self.arguments.parent_agent {
+            Some(parent) => Some(parent.clone()),
+            None => ractor::registry::where_is(self.arguments.name.clone()).map(Arc::new),
+        }
     }
 
-    pub fn root_agent(&self) -> Arc<ActorCell> {
-        let mut current = Arc::new(ActorCell::new_null());
-        if let Some(parent) = &self.parent_agent {
-            current = parent.clone();
-        }
-        current
+    pub fn before_agent_callback(&self) -> Option<&Vec<BeforeAgentCallback>> {
+        self.arguments.before_agent_callback.as_ref()
     }
 
-    pub fn find_agent(&self, name: &str) -> Option<Arc<ActorCell>> {
-        if self.name == name {
-            return Some(Arc::new(ActorCell::new_null()));
-        }
-        self.find_sub_agent(name)
+    pub fn after_agent_callback(&self) -> Option<&Vec<AfterAgentCallback>> {
+        self.arguments.after_agent_callback.as_ref()
     }
 
-    pub fn find_sub_agent(&self, name: &str) -> Option<Arc<ActorCell>> {
-        for sub_agent in &self.sub_agents {
-            // Placeholder: Implement actor query
-        }
-        None
+    pub fn before_tool_callback(&self) -> Option<&Vec<BeforeToolCallback>> {
+        self.arguments.before_tool_callback.as_ref()
     }
 
-    pub fn sub_agents(&self) -> &Vec<Arc<ActorCell>> {
-        &self.sub_agents
+    pub fn after_tool_callback(&self) -> Option<&Vec<AfterToolCallback>> {
+        self.arguments.after_tool_callback.as_ref()
     }
 
-    pub fn before_agent_callback(&self) -> Option<&Vec<BeforeAgentCallback>> {
-        self.before_agent_callback.as_ref()
+    /// Asks a running agent for its `AgentInfo`.
+    pub async fn get_info(agent: &ActorCell) -> Result<AgentInfo, AgentError> {
+        let agent: ActorRef<BaseAgentMessage> = agent.clone().into();
+        ractor::call!(agent, BaseAgentMessage::GetInfo).map_err(|e| AgentError::AgentFailed(e.to_string()))
     }
 
-    pub fn after_agent_callback(&self) -> Option<&Vec<AfterAgentCallback>> {
-        self.after_agent_callback.as_ref()
+    /// Asks a running agent for its live sub-agents.
+    pub async fn get_children(agent: &ActorCell) -> Result<Vec<ActorCell>, AgentError> {
+        let agent: ActorRef<BaseAgentMessage> = agent.clone().into();
+        ractor::call!(agent, BaseAgentMessage::GetChildren).map_err(|e| AgentError::AgentFailed(e.to_string()))
     }
 
     pub fn create_invocation_context(&self, parent_context: &InvocationContext) -> InvocationContext {
         let mut context = InvocationContext::copy_of(parent_context);
-        context.set_agent(Arc::new(ActorCell::new_null()));
         if let Some(branch) = parent_context.branch().filter(|s| !s.is_empty()) {
-            context.set_branch(Some(format!("{}.{}", branch, self.name)));
+            context.set_branch(Some(format!("{}.{}", branch, self.arguments.name)));
         } else {
-            context.set_branch(Some(self.name.clone()));
+            context.set_branch(Some(self.arguments.name.clone()));
         }
         context
     }
 
-    pub async fn run_async(&self, parent_context: InvocationContext) -> Result<Vec<Event>, AgentError> {
+    pub async fn run_sub_agent(sub_agent: &ActorCell, context: InvocationContext) -> Result<Vec<Event>, AgentError> {
+        let (sender, mut receiver) = mpsc::channel(1);
+        sub_agent
+            .send_message(BaseAgentMessage::RunAsync { context, sender })
+            .map_err(|e| AgentError::AgentFailed(e.to_string()))?;
+        receiver
+            .recv()
+            .await
+            .ok_or_else(|| AgentError::AgentFailed("Sub-agent stopped before sending its events".to_string()))?
+    }
+
+    pub async fn run_sub_agent_live(sub_agent: &ActorCell, context: InvocationContext) -> Result<Vec<Event>, AgentError> {
+        let (sender, mut receiver) = mpsc::channel(1);
+        sub_agent
+            .send_message(BaseAgentMessage::RunLive { context, sender })
+            .map_err(|e| AgentError::AgentFailed(e.to_string()))?;
+        receiver
+            .recv()
+            .await
+            .ok_or_else(|| AgentError::AgentFailed("Sub-agent stopped before sending its events".to_string()))?
+    }
+
+    /// Serves a live session from the context's `LiveRequestQueue`. Each user
+    /// turn is appended to the session and handed to `run_turn`; realtime
+    /// chunks are buffered and become one turn at `ActivityEnd`. A new turn,
+    /// `ActivityStart` or an `ActivityEnd` closing buffered chunks arriving
+    /// while `run_turn` is busy drops it and emits an `interrupted` event;
+    /// `Close` waits for it to finish. Each
+    /// turn runs with its own cancellation token, cancelled on interruption
+    /// so that the sub-agent runs and tool calls of the turn stop too.
+    pub async fn run_live_turns<F, Fut>(&self, context: &InvocationContext, mut run_turn: F) -> Result<Vec<Event>, AgentError>
+    where
+        F: FnMut(InvocationContext) -> Fut,
+        Fut: Future<Output = Result<Vec<Event>, AgentError>>,
+    {
+        let queue = context.live_request_queue().cloned().ok_or_else(|| {
+            AgentError::UnsupportedOperation(format!("Agent {} needs a LiveRequestQueue to run live", self.arguments.name))
+        })?;
+        let mut context = context.clone();
+        let mut events = Vec::new();
+        let mut chunks = Vec::new();
+        let mut pending = None;
+        loop {
+            let request = match pending.take() {
+                Some(request) => request,
+                None => queue.recv().await,
+            };
+            let content = match request {
+                LiveRequest::Close => break,
+                LiveRequest::Content(content) => content,
+                LiveRequest::Realtime(blob) => {
+                    chunks.push(Part::from_blob(blob));
+                    continue;
+                }
+                LiveRequest::ActivityStart => {
+                    chunks.clear();
+                    continue;
+                }
+                LiveRequest::ActivityEnd if chunks.is_empty() => continue,
+                LiveRequest::ActivityEnd => Content::new("user".to_string(), std::mem::take(&mut chunks)),
+            };
+
+            let user_event = Event::builder()
+                .invocation_id(context.invocation_id().to_string())
+                .author("user".to_string())
+                .content(Some(content.clone()))
+                .build();
+            context.session_mut().append_event(user_event.clone());
+            context.set_user_content(Some(content));
+            context.emit_event(&user_event);
+            events.push(user_event);
+
+            let mut closing = false;
+            let mut turn_context = context.clone();
+            turn_context.set_cancellation(context.cancellation().child_token());
+            let cancellation = turn_context.cancellation().clone();
+            let turn = run_turn(turn_context);
+            tokio::pin!(turn);
+            let result = loop {
+                tokio::select! {
+                    result = &mut turn => break Some(result),
+                    request = queue.recv(), if !closing => match request {
+                        LiveRequest::Realtime(blob) => chunks.push(Part::from_blob(blob)),
+                        LiveRequest::ActivityEnd if chunks.is_empty() => {}
+                        LiveRequest::Close => {
+                            closing = true;
+                            pending = Some(LiveRequest::Close);
+                        }
+                        request => {
+                            pending = Some(request);
+                            break None;
+                        }
+                    },
+                }
+            };
+
+            let marker = Event::builder()
+                .invocation_id(context.invocation_id().to_string())
+                .author(self.arguments.name.clone())
+                .branch(context.branch().map(|s| s.to_string()));
+            let marker = match result {
+                Some(turn_events) => {
+                    let turn_events = turn_events?;
+                    // Agents that do not stream their events would otherwise
+                    // only reach the client when the session ends.
+                    for event in &turn_events {
+                        context.session_mut().append_event(event.clone());
+                        context.emit_event(event);
+                    }
+                    events.extend(turn_events);
+                    marker.turn_complete(true).build()
+                }
+                None => {
+                    cancellation.cancel();
+                    marker.interrupted(true).build()
+                }
+            };
+            context.emit_event(&marker);
+            events.push(marker);
+
+            if context.end_invocation() {
+                break;
+            }
+        }
+        Ok(events)
+    }
+
+    /// Runs the before-agent callbacks, the agent's own logic in `run_impl`
+    /// and the after-agent callbacks in order. The first before-callback that
+    /// returns content skips the rest of the agent and its content becomes
+    /// the agent's reply.
+    pub async fn run_async<F, Fut>(&self, parent_context: InvocationContext, run_impl: F) -> Result<Vec<Event>, AgentError>
+    where
+        F: FnOnce(InvocationContext) -> Fut,
+        Fut: Future<Output = Result<Vec<Event>, AgentError>>,
+    {
         let mut context = self.create_invocation_context(&parent_context);
+        let span = telemetry::agent_span(&context, &self.arguments.name, &self.arguments.description);
+        context.set_span(span.clone());
+        let started_at = Instant::now();
+        let events = self.run_with_callbacks(context, run_impl).instrument(span).await;
+        agent_metrics::record_agent_run(&self.arguments.name, started_at.elapsed(), events.is_ok());
+        events
+    }
+
+    async fn run_with_callbacks<F, Fut>(&self, context: InvocationContext, run_impl: F) -> Result<Vec<Event>, AgentError>
+    where
+        F: FnOnce(InvocationContext) -> Fut,
+        Fut: Future<Output = Result<Vec<Event>, AgentError>>,
+    {
         let mut events = Vec::new();
 
-        if let Some(callbacks) = &self.before_agent_callback {
+        let callback_context = CallbackContext::new(context.clone(), None);
+        if let Some(content) = context.plugin_manager().run_before_agent(&self.arguments.name, &callback_context).await {
+            let event = self.callback_event(&context, content, true);
+            context.emit_event(&event);
+            events.push(event);
+            return Ok(events);
+        }
+        if context.end_invocation() {
+            return Ok(events);
+        }
+
+        if let Some(callbacks) = &self.arguments.before_agent_callback {
             for callback in callbacks {
                 let callback_context = CallbackContext::new(context.clone(), None);
                 let receiver = callback(callback_context);
                 if let Ok(Some(content)) = receiver.await {
-                    events.push(Event {
-                        id: Event::generate_event_id(),
-                        invocation_id: context.invocation_id().to_string(),
-                        author: self.name.clone(),
-                        branch: context.branch().map(|s| s.to_string()),
-                        actions: EventActions::builder().build(),
-                        content: Some(content),
-                        final_response: false,
-                    });
-                    if context.end_invocation() {
-                        return Ok(events);
-                    }
+                    let event = self.callback_event(&context, content, true);
+                    context.emit_event(&event);
+                    events.push(event);
+                    return Ok(events);
+                }
+                if context.end_invocation() {
+                    return Ok(events);
                 }
             }
         }
 
-        let main_events = self.run_async_impl(&context).await?;
+        let main_events = run_impl(context.clone()).await?;
         events.extend(main_events);
+        if context.end_invocation() {
+            return Ok(events);
+        }
+
+        let callback_context = CallbackContext::new(context.clone(), None);
+        if let Some(content) = context.plugin_manager().run_after_agent(&self.arguments.name, &callback_context).await {
+            let event = self.callback_event(&context, content, false);
+            context.emit_event(&event);
+            events.push(event);
+            return Ok(events);
+        }
 
-        if let Some(callbacks) = &self.after_agent_callback {
+        if let Some(callbacks) = &self.arguments.after_agent_callback {
             for callback in callbacks {
                 let callback_context = CallbackContext::new(context.clone(), None);
                 let receiver = callback(callback_context);
                 if let Ok(Some(content)) = receiver.await {
-                    events.push(Event {
-                        id: Event::generate_event_id(),
-                        invocation_id: context.invocation_id().to_string(),
-                        author: self.name.clone(),
-                        branch: context.branch().map(|s| s.to_string()),
-                        actions: EventActions::builder().build(),
-                        content: Some(content),
-                        final_response: false,
-                    });
+                    let event = self.callback_event(&context, content, false);
+                    context.emit_event(&event);
+                    events.push(event);
+                }
+                if context.end_invocation() {
+                    break;
                 }
             }
         }
@@ -216,11 +529,431 @@ impl BaseAgent {
         Ok(events)
     }
 
-    async fn run_async_impl(&self, _context: &InvocationContext) -> Result<Vec<Event>, AgentError> {
-        Ok(Vec::new())
+    fn callback_event(&self, context: &InvocationContext, content: Content, final_response: bool) -> Event {
+        Event::builder()
+            .invocation_id(context.invocation_id().to_string())
+            .author(self.arguments.name.clone())
+            .branch(context.branch().map(|s| s.to_string()))
+            .content(Some(content))
+            .final_response(final_response)
+            .build()
     }
 
-    async fn run_live_impl(&self, _context: &InvocationContext) -> Result<Vec<Event>, AgentError> {
-        Err(AgentError::UnsupportedOperation("run_live not implemented".to_string()))
+    pub async fn run_tool(
+        &self,
+        tool: Arc<dyn BaseTool>,
+        args: serde_json::Value,
+        tool_context: ToolContext,
+    ) -> Result<serde_json::Value, AgentError> {
+        let span = telemetry::tool_span(
+            tool_context.invocation_context(),
+            tool.name(),
+            tool.description(),
+            tool_context.function_call_id(),
+            &args,
+        );
+        let tool_name = tool.name().to_string();
+        let started_at = Instant::now();
+        let cancellation = tool_context.invocation_context().cancellation().clone();
+        let cancelled = || AgentError::ToolExecutionFailed(format!("The call to {} was cancelled", tool_name));
+        let result = if cancellation.is_cancelled() {
+            Err(cancelled())
+        } else {
+            tokio::select! {
+                result = self.run_tool_with_callbacks(tool, args, tool_context).instrument(span.clone()) => result,
+                _ = cancellation.cancelled() => Err(cancelled()),
+            }
+        };
+        agent_metrics::record_tool_call(&self.arguments.name, &tool_name, started_at.elapsed(), result.is_ok());
+        match &result {
+            Ok(result) => span.record("coagent.tool.result_size", result.to_string().len()),
+            Err(error) => span.record("error.type", error.to_string()),
+        };
+        result
     }
-}
\ No newline at end of file
+
+    async fn run_tool_with_callbacks(
+        &self,
+        tool: Arc<dyn BaseTool>,
+        args: serde_json::Value,
+        tool_context: ToolContext,
+    ) -> Result<serde_json::Value, AgentError> {
+        let mut args = args;
+        let plugin_manager = tool_context.invocation_context().plugin_manager().clone();
+        let mut result = plugin_manager.run_before_tool(tool.as_ref(), &mut args, &tool_context).await;
+
+        if let Some(callbacks) = self.arguments.before_tool_callback.as_ref().filter(|_| result.is_none()) {
+            for callback in callbacks {
+                let receiver = callback(tool.clone(), args.clone(), tool_context.clone());
+                match receiver.await {
+                    Ok(BeforeToolResult::Continue(new_args)) => args = new_args,
+                    Ok(BeforeToolResult::Respond(response)) => {
+                        result = Some(response);
+                        break;
+                    }
+                    Err(_) => {}
+                }
+            }
+        }
+
+        let mut result = match result {
+            Some(result) => result,
+            None => tool.run_async(args.clone(), tool_context.clone()).await?,
+        };
+
+        if let Some(response) = plugin_manager.run_after_tool(tool.as_ref(), &args, &tool_context, &result).await {
+            return Ok(response);
+        }
+
+        if let Some(callbacks) = &self.arguments.after_tool_callback {
+            for callback in callbacks {
+                let receiver = callback(tool.clone(), args.clone(), tool_context.clone(), result.clone());
+                if let Ok(Some(response)) = receiver.await {
+                    result = response;
+                    break;
+                }
+            }
+        }
+
+        Ok(result)
+    }
+
+    pub async fn call_llm(
+        &self,
+        model: &dyn BaseLlm,
+        request: LlmRequest,
+        context: &mut InvocationContext,
+    ) -> Result<LlmResponse, AgentError> {
+        context.increment_llm_calls_count()?;
+        let span = telemetry::model_span(context, model.provider(), model.model());
+        let started_at = Instant::now();
+        let response = Self::call_llm_with_callbacks(model, request, context).instrument(span.clone()).await;
+        agent_metrics::record_model_call(
+            &self.arguments.name,
+            model.model(),
+            started_at.elapsed(),
+            response.as_ref().ok().and_then(|response| response.usage_metadata.as_ref()),
+            response.as_ref().is_ok_and(|response| response.error_message.is_none()),
+        );
+        match &response {
+            Ok(response) => {
+                if let Some(usage) = &response.usage_metadata {
+                    span.record("gen_ai.usage.input_tokens", usage.prompt_token_count);
+                    span.record("gen_ai.usage.output_tokens", usage.candidates_token_count);
+                }
+                if let Some(error) = &response.error_message {
+                    span.record("error.type", error.as_str());
+                }
+            }
+            Err(error) => {
+                span.record("error.type", error.to_string());
+            }
+        }
+        response
+    }
+
+    async fn call_llm_with_callbacks(
+        model: &dyn BaseLlm,
+        request: LlmRequest,
+        context: &InvocationContext,
+    ) -> Result<LlmResponse, AgentError> {
+        let mut request = request;
+        let callback_context = CallbackContext::new(context.clone(), None);
+        let plugin_manager = context.plugin_manager();
+
+        if let Some(response) = plugin_manager.run_before_model(&callback_context, &mut request).await {
+            return Ok(response);
+        }
+
+        let response = model.generate_content(request).await?;
+
+        Ok(plugin_manager.run_after_model(&callback_context, &response).await.unwrap_or(response))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::{Blob, LiveRequestQueue};
+    use crate::run_config::RunConfig;
+    use crate::sequential_agent::SequentialAgent;
+    use crate::llm_agent::{LlmAgent, LlmAgentBuilder};
+    use crate::plugin::Plugin;
+    use crate::runner::Runner;
+    use crate::testing::{
+        function_call, function_responses, ready, run, runner_with_session, unique_name, Echo, Reply, ScriptedLlm,
+    };
+    use async_trait::async_trait;
+    use serde_json::json;
+    use std::sync::atomic::Ordering;
+
+    /// An agent calling `echo` once with `{"text": "hi"}` and then answering,
+    /// with the model it asks.
+    fn echo_caller(echo: &Arc<Echo>) -> (LlmAgentBuilder, Arc<ScriptedLlm>) {
+        let model = ScriptedLlm::new(vec![function_call("echo", json!({ "text": "hi" })), Content::from_text("model", "done")]);
+        let builder = LlmAgent::builder(model.clone()).tools(vec![echo.clone()]).name(unique_name("caller"));
+        (builder, model)
+    }
+
+    /// The result of the echo call, as sent back to the model.
+    fn tool_result(model: &ScriptedLlm) -> serde_json::Value {
+        function_responses(&model.requests()[1]).remove(0)
+    }
+
+    #[tokio::test]
+    async fn before_tool_callbacks_rewrite_args_in_order() {
+        let echo = Echo::new();
+        let (builder, model) = echo_caller(&echo);
+        let agent = builder
+            .before_tool_callback(Arc::new(|_, args, _| {
+                ready(BeforeToolResult::Continue(json!({ "text": format!("{}!", args["text"].as_str().unwrap()) })))
+            }))
+            .before_tool_callback(Arc::new(|_, args, _| {
+                ready(BeforeToolResult::Continue(json!({ "text": format!("{}?", args["text"].as_str().unwrap()) })))
+            }))
+            .spawn()
+            .await
+            .unwrap();
+        run(&agent, "go").await.unwrap();
+        assert_eq!(tool_result(&model), json!({ "echo": { "text": "hi!?" } }));
+    }
+
+    #[tokio::test]
+    async fn a_before_tool_response_skips_the_tool() {
+        let echo = Echo::new();
+        let (builder, model) = echo_caller(&echo);
+        let agent = builder
+            .before_tool_callback(Arc::new(|_, _, _| ready(BeforeToolResult::Respond(json!({ "cached": true })))))
+            .before_tool_callback(Arc::new(|_, _, _| ready(BeforeToolResult::Respond(json!({ "cached": false })))))
+            .spawn()
+            .await
+            .unwrap();
+        run(&agent, "go").await.unwrap();
+        assert_eq!(echo.calls.load(Ordering::SeqCst), 0);
+        assert_eq!(tool_result(&model), json!({ "cached": true }));
+    }
+
+    fn recording_after_tool(
+        log: &Arc<Mutex<Vec<&'static str>>>,
+        name: &'static str,
+        result: Option<serde_json::Value>,
+    ) -> AfterToolCallback {
+        let log = log.clone();
+        Arc::new(move |_, _, _, _| {
+            log.lock().unwrap().push(name);
+            ready(result.clone())
+        })
+    }
+
+    #[tokio::test]
+    async fn the_first_after_tool_result_replaces_the_tool_result() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let echo = Echo::new();
+        let (builder, model) = echo_caller(&echo);
+        let agent = builder
+            .after_tool_callback(recording_after_tool(&log, "first", None))
+            .after_tool_callback(recording_after_tool(&log, "second", Some(json!({ "replaced": 2 }))))
+            .after_tool_callback(recording_after_tool(&log, "third", Some(json!({ "replaced": 3 }))))
+            .spawn()
+            .await
+            .unwrap();
+        run(&agent, "go").await.unwrap();
+        assert_eq!(echo.calls.load(Ordering::SeqCst), 1);
+        assert_eq!(*log.lock().unwrap(), ["first", "second"]);
+        assert_eq!(tool_result(&model), json!({ "replaced": 2 }));
+    }
+
+    #[tokio::test]
+    async fn a_before_agent_answer_skips_the_agent() {
+        let (agent, runs) = Reply::spawn_with(
+            Reply::builder("hi".to_string())
+                .before_agent_callback(Arc::new(|_| ready(Some(Content::from_text("model", "closed")))))
+                .name(unique_name("reply")),
+        )
+        .await;
+        let events = run(&agent, "hello").await.unwrap();
+        assert_eq!(runs.load(Ordering::SeqCst), 0);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].content().unwrap().text(), "closed");
+        assert!(events[0].final_response());
+        assert_eq!(events[0].author, agent.get_name().unwrap());
+    }
+
+    #[tokio::test]
+    async fn agent_callbacks_run_in_order() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let record = |name: &'static str, answer: Option<&'static str>| {
+            let log = log.clone();
+            Arc::new(move |_: CallbackContext| {
+                log.lock().unwrap().push(name);
+                ready(answer.map(|answer| Content::from_text("model", answer)))
+            })
+        };
+        let (agent, runs) = Reply::spawn_with(
+            Reply::builder("hi".to_string())
+                .before_agent_callback(record("before 1", None))
+                .before_agent_callback(record("before 2", None))
+                .after_agent_callback(record("after 1", Some("note 1")))
+                .after_agent_callback(record("after 2", Some("note 2")))
+                .name(unique_name("reply")),
+        )
+        .await;
+        let events = run(&agent, "hello").await.unwrap();
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        assert_eq!(*log.lock().unwrap(), ["before 1", "before 2", "after 1", "after 2"]);
+        let texts: Vec<String> = events.iter().map(|event| event.content().unwrap().text()).collect();
+        assert_eq!(texts, ["hi", "note 1", "note 2"]);
+        assert!(!events[1].final_response());
+    }
+
+    #[tokio::test]
+    async fn ending_the_invocation_skips_the_remaining_agents() {
+        let (first, first_runs) = Reply::spawn_with(
+            Reply::builder("first".to_string())
+                .after_agent_callback(Arc::new(|callback_context: CallbackContext| {
+                    callback_context.set_end_invocation(true);
+                    ready(None)
+                }))
+                .name(unique_name("first")),
+        )
+        .await;
+        let (second, second_runs) = Reply::spawn_with(Reply::builder("second".to_string()).name(unique_name("second"))).await;
+        let root = SequentialAgent::builder()
+            .name(unique_name("pipeline"))
+            .sub_agents(vec![Arc::new(first.get_cell()), Arc::new(second.get_cell())])
+            .spawn()
+            .await
+            .unwrap();
+        let events = run(&root, "hello").await.unwrap();
+        assert_eq!(first_runs.load(Ordering::SeqCst), 1);
+        assert_eq!(second_runs.load(Ordering::SeqCst), 0);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].content().unwrap().text(), "first");
+    }
+
+    struct ReplaceResults;
+
+    #[async_trait]
+    impl Plugin for ReplaceResults {
+        fn name(&self) -> &str {
+            "replace_results"
+        }
+
+        async fn after_tool(
+            &self,
+            _tool: &dyn BaseTool,
+            _args: &serde_json::Value,
+            _tool_context: &ToolContext,
+            _result: &serde_json::Value,
+        ) -> Option<serde_json::Value> {
+            Some(json!({ "from": "plugin" }))
+        }
+    }
+
+    #[tokio::test]
+    async fn a_plugin_after_tool_result_skips_the_after_tool_callbacks() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let echo = Echo::new();
+        let (builder, model) = echo_caller(&echo);
+        let agent = builder
+            .after_tool_callback(recording_after_tool(&log, "callback", Some(json!({ "from": "callback" }))))
+            .spawn()
+            .await
+            .unwrap();
+        let runner = Runner::builder("test".to_string(), agent.get_cell()).plugin(Arc::new(ReplaceResults)).build();
+        let session = runner.session_service().create_session("test", "user", None, None).await.unwrap();
+        runner
+            .run_async("user", session.id(), Content::from_text("user", "go"), RunConfig::default())
+            .await
+            .unwrap();
+        assert!(log.lock().unwrap().is_empty());
+        assert_eq!(tool_result(&model), json!({ "from": "plugin" }));
+    }
+
+    #[tokio::test]
+    async fn interrupted_turns_stop_their_sub_agents() {
+        let (slow, runs) = Reply::spawn_with(
+            Reply::builder("late".to_string())
+                .delay(Duration::from_millis(200))
+                .name(unique_name("slow")),
+        )
+        .await;
+        let root = SequentialAgent::builder()
+            .name(unique_name("live"))
+            .sub_agents(vec![Arc::new(slow.get_cell())])
+            .spawn()
+            .await
+            .unwrap();
+        let (runner, session_id) = runner_with_session(&root).await;
+        let queue = LiveRequestQueue::new();
+        let mut stream = runner.run_live("user", &session_id, queue.clone(), RunConfig::default());
+
+        queue.send_content(Content::from_text("user", "first"));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        queue.send_activity_start();
+        // Long enough for the sub-agent to answer had it not been cancelled.
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        queue.send(LiveRequest::Close);
+
+        let mut events = Vec::new();
+        while let Some(event) = stream.recv().await {
+            events.push(event.unwrap());
+        }
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        let interrupted = events.iter().position(|event| event.interrupted).unwrap();
+        assert_eq!(interrupted, events.len() - 1);
+        assert!(events
+            .iter()
+            .all(|event| event.content.as_ref().map(|content| content.text()).as_deref() != Some("late")));
+    }
+
+    #[tokio::test]
+    async fn activity_ended_during_a_turn_becomes_the_next_turn() {
+        let (slow, runs) = Reply::spawn_with(
+            Reply::builder("late".to_string())
+                .delay(Duration::from_millis(200))
+                .name(unique_name("slow")),
+        )
+        .await;
+        let root = SequentialAgent::builder()
+            .name(unique_name("live"))
+            .sub_agents(vec![Arc::new(slow.get_cell())])
+            .spawn()
+            .await
+            .unwrap();
+        let (runner, session_id) = runner_with_session(&root).await;
+        let queue = LiveRequestQueue::new();
+        let mut stream = runner.run_live("user", &session_id, queue.clone(), RunConfig::default());
+
+        queue.send_content(Content::from_text("user", "first"));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        queue.send_realtime(Blob {
+            mime_type: "audio/pcm".to_string(),
+            data: vec![1, 2, 3],
+        });
+        queue.send_activity_end();
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        queue.send(LiveRequest::Close);
+
+        let mut events = Vec::new();
+        while let Some(event) = stream.recv().await {
+            events.push(event.unwrap());
+        }
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+        let spoken = events
+            .iter()
+            .position(|event| {
+                event.author == "user"
+                    && event.content.as_ref().is_some_and(|content| {
+                        content.parts.iter().any(|part| part.inline_data.as_ref().is_some_and(|blob| blob.data == [1, 2, 3]))
+                    })
+            })
+            .unwrap();
+        assert!(events[..spoken].iter().any(|event| event.interrupted));
+        let answered = &events[spoken + 1..];
+        assert!(answered
+            .iter()
+            .any(|event| event.content.as_ref().map(|content| content.text()).as_deref() == Some("late")));
+        assert!(answered.last().unwrap().turn_complete);
+    }
+}
@@ -0,0 +1,56 @@
+use crate::common::AgentError;
+use crate::tool_context::ToolContext;
+use async_trait::async_trait;
+use std::sync::Arc;
+
+#[async_trait]
+pub trait BaseTool: Send + Sync {
+    fn name(&self) -> &str;
+
+    fn description(&self) -> &str;
+
+    /// JSON Schema of the arguments, sent to the model with the tool's name
+    /// and description.
+    fn parameters(&self) -> Option<serde_json::Value> {
+        None
+    }
+
+    /// Whether calls must be approved by the user before the tool runs. The
+    /// agent pauses the invocation until `Runner::resume_async` brings the
+    /// user's decision.
+    fn requires_confirmation(&self) -> bool {
+        false
+    }
+
+    async fn run_async(&self, args: serde_json::Value, tool_context: ToolContext) -> Result<serde_json::Value, AgentError>;
+}
+
+struct ConfirmedTool(Arc<dyn BaseTool>);
+
+#[async_trait]
+impl BaseTool for ConfirmedTool {
+    fn name(&self) -> &str {
+        self.0.name()
+    }
+
+    fn description(&self) -> &str {
+        self.0.description()
+    }
+
+    fn parameters(&self) -> Option<serde_json::Value> {
+        self.0.parameters()
+    }
+
+    fn requires_confirmation(&self) -> bool {
+        true
+    }
+
+    async fn run_async(&self, args: serde_json::Value, tool_context: ToolContext) -> Result<serde_json::Value, AgentError> {
+        self.0.run_async(args, tool_context).await
+    }
+}
+
+/// Makes calls to `tool` wait for the user's confirmation.
+pub fn require_confirmation(tool: Arc<dyn BaseTool>) -> Arc<dyn BaseTool> {
+    Arc::new(ConfirmedTool(tool))
+}
@@ -1,11 +1,92 @@
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::{mpsc, Mutex};
 use uuid::Uuid;
 
-#[derive(Clone, Debug)]
-pub struct Content;
+/// Version of the JSON format of `Event`, `Session` and `RunConfig`; nested
+/// types such as `Content` are versioned by the document holding them. Bump
+/// it when the format changes and teach the `upgrade` methods to read the
+/// previous one. Documents written before versioning read as version 0.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Rejects documents written by a newer version of the format.
+pub(crate) fn check_schema_version(kind: &str, schema_version: u32) -> Result<(), AgentError> {
+    if schema_version > SCHEMA_VERSION {
+        return Err(AgentError::SerializationError(format!(
+            "{} uses schema version {}, this build reads up to {}",
+            kind, schema_version, SCHEMA_VERSION
+        )));
+    }
+    Ok(())
+}
 
-#[derive(Clone, Debug)]
+/// Seconds since the Unix epoch.
+pub fn now() -> f64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_secs_f64()).unwrap_or_default()
+}
+
+fn to_json<T: Serialize>(value: &T) -> Result<String, AgentError> {
+    serde_json::to_string(value).map_err(|e| AgentError::SerializationError(e.to_string()))
+}
+
+fn from_json<T: serde::de::DeserializeOwned>(kind: &str, json: &str) -> Result<T, AgentError> {
+    serde_json::from_str(json).map_err(|e| AgentError::SerializationError(format!("Invalid {}: {}", kind, e)))
+}
+
+/// Blob data as a base64 string rather than an array of numbers.
+mod base64_data {
+    use base64::Engine;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&base64::engine::general_purpose::STANDARD.encode(data))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let data = String::deserialize(deserializer)?;
+        base64::engine::general_purpose::STANDARD.decode(data).map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Content {
+    pub role: String,
+    pub parts: Vec<Part>,
+}
+
+impl Content {
+    pub fn new(role: String, parts: Vec<Part>) -> Self {
+        Content { role, parts }
+    }
+
+    pub fn from_text(role: &str, text: &str) -> Self {
+        Content {
+            role: role.to_string(),
+            parts: vec![Part::from_text(text)],
+        }
+    }
+
+    /// The text of the parts, leaving out the model's thoughts.
+    pub fn text(&self) -> String {
+        self.parts
+            .iter()
+            .filter(|part| part.thought.is_none())
+            .filter_map(|part| part.text.as_deref())
+            .collect::<Vec<_>>()
+            .join("")
+    }
+
+    pub fn function_calls(&self) -> Vec<&FunctionCall> {
+        self.parts.iter().filter_map(|part| part.function_call.as_ref()).collect()
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Event {
+    #[serde(default)]
+    pub schema_version: u32,
     pub id: String,
     pub invocation_id: String,
     pub author: String,
@@ -13,6 +94,30 @@ pub struct Event {
     pub actions: EventActions,
     pub content: Option<Content>,
     pub final_response: bool,
+    /// Creation time in seconds since the Unix epoch.
+    #[serde(default)]
+    pub timestamp: f64,
+    /// Set in live mode on the event that ends an agent's reply to a turn.
+    #[serde(default)]
+    pub turn_complete: bool,
+    /// Set in live mode when new user activity cut the agent's reply short.
+    #[serde(default)]
+    pub interrupted: bool,
+    /// Set when a supervising agent restarted failed sub-agents.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub restart: Option<AgentRestart>,
+    /// Set when a routing agent picked the sub-agent to handle the request.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub route: Option<AgentRoute>,
+    /// Set when a voting agent combined the answers of its sub-agents.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vote: Option<AgentVote>,
+    /// Set when an agent paused to have tool calls confirmed by the user.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub confirmation: Option<ToolConfirmation>,
+    /// Set on the user's answer to a `confirmation`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decisions: Option<Vec<ToolDecision>>,
 }
 
 impl Event {
@@ -25,6 +130,14 @@ impl Event {
             actions: EventActions::builder().build(),
             content: None,
             final_response: false,
+            timestamp: now(),
+            turn_complete: false,
+            interrupted: false,
+            restart: None,
+            route: None,
+            vote: None,
+            confirmation: None,
+            decisions: None,
         }
     }
 
@@ -43,6 +156,27 @@ impl Event {
     pub fn content(&self) -> Option<&Content> {
         self.content.as_ref()
     }
+
+    pub fn timestamp(&self) -> f64 {
+        self.timestamp
+    }
+
+    /// Brings an event read from an older schema version to the current one.
+    pub fn upgrade(&mut self) -> Result<(), AgentError> {
+        check_schema_version("Event", self.schema_version)?;
+        self.schema_version = SCHEMA_VERSION;
+        Ok(())
+    }
+
+    pub fn to_json(&self) -> Result<String, AgentError> {
+        to_json(self)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, AgentError> {
+        let mut event: Event = from_json("event", json)?;
+        event.upgrade()?;
+        Ok(event)
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -54,6 +188,14 @@ pub struct EventBuilder {
     actions: EventActions,
     content: Option<Content>,
     final_response: bool,
+    timestamp: f64,
+    turn_complete: bool,
+    interrupted: bool,
+    restart: Option<AgentRestart>,
+    route: Option<AgentRoute>,
+    vote: Option<AgentVote>,
+    confirmation: Option<ToolConfirmation>,
+    decisions: Option<Vec<ToolDecision>>,
 }
 
 impl EventBuilder {
@@ -87,8 +229,54 @@ impl EventBuilder {
         self
     }
 
+    pub fn final_response(mut self, final_response: bool) -> Self {
+        self.final_response = final_response;
+        self
+    }
+
+    pub fn timestamp(mut self, timestamp: f64) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+
+    pub fn turn_complete(mut self, turn_complete: bool) -> Self {
+        self.turn_complete = turn_complete;
+        self
+    }
+
+    pub fn interrupted(mut self, interrupted: bool) -> Self {
+        self.interrupted = interrupted;
+        self
+    }
+
+    pub fn restart(mut self, restart: Option<AgentRestart>) -> Self {
+        self.restart = restart;
+        self
+    }
+
+    pub fn route(mut self, route: Option<AgentRoute>) -> Self {
+        self.route = route;
+        self
+    }
+
+    pub fn vote(mut self, vote: Option<AgentVote>) -> Self {
+        self.vote = vote;
+        self
+    }
+
+    pub fn confirmation(mut self, confirmation: Option<ToolConfirmation>) -> Self {
+        self.confirmation = confirmation;
+        self
+    }
+
+    pub fn decisions(mut self, decisions: Option<Vec<ToolDecision>>) -> Self {
+        self.decisions = decisions;
+        self
+    }
+
     pub fn build(self) -> Event {
         Event {
+            schema_version: SCHEMA_VERSION,
             id: self.id,
             invocation_id: self.invocation_id,
             author: self.author,
@@ -96,11 +284,107 @@ impl EventBuilder {
             actions: self.actions,
             content: self.content,
             final_response: self.final_response,
+            timestamp: self.timestamp,
+            turn_complete: self.turn_complete,
+            interrupted: self.interrupted,
+            restart: self.restart,
+            route: self.route,
+            vote: self.vote,
+            confirmation: self.confirmation,
+            decisions: self.decisions,
         }
     }
 }
 
-#[derive(Clone, Debug)]
+/// Describes one restart performed by a supervising agent.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AgentRestart {
+    /// The sub-agent whose failure caused the restart.
+    pub failed_agent: String,
+    pub reason: String,
+    /// Every sub-agent that was restarted, more than one with one-for-all.
+    pub restarted_agents: Vec<String>,
+    /// Restarts within the current intensity window, this one included.
+    pub restart_count: usize,
+}
+
+/// Describes the choice of a routing agent.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AgentRoute {
+    /// The sub-agent the request was sent to.
+    pub agent: String,
+    pub reason: String,
+    /// Whether the router fell back to its default sub-agent.
+    pub fallback: bool,
+}
+
+/// Describes how a voting agent combined its candidate answers.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AgentVote {
+    /// `majority`, `judge` or `merge`.
+    pub method: String,
+    pub candidates: Vec<VoteCandidate>,
+    /// Index of the chosen candidate; unset when the answers were merged.
+    pub winner: Option<usize>,
+    pub reason: Option<String>,
+}
+
+/// Tool calls held until the user approves or rejects them.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ToolConfirmation {
+    /// The held calls with the arguments the model proposed.
+    pub function_calls: Vec<FunctionCall>,
+}
+
+/// The user's answer to one held tool call.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ToolDecision {
+    pub function_call_id: String,
+    pub approved: bool,
+    /// Arguments to run the tool with instead of the proposed ones.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub args: Option<serde_json::Value>,
+    /// Why the call was rejected, passed on to the model.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+impl ToolDecision {
+    pub fn approve(function_call_id: &str) -> Self {
+        ToolDecision {
+            function_call_id: function_call_id.to_string(),
+            approved: true,
+            args: None,
+            reason: None,
+        }
+    }
+
+    pub fn approve_with_args(function_call_id: &str, args: serde_json::Value) -> Self {
+        ToolDecision {
+            args: Some(args),
+            ..ToolDecision::approve(function_call_id)
+        }
+    }
+
+    pub fn reject(function_call_id: &str, reason: Option<String>) -> Self {
+        ToolDecision {
+            function_call_id: function_call_id.to_string(),
+            approved: false,
+            args: None,
+            reason,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct VoteCandidate {
+    /// The sub-agent that gave the answer.
+    pub agent: String,
+    pub answer: String,
+    pub score: Option<f64>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct EventActions {
     state_delta: HashMap<String, serde_json::Value>,
     escalate: bool,
@@ -137,6 +421,11 @@ pub struct EventActionsBuilder {
 }
 
 impl EventActionsBuilder {
+    pub fn escalate(mut self, escalate: bool) -> Self {
+        self.escalate = escalate;
+        self
+    }
+
     pub fn build(self) -> EventActions {
         EventActions {
             state_delta: self.state_delta,
@@ -146,22 +435,123 @@ impl EventActionsBuilder {
     }
 }
 
-#[derive(Clone, Debug)]
-pub struct Part;
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Part {
+    pub text: Option<String>,
+    pub function_call: Option<FunctionCall>,
+    pub function_response: Option<FunctionResponse>,
+    pub inline_data: Option<Blob>,
+    /// Set on text the model wrote while thinking rather than answering.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thought: Option<Thought>,
+}
 
-#[derive(Clone, Debug)]
+impl Part {
+    pub fn from_text(text: &str) -> Self {
+        Part {
+            text: Some(text.to_string()),
+            ..Default::default()
+        }
+    }
+
+    pub fn from_function_call(function_call: FunctionCall) -> Self {
+        Part {
+            function_call: Some(function_call),
+            ..Default::default()
+        }
+    }
+
+    pub fn from_function_response(function_response: FunctionResponse) -> Self {
+        Part {
+            function_response: Some(function_response),
+            ..Default::default()
+        }
+    }
+
+    pub fn from_blob(blob: Blob) -> Self {
+        Part {
+            inline_data: Some(blob),
+            ..Default::default()
+        }
+    }
+
+    pub fn from_thought(thought: Thought, text: &str) -> Self {
+        Part {
+            text: Some(text.to_string()),
+            thought: Some(thought),
+            ..Default::default()
+        }
+    }
+}
+
+/// The kind of thinking a thought part holds, so that clients can show or
+/// collapse each kind on its own.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Thought {
+    Planning,
+    Reasoning,
+    Action,
+}
+
+/// Raw media such as an audio chunk or an image.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Blob {
+    pub mime_type: String,
+    #[serde(with = "base64_data")]
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FunctionCall {
+    pub id: Option<String>,
+    pub name: String,
+    pub args: serde_json::Value,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FunctionResponse {
+    pub id: Option<String>,
+    pub name: String,
+    pub response: serde_json::Value,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Session {
+    #[serde(default)]
+    schema_version: u32,
     state: HashMap<String, serde_json::Value>,
     app_name: String,
     user_id: String,
     id: String,
+    events: Vec<Event>,
+    /// Time of the last appended event, or of creation, in seconds since the
+    /// Unix epoch.
+    #[serde(default)]
+    last_update_time: f64,
 }
 
 impl Session {
+    pub fn new(app_name: String, user_id: String, id: String, state: HashMap<String, serde_json::Value>) -> Self {
+        Session {
+            schema_version: SCHEMA_VERSION,
+            state,
+            app_name,
+            user_id,
+            id,
+            events: Vec::new(),
+            last_update_time: now(),
+        }
+    }
+
     pub fn state(&self) -> &HashMap<String, serde_json::Value> {
         &self.state
     }
 
+    pub fn state_mut(&mut self) -> &mut HashMap<String, serde_json::Value> {
+        &mut self.state
+    }
+
     pub fn app_name(&self) -> &str {
         &self.app_name
     }
@@ -173,24 +563,144 @@ impl Session {
     pub fn id(&self) -> &str {
         &self.id
     }
+
+    pub fn events(&self) -> &Vec<Event> {
+        &self.events
+    }
+
+    pub fn last_update_time(&self) -> f64 {
+        self.last_update_time
+    }
+
+    /// The event of an agent waiting for tool calls to be confirmed, unless
+    /// the user already answered it.
+    pub fn pending_confirmation(&self) -> Option<&Event> {
+        self.events
+            .iter()
+            .rev()
+            .find(|event| event.confirmation.is_some() || event.decisions.is_some())
+            .filter(|event| event.confirmation.is_some())
+    }
+
+    pub fn append_event(&mut self, event: Event) {
+        let mut actions = event.actions.clone();
+        for (key, value) in actions.state_delta().drain() {
+            self.state.insert(key, value);
+        }
+        self.last_update_time = self.last_update_time.max(event.timestamp);
+        self.events.push(event);
+    }
+
+    /// Brings a session read from an older schema version, and its events,
+    /// to the current one.
+    pub fn upgrade(&mut self) -> Result<(), AgentError> {
+        check_schema_version("Session", self.schema_version)?;
+        for event in &mut self.events {
+            event.upgrade()?;
+        }
+        if self.last_update_time == 0.0 {
+            self.last_update_time = self.events.iter().map(|event| event.timestamp).fold(0.0, f64::max);
+        }
+        self.schema_version = SCHEMA_VERSION;
+        Ok(())
+    }
+
+    pub fn to_json(&self) -> Result<String, AgentError> {
+        to_json(self)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, AgentError> {
+        let mut session: Session = from_json("session", json)?;
+        session.upgrade()?;
+        Ok(session)
+    }
 }
 
+/// A message from the client of a live session.
 #[derive(Clone, Debug)]
-pub struct BaseSessionService;
+pub enum LiveRequest {
+    /// A complete user turn.
+    Content(Content),
+    /// A chunk of realtime media. Chunks are buffered until `ActivityEnd`.
+    Realtime(Blob),
+    /// The user started speaking; interrupts the agent's reply in progress.
+    ActivityStart,
+    /// The user stopped speaking; the buffered chunks form the next turn.
+    ActivityEnd,
+    /// Ends the live session once the current reply is done.
+    Close,
+}
 
+/// Channel from the client to the agents of a live invocation. Clones share
+/// the same queue, so the client keeps one clone while the runner hands the
+/// other to the agents.
 #[derive(Clone, Debug)]
-pub struct BaseArtifactService;
+pub struct LiveRequestQueue {
+    sender: mpsc::UnboundedSender<LiveRequest>,
+    receiver: Arc<Mutex<mpsc::UnboundedReceiver<LiveRequest>>>,
+}
 
-#[derive(Clone, Debug)]
-pub struct LiveRequestQueue;
+impl LiveRequestQueue {
+    pub fn new() -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        LiveRequestQueue {
+            sender,
+            receiver: Arc::new(Mutex::new(receiver)),
+        }
+    }
 
-#[derive(Clone, Debug)]
-pub struct BaseTool;
+    pub fn send(&self, request: LiveRequest) {
+        let _ = self.sender.send(request);
+    }
+
+    pub fn send_content(&self, content: Content) {
+        self.send(LiveRequest::Content(content));
+    }
+
+    pub fn send_realtime(&self, blob: Blob) {
+        self.send(LiveRequest::Realtime(blob));
+    }
+
+    pub fn send_activity_start(&self) {
+        self.send(LiveRequest::ActivityStart);
+    }
+
+    pub fn send_activity_end(&self) {
+        self.send(LiveRequest::ActivityEnd);
+    }
+
+    pub fn close(&self) {
+        self.send(LiveRequest::Close);
+    }
+
+    /// Waits for the next request. Only one consumer reads at a time.
+    pub async fn recv(&self) -> LiveRequest {
+        // The queue holds a sender itself, so the channel never closes.
+        self.receiver.lock().await.recv().await.unwrap_or(LiveRequest::Close)
+    }
+}
+
+impl Default for LiveRequestQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub enum AgentError {
     LlmCallsLimitExceeded(String),
     UnsupportedOperation(String),
+    ToolExecutionFailed(String),
+    AgentFailed(String),
+    SessionNotFound(String),
+    ModelError(String),
+    TelemetryError(String),
+    EmbeddingError(String),
+    StorageError(String),
+    InstructionError(String),
+    OutputValidationError(String),
+    ConfigError(String),
+    SerializationError(String),
 }
 
 impl std::fmt::Display for AgentError {
@@ -198,8 +708,104 @@ impl std::fmt::Display for AgentError {
         match self {
             AgentError::LlmCallsLimitExceeded(msg) => write!(f, "{}", msg),
             AgentError::UnsupportedOperation(msg) => write!(f, "{}", msg),
+            AgentError::ToolExecutionFailed(msg) => write!(f, "{}", msg),
+            AgentError::AgentFailed(msg) => write!(f, "{}", msg),
+            AgentError::SessionNotFound(msg) => write!(f, "{}", msg),
+            AgentError::ModelError(msg) => write!(f, "{}", msg),
+            AgentError::TelemetryError(msg) => write!(f, "{}", msg),
+            AgentError::EmbeddingError(msg) => write!(f, "{}", msg),
+            AgentError::StorageError(msg) => write!(f, "{}", msg),
+            AgentError::InstructionError(msg) => write!(f, "{}", msg),
+            AgentError::OutputValidationError(msg) => write!(f, "{}", msg),
+            AgentError::ConfigError(msg) => write!(f, "{}", msg),
+            AgentError::SerializationError(msg) => write!(f, "{}", msg),
         }
     }
 }
 
-impl std::error::Error for AgentError {}
\ No newline at end of file
+impl std::error::Error for AgentError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn session() -> Session {
+        let mut session = Session::new("app".to_string(), "user".to_string(), "s-1".to_string(), HashMap::new());
+        let mut event = Event::builder()
+            .invocation_id("i-1".to_string())
+            .author("router".to_string())
+            .content(Some(Content::new(
+                "model".to_string(),
+                vec![
+                    Part::from_text("hi"),
+                    Part::from_blob(Blob {
+                        mime_type: "image/png".to_string(),
+                        data: vec![0, 159, 255],
+                    }),
+                ],
+            )))
+            .route(Some(AgentRoute {
+                agent: "support".to_string(),
+                reason: "matched keyword 'help'".to_string(),
+                fallback: false,
+            }))
+            .build();
+        event.actions.state_delta().insert("topic".to_string(), json!("rust"));
+        session.append_event(event);
+        session
+    }
+
+    fn to_value(json: &str) -> serde_json::Value {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn sessions_round_trip_through_json() {
+        let session = session();
+        let json = session.to_json().unwrap();
+        let read = Session::from_json(&json).unwrap();
+        assert_eq!(to_value(&read.to_json().unwrap()), to_value(&json));
+        assert_eq!(read.state()["topic"], json!("rust"));
+        assert_eq!(read.last_update_time(), session.last_update_time());
+        let event = &read.events()[0];
+        assert_eq!(event.content().unwrap().parts[1].inline_data.as_ref().unwrap().data, [0, 159, 255]);
+        assert_eq!(event.route.as_ref().unwrap().agent, "support");
+        assert_eq!(to_value(&json)["events"][0]["content"]["parts"][1]["inline_data"]["data"], json!("AJ//"));
+    }
+
+    #[test]
+    fn unversioned_sessions_are_upgraded() {
+        let mut value = to_value(&session().to_json().unwrap());
+        let timestamp = value["events"][0]["timestamp"].as_f64().unwrap();
+        let event = value["events"][0].as_object_mut().unwrap();
+        event.remove("schema_version");
+        event.remove("route");
+        let session = value.as_object_mut().unwrap();
+        session.remove("schema_version");
+        session.remove("last_update_time");
+
+        let session = Session::from_json(&value.to_string()).unwrap();
+        assert_eq!(session.schema_version, SCHEMA_VERSION);
+        assert_eq!(session.events()[0].schema_version, SCHEMA_VERSION);
+        assert_eq!(session.last_update_time(), timestamp);
+        assert!(session.events()[0].route.is_none());
+    }
+
+    #[test]
+    fn documents_from_newer_versions_are_rejected() {
+        let mut value = to_value(&session().to_json().unwrap());
+        value["events"][0]["schema_version"] = json!(SCHEMA_VERSION + 1);
+        let Err(AgentError::SerializationError(message)) = Session::from_json(&value.to_string()) else {
+            panic!("a newer event was read");
+        };
+        assert_eq!(
+            message,
+            format!("Event uses schema version {}, this build reads up to {}", SCHEMA_VERSION + 1, SCHEMA_VERSION)
+        );
+        let Err(AgentError::SerializationError(message)) = Session::from_json("{}") else {
+            panic!("an empty session was read");
+        };
+        assert!(message.starts_with("Invalid session"), "{}", message);
+    }
+}
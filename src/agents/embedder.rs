@@ -0,0 +1,147 @@
+use crate::common::AgentError;
+use async_trait::async_trait;
+use serde_json::json;
+
+#[async_trait]
+pub trait Embedder: Send + Sync + std::fmt::Debug {
+    async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, AgentError>;
+}
+
+/// Deterministic feature-hashing embedder. Every lowercase word is hashed
+/// into one of `dimensions` buckets and the vector is L2-normalized, so texts
+/// sharing words score high under cosine similarity. Needs no model, which
+/// makes it suitable for tests and offline use.
+#[derive(Clone, Debug)]
+pub struct HashingEmbedder {
+    dimensions: usize,
+}
+
+impl HashingEmbedder {
+    pub fn new(dimensions: usize) -> Self {
+        HashingEmbedder {
+            dimensions: dimensions.max(1),
+        }
+    }
+
+    pub fn dimensions(&self) -> usize {
+        self.dimensions
+    }
+
+    fn fnv1a(word: &str) -> u64 {
+        word.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        })
+    }
+
+    fn embed_text(&self, text: &str) -> Vec<f32> {
+        let mut vector = vec![0.0f32; self.dimensions];
+        for word in text.split(|c: char| !c.is_alphanumeric()).filter(|word| !word.is_empty()) {
+            let hash = Self::fnv1a(&word.to_lowercase());
+            let sign = if hash >> 63 == 0 { 1.0 } else { -1.0 };
+            vector[(hash % self.dimensions as u64) as usize] += sign;
+        }
+        let norm = vector.iter().map(|value| value * value).sum::<f32>().sqrt();
+        if norm > 0.0 {
+            vector.iter_mut().for_each(|value| *value /= norm);
+        }
+        vector
+    }
+}
+
+impl Default for HashingEmbedder {
+    fn default() -> Self {
+        HashingEmbedder::new(256)
+    }
+}
+
+#[async_trait]
+impl Embedder for HashingEmbedder {
+    async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, AgentError> {
+        Ok(texts.iter().map(|text| self.embed_text(text)).collect())
+    }
+}
+
+/// Client for any server implementing the OpenAI `/embeddings` endpoint,
+/// including local ones such as llama.cpp or Ollama.
+#[derive(Clone, Debug)]
+pub struct OpenAiEmbedder {
+    client: reqwest::Client,
+    base_url: String,
+    api_key: Option<String>,
+    model: String,
+}
+
+impl OpenAiEmbedder {
+    pub fn new(base_url: String, api_key: Option<String>, model: String) -> Self {
+        OpenAiEmbedder {
+            client: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key,
+            model,
+        }
+    }
+
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+}
+
+#[async_trait]
+impl Embedder for OpenAiEmbedder {
+    async fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, AgentError> {
+        if texts.is_empty() {
+            return Ok(Vec::new());
+        }
+        let body = json!({ "model": self.model, "input": texts });
+        let mut request = self
+            .client
+            .post(format!("{}/embeddings", self.base_url))
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body.to_string());
+        if let Some(api_key) = &self.api_key {
+            request = request.bearer_auth(api_key);
+        }
+        let response = request
+            .send()
+            .await
+            .map_err(|e| AgentError::EmbeddingError(e.to_string()))?;
+        let status = response.status();
+        let bytes = response
+            .bytes()
+            .await
+            .map_err(|e| AgentError::EmbeddingError(e.to_string()))?;
+        if !status.is_success() {
+            return Err(AgentError::EmbeddingError(format!(
+                "Embeddings request failed with {}: {}",
+                status,
+                String::from_utf8_lossy(&bytes)
+            )));
+        }
+        let response: serde_json::Value =
+            serde_json::from_slice(&bytes).map_err(|e| AgentError::EmbeddingError(e.to_string()))?;
+        let mut data: Vec<(u64, Vec<f32>)> = response
+            .get("data")
+            .and_then(|data| data.as_array())
+            .ok_or_else(|| AgentError::EmbeddingError("Embeddings response has no data".to_string()))?
+            .iter()
+            .map(|item| {
+                let index = item.get("index").and_then(|index| index.as_u64()).unwrap_or_default();
+                let embedding = item
+                    .get("embedding")
+                    .and_then(|embedding| embedding.as_array())
+                    .map(|values| values.iter().filter_map(|value| value.as_f64()).map(|value| value as f32).collect())
+                    .unwrap_or_default();
+                (index, embedding)
+            })
+            .collect();
+        data.sort_by_key(|(index, _)| *index);
+        if data.len() != texts.len() {
+            return Err(AgentError::EmbeddingError(format!(
+                "Expected {} embeddings, got {}",
+                texts.len(),
+                data.len()
+            )));
+        }
+        Ok(data.into_iter().map(|(_, embedding)| embedding).collect())
+    }
+}
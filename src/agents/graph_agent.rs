@@ -0,0 +1,779 @@
+//! Workflows shaped as a graph of sub-agents. Each node is a sub-agent,
+//! named as the agent is. After a node runs, its outgoing edges are decided
+//! from the session state or the node's last event; the nodes they lead to
+//! run next, and nodes that become ready together run in parallel. A node
+//! none of whose incoming edges fired is skipped, and so are the nodes only
+//! it leads to. With `Join::Any` a node runs once one of its incoming edges
+//! fires; with `Join::All` it waits until all of them are decided.
+//!
+//! Cycles are rejected unless they pass through a node marked as a loop.
+//! An edge back into a loop node runs the loop's body again, up to the
+//! node's iteration limit.
+
+use crate::agent::Agent;
+use crate::base_agent::BaseAgentState;
+use crate::common::{AgentError, Event};
+use crate::invocation_context::InvocationContext;
+use async_trait::async_trait;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use std::sync::Arc;
+use tokio::task::JoinSet;
+
+type StatePredicate = Arc<dyn Fn(&HashMap<String, serde_json::Value>) -> bool + Send + Sync>;
+type EventPredicate = Arc<dyn Fn(&Event) -> bool + Send + Sync>;
+
+#[derive(Clone)]
+enum ConditionKind {
+    Always,
+    Otherwise,
+    State(StatePredicate),
+    LastEvent(EventPredicate),
+}
+
+/// When an edge fires, decided right after its source node ran.
+#[derive(Clone)]
+pub struct EdgeCondition {
+    label: Option<String>,
+    kind: ConditionKind,
+}
+
+impl EdgeCondition {
+    pub fn always() -> Self {
+        EdgeCondition { label: None, kind: ConditionKind::Always }
+    }
+
+    /// Fires when no other edge leaving the same node fires.
+    pub fn otherwise() -> Self {
+        EdgeCondition {
+            label: Some("otherwise".to_string()),
+            kind: ConditionKind::Otherwise,
+        }
+    }
+
+    pub fn state_equals(key: &str, value: serde_json::Value) -> Self {
+        let label = format!("{} == {}", key, value);
+        let key = key.to_string();
+        EdgeCondition::when_state(&label, move |state| state.get(&key) == Some(&value))
+    }
+
+    /// Fires when `predicate` holds for the session state. `label` names the
+    /// condition in the DOT export.
+    pub fn when_state(label: &str, predicate: impl Fn(&HashMap<String, serde_json::Value>) -> bool + Send + Sync + 'static) -> Self {
+        EdgeCondition {
+            label: Some(label.to_string()),
+            kind: ConditionKind::State(Arc::new(predicate)),
+        }
+    }
+
+    /// Fires when `predicate` holds for the last event of the source node.
+    /// Never fires for a node that produced no events.
+    pub fn when_last_event(label: &str, predicate: impl Fn(&Event) -> bool + Send + Sync + 'static) -> Self {
+        EdgeCondition {
+            label: Some(label.to_string()),
+            kind: ConditionKind::LastEvent(Arc::new(predicate)),
+        }
+    }
+
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
+    fn is_otherwise(&self) -> bool {
+        matches!(self.kind, ConditionKind::Otherwise)
+    }
+
+    fn holds(&self, state: &HashMap<String, serde_json::Value>, last_event: Option<&Event>) -> bool {
+        match &self.kind {
+            ConditionKind::Always => true,
+            ConditionKind::Otherwise => false,
+            ConditionKind::State(predicate) => predicate(state),
+            ConditionKind::LastEvent(predicate) => last_event.is_some_and(|event| predicate(event)),
+        }
+    }
+}
+
+/// How many incoming edges a node waits for.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Join {
+    /// Runs as soon as one incoming edge fires.
+    #[default]
+    Any,
+    /// Runs once every incoming edge is decided and at least one fired.
+    All,
+}
+
+#[derive(Clone, Debug)]
+struct Node {
+    name: String,
+    entry: bool,
+    join: Join,
+    /// Set for loop nodes: how many times edges may lead back into the node.
+    max_iterations: Option<usize>,
+}
+
+#[derive(Clone)]
+struct Edge {
+    from: usize,
+    to: usize,
+    condition: EdgeCondition,
+    /// Leads back into a loop node from its loop's body.
+    back: bool,
+}
+
+/// A validated workflow graph.
+#[derive(Clone)]
+pub struct Graph {
+    nodes: Vec<Node>,
+    edges: Vec<Edge>,
+}
+
+impl Graph {
+    pub fn builder() -> GraphBuilder {
+        GraphBuilder {
+            nodes: Vec::new(),
+            edges: Vec::new(),
+            entries: Vec::new(),
+            joins: Vec::new(),
+            loops: Vec::new(),
+        }
+    }
+
+    pub fn node_names(&self) -> Vec<&str> {
+        self.nodes.iter().map(|node| node.name.as_str()).collect()
+    }
+
+    /// The graph in Graphviz DOT. Entry nodes are bold, loop nodes have a
+    /// double border and edges back into loops are dashed.
+    pub fn to_dot(&self, name: &str) -> String {
+        let mut dot = format!("digraph {} {{\n", dot_id(name));
+        for node in &self.nodes {
+            let mut lines = vec![node.name.clone()];
+            if node.join == Join::All {
+                lines.push("(join all)".to_string());
+            }
+            if let Some(max_iterations) = node.max_iterations {
+                lines.push(format!("(loop, max {})", max_iterations));
+            }
+            let label = lines.iter().map(|line| dot_escape(line)).collect::<Vec<_>>().join("\\n");
+            let mut attributes = vec!["shape=box".to_string(), format!("label=\"{}\"", label)];
+            if node.entry {
+                attributes.push("style=bold".to_string());
+            }
+            if node.max_iterations.is_some() {
+                attributes.push("peripheries=2".to_string());
+            }
+            let _ = writeln!(dot, "    {} [{}];", dot_id(&node.name), attributes.join(", "));
+        }
+        for edge in &self.edges {
+            let mut attributes = Vec::new();
+            if let Some(label) = edge.condition.label() {
+                attributes.push(format!("label={}", dot_id(label)));
+            }
+            if edge.back {
+                attributes.push("style=dashed".to_string());
+            }
+            let attributes = if attributes.is_empty() {
+                String::new()
+            } else {
+                format!(" [{}]", attributes.join(", "))
+            };
+            let _ = writeln!(
+                dot,
+                "    {} -> {}{};",
+                dot_id(&self.nodes[edge.from].name),
+                dot_id(&self.nodes[edge.to].name),
+                attributes
+            );
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Nodes reachable from `start` over forward edges.
+    fn reachable_from(&self, start: usize) -> HashSet<usize> {
+        let mut seen = HashSet::from([start]);
+        let mut stack = vec![start];
+        while let Some(node) = stack.pop() {
+            for edge in self.edges.iter().filter(|edge| edge.from == node && !edge.back) {
+                if seen.insert(edge.to) {
+                    stack.push(edge.to);
+                }
+            }
+        }
+        seen
+    }
+
+    /// Nodes `target` is reachable from over forward edges.
+    fn reaching(&self, target: usize) -> HashSet<usize> {
+        let mut seen = HashSet::from([target]);
+        let mut stack = vec![target];
+        while let Some(node) = stack.pop() {
+            for edge in self.edges.iter().filter(|edge| edge.to == node && !edge.back) {
+                if seen.insert(edge.from) {
+                    stack.push(edge.from);
+                }
+            }
+        }
+        seen
+    }
+}
+
+fn dot_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn dot_id(text: &str) -> String {
+    format!("\"{}\"", dot_escape(text))
+}
+
+#[derive(Clone)]
+pub struct GraphBuilder {
+    nodes: Vec<String>,
+    edges: Vec<(String, String, EdgeCondition)>,
+    entries: Vec<String>,
+    joins: Vec<String>,
+    loops: Vec<(String, usize)>,
+}
+
+impl GraphBuilder {
+    pub fn node(mut self, name: &str) -> Self {
+        self.add_node(name);
+        self
+    }
+
+    /// Marks a node the run starts from. Without entries, the run starts
+    /// from the nodes that no edge leads to.
+    pub fn entry(mut self, name: &str) -> Self {
+        self.add_node(name);
+        self.entries.push(name.to_string());
+        self
+    }
+
+    pub fn edge(self, from: &str, to: &str) -> Self {
+        self.conditional_edge(from, to, EdgeCondition::always())
+    }
+
+    pub fn conditional_edge(mut self, from: &str, to: &str, condition: EdgeCondition) -> Self {
+        self.add_node(from);
+        self.add_node(to);
+        self.edges.push((from.to_string(), to.to_string(), condition));
+        self
+    }
+
+    /// Makes the node wait for all its incoming edges, see `Join::All`.
+    pub fn join(mut self, name: &str) -> Self {
+        self.add_node(name);
+        self.joins.push(name.to_string());
+        self
+    }
+
+    /// Allows cycles through the node. Edges back into it run its loop
+    /// again at most `max_iterations` times; after that they do not fire.
+    pub fn loop_node(mut self, name: &str, max_iterations: usize) -> Self {
+        self.add_node(name);
+        self.loops.push((name.to_string(), max_iterations));
+        self
+    }
+
+    fn add_node(&mut self, name: &str) {
+        if !self.nodes.iter().any(|node| node == name) {
+            self.nodes.push(name.to_string());
+        }
+    }
+
+    pub fn build(self) -> Result<Graph, AgentError> {
+        let index: HashMap<&str, usize> = self.nodes.iter().enumerate().map(|(i, name)| (name.as_str(), i)).collect();
+        let mut nodes: Vec<Node> = self
+            .nodes
+            .iter()
+            .map(|name| Node {
+                name: name.clone(),
+                entry: self.entries.contains(name),
+                join: if self.joins.contains(name) { Join::All } else { Join::Any },
+                max_iterations: self.loops.iter().find(|(node, _)| node == name).map(|(_, max)| *max),
+            })
+            .collect();
+        if nodes.is_empty() {
+            return Err(AgentError::ConfigError("Graph has no nodes".to_string()));
+        }
+        let mut graph = Graph {
+            edges: self
+                .edges
+                .iter()
+                .map(|(from, to, condition)| Edge {
+                    from: index[from.as_str()],
+                    to: index[to.as_str()],
+                    condition: condition.clone(),
+                    back: false,
+                })
+                .collect(),
+            nodes: Vec::new(),
+        };
+
+        // An edge into a loop node from a node the loop node reaches closes
+        // the loop; every other cycle is an error.
+        let mut reach = Vec::new();
+        for start in 0..nodes.len() {
+            reach.push(all_reachable(&graph.edges, start));
+        }
+        for edge in &mut graph.edges {
+            edge.back = nodes[edge.to].max_iterations.is_some() && reach[edge.to].contains(&edge.from);
+        }
+        if let Some(cycle) = find_cycle(nodes.len(), &graph.edges) {
+            let names: Vec<&str> = cycle.iter().map(|node| nodes[*node].name.as_str()).collect();
+            return Err(AgentError::ConfigError(format!(
+                "Graph has a cycle through {}; mark one of its nodes as a loop",
+                names.join(" -> ")
+            )));
+        }
+
+        if !nodes.iter().any(|node| node.entry) {
+            for (i, node) in nodes.iter_mut().enumerate() {
+                node.entry = !graph.edges.iter().any(|edge| edge.to == i && !edge.back);
+            }
+        }
+        graph.nodes = nodes;
+        let mut reachable = HashSet::new();
+        for entry in graph.nodes.iter().enumerate().filter(|(_, node)| node.entry).map(|(i, _)| i) {
+            reachable.extend(all_reachable(&graph.edges, entry));
+        }
+        if let Some(node) = graph.nodes.iter().enumerate().find(|(i, _)| !reachable.contains(i)).map(|(_, node)| node) {
+            return Err(AgentError::ConfigError(format!(
+                "Graph node '{}' cannot be reached from an entry node",
+                node.name
+            )));
+        }
+        Ok(graph)
+    }
+}
+
+fn all_reachable(edges: &[Edge], start: usize) -> HashSet<usize> {
+    let mut seen = HashSet::from([start]);
+    let mut stack = vec![start];
+    while let Some(node) = stack.pop() {
+        for edge in edges.iter().filter(|edge| edge.from == node) {
+            if seen.insert(edge.to) {
+                stack.push(edge.to);
+            }
+        }
+    }
+    seen
+}
+
+/// A cycle over forward edges, as the nodes along it.
+fn find_cycle(len: usize, edges: &[Edge]) -> Option<Vec<usize>> {
+    fn visit(node: usize, edges: &[Edge], state: &mut [u8], path: &mut Vec<usize>) -> Option<Vec<usize>> {
+        state[node] = 1;
+        path.push(node);
+        for edge in edges.iter().filter(|edge| edge.from == node && !edge.back) {
+            match state[edge.to] {
+                0 => {
+                    if let Some(cycle) = visit(edge.to, edges, state, path) {
+                        return Some(cycle);
+                    }
+                }
+                1 => {
+                    let start = path.iter().position(|n| *n == edge.to).unwrap_or(0);
+                    let mut cycle = path[start..].to_vec();
+                    cycle.push(edge.to);
+                    return Some(cycle);
+                }
+                _ => {}
+            }
+        }
+        path.pop();
+        state[node] = 2;
+        None
+    }
+
+    let mut state = vec![0u8; len];
+    (0..len).find_map(|node| if state[node] == 0 { visit(node, edges, &mut state, &mut Vec::new()) } else { None })
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Pending,
+    Done,
+    Skipped,
+}
+
+/// Progress of one run through the graph.
+struct Walk<'a> {
+    graph: &'a Graph,
+    status: Vec<Status>,
+    /// Whether each edge fired, once decided.
+    fired: Vec<Option<bool>>,
+    runs: Vec<usize>,
+    iterations: Vec<usize>,
+    reentered: Vec<bool>,
+}
+
+impl<'a> Walk<'a> {
+    fn new(graph: &'a Graph) -> Self {
+        let len = graph.nodes.len();
+        Walk {
+            graph,
+            status: vec![Status::Pending; len],
+            fired: vec![None; graph.edges.len()],
+            runs: vec![0; len],
+            iterations: vec![0; len],
+            reentered: vec![false; len],
+        }
+    }
+
+    fn incoming(&self, node: usize) -> impl Iterator<Item = usize> + '_ {
+        (0..self.graph.edges.len()).filter(move |i| self.graph.edges[*i].to == node && !self.graph.edges[*i].back)
+    }
+
+    /// Entry nodes are ready until they first run.
+    fn starts_here(&self, node: usize) -> bool {
+        self.graph.nodes[node].entry && self.runs[node] == 0
+    }
+
+    fn is_ready(&self, node: usize) -> bool {
+        if self.status[node] != Status::Pending {
+            return false;
+        }
+        if self.reentered[node] || self.starts_here(node) {
+            return true;
+        }
+        let incoming: Vec<Option<bool>> = self.incoming(node).map(|i| self.fired[i]).collect();
+        match self.graph.nodes[node].join {
+            Join::Any => incoming.contains(&Some(true)),
+            Join::All => incoming.iter().all(Option::is_some) && incoming.contains(&Some(true)),
+        }
+    }
+
+    fn ready(&self) -> Vec<usize> {
+        (0..self.graph.nodes.len()).filter(|node| self.is_ready(*node)).collect()
+    }
+
+    fn complete(&mut self, node: usize, state: &HashMap<String, serde_json::Value>, last_event: Option<&Event>) {
+        self.status[node] = Status::Done;
+        self.runs[node] += 1;
+        self.reentered[node] = false;
+
+        let outgoing: Vec<usize> = (0..self.graph.edges.len()).filter(|i| self.graph.edges[*i].from == node).collect();
+        let mut any_fired = false;
+        for &i in outgoing.iter().filter(|i| !self.graph.edges[**i].condition.is_otherwise()) {
+            let fired = self.graph.edges[i].condition.holds(state, last_event);
+            self.fired[i] = Some(fired);
+            any_fired |= fired;
+        }
+        for &i in outgoing.iter().filter(|i| self.graph.edges[**i].condition.is_otherwise()) {
+            self.fired[i] = Some(!any_fired);
+        }
+
+        for i in outgoing {
+            let edge = &self.graph.edges[i];
+            if !edge.back || self.fired[i] != Some(true) {
+                continue;
+            }
+            let target = edge.to;
+            let max_iterations = self.graph.nodes[target].max_iterations.unwrap_or(0);
+            if self.iterations[target] >= max_iterations {
+                self.fired[i] = Some(false);
+                continue;
+            }
+            self.iterations[target] += 1;
+            self.restart_loop(target, node);
+        }
+        self.skip_unreachable();
+    }
+
+    /// Makes the body of the loop from `head` back to `tail`, and whatever
+    /// it led to that has not run, pending again.
+    fn restart_loop(&mut self, head: usize, tail: usize) {
+        let mut body: HashSet<usize> = self.graph.reachable_from(head).intersection(&self.graph.reaching(tail)).copied().collect();
+        body.extend([head, tail]);
+        let mut reset = body.clone();
+        for node in &body {
+            reset.extend(self.graph.reachable_from(*node).into_iter().filter(|n| self.status[*n] != Status::Done));
+        }
+        for node in &reset {
+            self.status[*node] = Status::Pending;
+        }
+        for (i, edge) in self.graph.edges.iter().enumerate() {
+            if reset.contains(&edge.from) && !edge.back {
+                self.fired[i] = None;
+            }
+        }
+        self.reentered[head] = true;
+    }
+
+    fn skip_unreachable(&mut self) {
+        loop {
+            let skipped: Vec<usize> = (0..self.graph.nodes.len())
+                .filter(|node| {
+                    let node = *node;
+                    let incoming: Vec<Option<bool>> = self.incoming(node).map(|i| self.fired[i]).collect();
+                    self.status[node] == Status::Pending
+                        && !self.reentered[node]
+                        && !self.starts_here(node)
+                        && !incoming.is_empty()
+                        && incoming.iter().all(|fired| *fired == Some(false))
+                })
+                .collect();
+            if skipped.is_empty() {
+                return;
+            }
+            for node in skipped {
+                self.status[node] = Status::Skipped;
+                for (i, edge) in self.graph.edges.iter().enumerate() {
+                    if edge.from == node {
+                        self.fired[i] = Some(false);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Runs its sub-agents along a `Graph`.
+#[derive(Clone, Agent)]
+pub struct GraphAgent {
+    #[agent(required)]
+    graph: Graph,
+}
+
+impl GraphAgent {
+    pub fn graph(&self) -> &Graph {
+        &self.graph
+    }
+}
+
+#[async_trait]
+impl Agent for GraphAgent {
+    async fn run_async_impl(&self, state: &BaseAgentState, context: &InvocationContext) -> Result<Vec<Event>, AgentError> {
+        let supervisor = &state.supervisor;
+        let children = self
+            .graph
+            .nodes
+            .iter()
+            .map(|node| {
+                supervisor.position(&node.name).ok_or_else(|| {
+                    AgentError::ConfigError(format!("Graph node '{}' is not a sub-agent of {}", node.name, state.base.name()))
+                })
+            })
+            .collect::<Result<Vec<usize>, AgentError>>()?;
+
+        let mut context = context.clone();
+        let mut events = Vec::new();
+        let mut walk = Walk::new(&self.graph);
+        loop {
+            let ready = walk.ready();
+            if ready.is_empty() {
+                break;
+            }
+            let mut runs = JoinSet::new();
+            let mut positions = HashMap::new();
+            for (position, node) in ready.iter().enumerate() {
+                let supervisor = supervisor.clone();
+                let context = context.clone();
+                let child = children[*node];
+                let run = runs.spawn(async move { supervisor.run_child(child, &context).await });
+                positions.insert(run.id(), position);
+            }
+            // A failing node fails the walk at once; dropping `runs` aborts
+            // the nodes still running next to it.
+            let mut results = vec![Vec::new(); ready.len()];
+            while let Some(result) = runs.join_next_with_id().await {
+                let (id, node_events) = result.map_err(|e| AgentError::AgentFailed(e.to_string()))?;
+                results[positions[&id]] = node_events?;
+            }
+            for (node, node_events) in ready.into_iter().zip(results) {
+                for event in &node_events {
+                    context.session_mut().append_event(event.clone());
+                }
+                walk.complete(node, context.session().state(), node_events.last());
+                events.extend(node_events);
+            }
+            if context.end_invocation() {
+                break;
+            }
+        }
+        Ok(events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn names(graph: &Graph, nodes: &[usize]) -> Vec<String> {
+        nodes.iter().map(|node| graph.nodes[*node].name.clone()).collect()
+    }
+
+    fn node(graph: &Graph, name: &str) -> usize {
+        graph.nodes.iter().position(|node| node.name == name).unwrap()
+    }
+
+    /// Completes every ready node, batch by batch, and returns the batches.
+    fn walk_through(graph: &Graph, state: &HashMap<String, serde_json::Value>) -> Vec<Vec<String>> {
+        let mut walk = Walk::new(graph);
+        let mut batches = Vec::new();
+        loop {
+            let ready = walk.ready();
+            if ready.is_empty() {
+                return batches;
+            }
+            batches.push(names(graph, &ready));
+            for node in ready {
+                walk.complete(node, state, None);
+            }
+        }
+    }
+
+    fn diamond() -> GraphBuilder {
+        Graph::builder().edge("split", "left").edge("split", "right").edge("left", "merge").edge("right", "merge")
+    }
+
+    #[test]
+    fn join_all_waits_for_every_incoming_edge() {
+        let state = HashMap::new();
+        let graph = diamond().join("merge").build().unwrap();
+        let mut walk = Walk::new(&graph);
+        walk.complete(node(&graph, "split"), &state, None);
+        walk.complete(node(&graph, "left"), &state, None);
+        assert_eq!(names(&graph, &walk.ready()), ["right"]);
+        walk.complete(node(&graph, "right"), &state, None);
+        assert_eq!(names(&graph, &walk.ready()), ["merge"]);
+
+        let graph = diamond().build().unwrap();
+        let mut walk = Walk::new(&graph);
+        walk.complete(node(&graph, "split"), &state, None);
+        walk.complete(node(&graph, "left"), &state, None);
+        assert_eq!(names(&graph, &walk.ready()), ["right", "merge"]);
+    }
+
+    #[test]
+    fn nodes_no_edge_leads_to_are_skipped() {
+        let graph = Graph::builder()
+            .conditional_edge("check", "approve", EdgeCondition::state_equals("ok", json!(true)))
+            .conditional_edge("check", "reject", EdgeCondition::otherwise())
+            .edge("approve", "notify")
+            .edge("reject", "log")
+            .build()
+            .unwrap();
+
+        let state = HashMap::from([("ok".to_string(), json!(true))]);
+        assert_eq!(walk_through(&graph, &state), [vec!["check"], vec!["approve"], vec!["notify"]]);
+        assert_eq!(walk_through(&graph, &HashMap::new()), [vec!["check"], vec!["reject"], vec!["log"]]);
+
+        let mut walk = Walk::new(&graph);
+        walk.complete(node(&graph, "check"), &HashMap::new(), None);
+        assert!(walk.status[node(&graph, "approve")] == Status::Skipped);
+        assert!(walk.status[node(&graph, "notify")] == Status::Skipped);
+    }
+
+    #[test]
+    fn a_join_runs_when_one_of_its_branches_is_skipped() {
+        let graph = Graph::builder()
+            .edge("split", "left")
+            .conditional_edge("split", "right", EdgeCondition::state_equals("both", json!(true)))
+            .edge("left", "merge")
+            .edge("right", "merge")
+            .join("merge")
+            .build()
+            .unwrap();
+        assert_eq!(walk_through(&graph, &HashMap::new()), [vec!["split"], vec!["left"], vec!["merge"]]);
+    }
+
+    #[test]
+    fn edges_back_into_a_loop_rerun_its_body_up_to_the_limit() {
+        let graph = Graph::builder()
+            .edge("start", "draft")
+            .edge("draft", "review")
+            .edge("review", "draft")
+            .edge("review", "publish")
+            .loop_node("draft", 2)
+            .build()
+            .unwrap();
+        let batches = walk_through(&graph, &HashMap::new());
+        assert_eq!(
+            batches,
+            [
+                vec!["start"],
+                vec!["draft"],
+                vec!["review"],
+                vec!["draft"],
+                vec!["review"],
+                vec!["draft"],
+                vec!["review"],
+                vec!["publish"],
+            ]
+        );
+    }
+
+    #[test]
+    fn a_loop_ends_when_its_back_edge_does_not_fire() {
+        let graph = Graph::builder()
+            .edge("draft", "review")
+            .conditional_edge("review", "draft", EdgeCondition::state_equals("approved", json!(false)))
+            .conditional_edge("review", "publish", EdgeCondition::otherwise())
+            .loop_node("draft", 5)
+            .build()
+            .unwrap();
+        let state = HashMap::from([("approved".to_string(), json!(false))]);
+        let mut walk = Walk::new(&graph);
+        walk.complete(node(&graph, "draft"), &state, None);
+        walk.complete(node(&graph, "review"), &state, None);
+        assert_eq!(names(&graph, &walk.ready()), ["draft"]);
+        walk.complete(node(&graph, "draft"), &state, None);
+        let state = HashMap::from([("approved".to_string(), json!(true))]);
+        walk.complete(node(&graph, "review"), &state, None);
+        assert_eq!(names(&graph, &walk.ready()), ["publish"]);
+    }
+
+    #[test]
+    fn cycles_need_a_loop_node() {
+        let cycle = Graph::builder().edge("start", "a").edge("a", "b").edge("b", "a");
+        let Err(AgentError::ConfigError(message)) = cycle.clone().build() else {
+            panic!("a cycle without a loop node was accepted");
+        };
+        assert!(message.contains("a -> b -> a"), "{}", message);
+        assert!(cycle.loop_node("a", 1).build().is_ok());
+
+        let edges = |pairs: &[(usize, usize)]| -> Vec<Edge> {
+            pairs
+                .iter()
+                .map(|(from, to)| Edge {
+                    from: *from,
+                    to: *to,
+                    condition: EdgeCondition::always(),
+                    back: false,
+                })
+                .collect()
+        };
+        assert_eq!(find_cycle(3, &edges(&[(0, 1), (1, 2), (2, 1)])), Some(vec![1, 2, 1]));
+        assert_eq!(find_cycle(3, &edges(&[(0, 1), (0, 2), (1, 2)])), None);
+        let mut looped = edges(&[(0, 1), (1, 2), (2, 1)]);
+        looped[2].back = true;
+        assert_eq!(find_cycle(3, &looped), None);
+    }
+
+    #[test]
+    fn dot_export_marks_entries_joins_loops_and_back_edges() {
+        let graph = Graph::builder()
+            .entry("start")
+            .edge("start", "draft")
+            .conditional_edge("draft", "review", EdgeCondition::state_equals("ready", json!(true)))
+            .edge("review", "draft")
+            .edge("review", "publish")
+            .loop_node("draft", 3)
+            .join("publish")
+            .build()
+            .unwrap();
+        let dot = graph.to_dot("my \"flow\"");
+        assert!(dot.starts_with("digraph \"my \\\"flow\\\"\" {\n"), "{}", dot);
+        assert!(dot.contains("\"start\" [shape=box, label=\"start\", style=bold];"), "{}", dot);
+        assert!(dot.contains("\"draft\" [shape=box, label=\"draft\\n(loop, max 3)\", peripheries=2];"), "{}", dot);
+        assert!(dot.contains("\"publish\" [shape=box, label=\"publish\\n(join all)\"];"), "{}", dot);
+        assert!(dot.contains("\"draft\" -> \"review\" [label=\"ready == true\"];"), "{}", dot);
+        assert!(dot.contains("\"review\" -> \"draft\" [style=dashed];"), "{}", dot);
+        assert!(dot.contains("\"review\" -> \"publish\";"), "{}", dot);
+        assert!(dot.ends_with("}\n"));
+    }
+}
@@ -0,0 +1,144 @@
+//! Templating for agent instructions. `{key}` is replaced by the state value
+//! of `key`, `{key?}` renders empty when the key is missing and
+//! `{artifact.name}` inlines the text of an artifact. Braces around anything
+//! that is not a valid state key, such as JSON examples, are left untouched.
+
+use crate::common::AgentError;
+use crate::invocation_context::InvocationContext;
+use crate::state::{APP_PREFIX, TEMP_PREFIX, USER_PREFIX};
+
+const ARTIFACT_PREFIX: &str = "artifact.";
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn is_state_key(key: &str) -> bool {
+    let name = [APP_PREFIX, USER_PREFIX, TEMP_PREFIX]
+        .iter()
+        .find_map(|prefix| key.strip_prefix(prefix))
+        .unwrap_or(key);
+    is_identifier(name)
+}
+
+async fn resolve(placeholder: &str, context: &InvocationContext) -> Result<Option<String>, AgentError> {
+    let (name, optional) = match placeholder.strip_suffix('?') {
+        Some(name) => (name, true),
+        None => (placeholder, false),
+    };
+
+    if let Some(filename) = name.strip_prefix(ARTIFACT_PREFIX) {
+        let artifact = context
+            .artifact_service()
+            .load_artifact(context.app_name(), context.user_id(), context.session().id(), filename, None)
+            .await?;
+        return match artifact.and_then(|artifact| artifact.text) {
+            Some(text) => Ok(Some(text)),
+            None if optional => Ok(Some(String::new())),
+            None => Err(AgentError::InstructionError(format!(
+                "Instruction references text artifact '{}', which was not found",
+                filename
+            ))),
+        };
+    }
+
+    if !is_state_key(name) {
+        return Ok(None);
+    }
+    match context.session().state().get(name) {
+        Some(serde_json::Value::String(value)) => Ok(Some(value.clone())),
+        Some(value) => Ok(Some(value.to_string())),
+        None if optional => Ok(Some(String::new())),
+        None => Err(AgentError::InstructionError(format!(
+            "Instruction references state key '{}', which is not in the session state",
+            name
+        ))),
+    }
+}
+
+/// Fills the placeholders of `template` from the session state and artifacts
+/// of the invocation.
+pub async fn inject_session_state(template: &str, context: &InvocationContext) -> Result<String, AgentError> {
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let end = match rest[start + 1..].find(['{', '}']) {
+            Some(offset) if rest.as_bytes()[start + 1 + offset] == b'}' => start + 1 + offset,
+            Some(offset) => {
+                result.push_str(&rest[..start + 1 + offset]);
+                rest = &rest[start + 1 + offset..];
+                continue;
+            }
+            None => break,
+        };
+        result.push_str(&rest[..start]);
+        let placeholder = rest[start + 1..end].trim();
+        match resolve(placeholder, context).await? {
+            Some(value) => result.push_str(&value),
+            None => result.push_str(&rest[start..=end]),
+        }
+        rest = &rest[end + 1..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::Part;
+    use crate::testing::{context, unique_name, Reply};
+    use serde_json::json;
+
+    async fn context_with_state(state: serde_json::Value) -> InvocationContext {
+        let (agent, _) = Reply::spawn_with(Reply::builder("done".to_string()).name(unique_name("agent"))).await;
+        let mut context = context(&agent).await;
+        for (key, value) in state.as_object().unwrap() {
+            context.session_mut().state_mut().insert(key.clone(), value.clone());
+        }
+        context
+    }
+
+    #[tokio::test]
+    async fn state_keys_are_filled_in() {
+        let context = context_with_state(json!({ "topic": "rust", "count": 3, "user:name": "Ada" })).await;
+        let instruction = inject_session_state("Write {count} posts about { topic } for {user:name}.", &context).await.unwrap();
+        assert_eq!(instruction, "Write 3 posts about rust for Ada.");
+    }
+
+    #[tokio::test]
+    async fn optional_keys_render_empty_and_required_ones_fail() {
+        let context = context_with_state(json!({})).await;
+        assert_eq!(inject_session_state("Notes: {notes?}.", &context).await.unwrap(), "Notes: .");
+        let Err(AgentError::InstructionError(message)) = inject_session_state("Notes: {notes}.", &context).await else {
+            panic!("a missing state key was filled in");
+        };
+        assert!(message.contains("'notes'"), "{}", message);
+    }
+
+    #[tokio::test]
+    async fn text_artifacts_are_inlined() {
+        let context = context_with_state(json!({})).await;
+        context
+            .artifact_service()
+            .save_artifact(context.app_name(), context.user_id(), context.session().id(), "style.md", Part::from_text("Be brief."))
+            .await
+            .unwrap();
+        let instruction = inject_session_state("Style: {artifact.style.md}{artifact.extra.md?}", &context).await.unwrap();
+        assert_eq!(instruction, "Style: Be brief.");
+        let Err(AgentError::InstructionError(message)) = inject_session_state("{artifact.missing.md}", &context).await else {
+            panic!("a missing artifact was filled in");
+        };
+        assert!(message.contains("'missing.md'"), "{}", message);
+    }
+
+    #[tokio::test]
+    async fn braces_around_other_text_are_kept() {
+        let context = context_with_state(json!({ "name": "Ada" })).await;
+        let template = r#"Reply as {"greeting": "hi {name}"} or { not a key } or {{name}}, then {"#;
+        let instruction = inject_session_state(template, &context).await.unwrap();
+        assert_eq!(instruction, r#"Reply as {"greeting": "hi Ada"} or { not a key } or {Ada}, then {"#);
+    }
+}
@@ -1,12 +1,23 @@
-use crate::common::{AgentError, BaseArtifactService, BaseSessionService, Content, LiveRequestQueue, RunConfig, Session};
+use crate::artifact_service::BaseArtifactService;
+use crate::common::{AgentError, Content, LiveRequestQueue, Session, ToolDecision};
+use crate::memory_service::BaseMemoryService;
+use crate::plugin::PluginManager;
+use crate::run_config::RunConfig;
+use crate::session_service::BaseSessionService;
+use crate::common::Event;
 use ractor::ActorCell;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+use tracing::Span;
 use uuid::Uuid;
 
 #[derive(Clone, Debug)]
 pub struct InvocationContext {
-    session_service: BaseSessionService,
-    artifact_service: BaseArtifactService,
+    session_service: Arc<dyn BaseSessionService>,
+    artifact_service: Arc<dyn BaseArtifactService>,
+    memory_service: Option<Arc<dyn BaseMemoryService>>,
     live_request_queue: Option<LiveRequestQueue>,
     branch: Option<String>,
     invocation_id: String,
@@ -14,14 +25,19 @@ pub struct InvocationContext {
     session: Session,
     user_content: Option<Content>,
     run_config: RunConfig,
-    end_invocation: bool,
+    end_invocation: Arc<AtomicBool>,
     invocation_cost_manager: InvocationCostManager,
+    plugin_manager: PluginManager,
+    span: Span,
+    event_sink: Option<mpsc::UnboundedSender<Event>>,
+    paused_calls: Arc<Mutex<Option<PausedCalls>>>,
+    cancellation: CancellationToken,
 }
 
 impl InvocationContext {
     pub fn create(
-        session_service: BaseSessionService,
-        artifact_service: BaseArtifactService,
+        session_service: Arc<dyn BaseSessionService>,
+        artifact_service: Arc<dyn BaseArtifactService>,
         invocation_id: String,
         agent: Arc<ActorCell>,
         session: Session,
@@ -31,6 +47,7 @@ impl InvocationContext {
         InvocationContext {
             session_service,
             artifact_service,
+            memory_service: None,
             live_request_queue: None,
             branch: None,
             invocation_id,
@@ -38,8 +55,13 @@ impl InvocationContext {
             session,
             user_content,
             run_config,
-            end_invocation: false,
-            invocation_cost_manager: InvocationCostManager { number_of_llm_calls: 0 },
+            end_invocation: Arc::new(AtomicBool::new(false)),
+            invocation_cost_manager: InvocationCostManager::default(),
+            plugin_manager: PluginManager::default(),
+            span: Span::none(),
+            event_sink: None,
+            paused_calls: Arc::default(),
+            cancellation: CancellationToken::new(),
         }
     }
 
@@ -47,6 +69,7 @@ impl InvocationContext {
         InvocationContext {
             session_service: other.session_service.clone(),
             artifact_service: other.artifact_service.clone(),
+            memory_service: other.memory_service.clone(),
             live_request_queue: other.live_request_queue.clone(),
             branch: other.branch.clone(),
             invocation_id: other.invocation_id.clone(),
@@ -54,23 +77,40 @@ impl InvocationContext {
             session: other.session.clone(),
             user_content: other.user_content.clone(),
             run_config: other.run_config.clone(),
-            end_invocation: other.end_invocation,
+            end_invocation: other.end_invocation.clone(),
             invocation_cost_manager: other.invocation_cost_manager.clone(),
+            plugin_manager: other.plugin_manager.clone(),
+            span: other.span.clone(),
+            event_sink: other.event_sink.clone(),
+            paused_calls: other.paused_calls.clone(),
+            cancellation: other.cancellation.clone(),
         }
     }
 
-    pub fn session_service(&self) -> &BaseSessionService {
+    pub fn session_service(&self) -> &Arc<dyn BaseSessionService> {
         &self.session_service
     }
 
-    pub fn artifact_service(&self) -> &BaseArtifactService {
+    pub fn artifact_service(&self) -> &Arc<dyn BaseArtifactService> {
         &self.artifact_service
     }
 
+    pub fn memory_service(&self) -> Option<&Arc<dyn BaseMemoryService>> {
+        self.memory_service.as_ref()
+    }
+
+    pub fn set_memory_service(&mut self, memory_service: Option<Arc<dyn BaseMemoryService>>) {
+        self.memory_service = memory_service;
+    }
+
     pub fn live_request_queue(&self) -> Option<&LiveRequestQueue> {
         self.live_request_queue.as_ref()
     }
 
+    pub fn set_live_request_queue(&mut self, live_request_queue: Option<LiveRequestQueue>) {
+        self.live_request_queue = live_request_queue;
+    }
+
     pub fn invocation_id(&self) -> &str {
         &self.invocation_id
     }
@@ -95,16 +135,113 @@ impl InvocationContext {
         &self.session
     }
 
+    pub fn session_mut(&mut self) -> &mut Session {
+        &mut self.session
+    }
+
+    pub fn set_session(&mut self, session: Session) {
+        self.session = session;
+    }
+
     pub fn user_content(&self) -> Option<&Content> {
         self.user_content.as_ref()
     }
 
+    pub fn set_user_content(&mut self, user_content: Option<Content>) {
+        self.user_content = user_content;
+    }
+
     pub fn run_config(&self) -> &RunConfig {
         &self.run_config
     }
 
     pub fn end_invocation(&self) -> bool {
-        self.end_invocation
+        self.end_invocation.load(Ordering::SeqCst)
+    }
+
+    /// Ends the whole invocation. The flag is shared by every copy of this
+    /// context, so agents up the tree stop once their current step returns.
+    pub fn set_end_invocation(&self, end_invocation: bool) {
+        self.end_invocation.store(end_invocation, Ordering::SeqCst);
+    }
+
+    pub fn plugin_manager(&self) -> &PluginManager {
+        &self.plugin_manager
+    }
+
+    pub fn set_plugin_manager(&mut self, plugin_manager: PluginManager) {
+        self.plugin_manager = plugin_manager;
+    }
+
+    /// The span of the innermost agent (or the invocation) running with this
+    /// context, used as the parent of spans opened further down the tree.
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
+    pub fn set_span(&mut self, span: Span) {
+        self.span = span;
+    }
+
+    /// Cancelled when the work running with this context must stop, such as
+    /// a live turn the user interrupted. Runs, sub-agent runs and tool calls
+    /// check it, and a cancelled context emits no more events.
+    pub fn cancellation(&self) -> &CancellationToken {
+        &self.cancellation
+    }
+
+    pub fn set_cancellation(&mut self, cancellation: CancellationToken) {
+        self.cancellation = cancellation;
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancellation.is_cancelled()
+    }
+
+    pub fn set_event_sink(&mut self, event_sink: Option<mpsc::UnboundedSender<Event>>) {
+        self.event_sink = event_sink;
+    }
+
+    /// Publishes an event to the runner as soon as it is produced, so callers
+    /// can stream it before the agent finishes. The event must still be
+    /// returned from the agent as usual.
+    pub fn emit_event(&self, event: &Event) {
+        if self.is_cancelled() {
+            return;
+        }
+        if let Some(event_sink) = &self.event_sink {
+            let _ = event_sink.send(event.clone());
+        }
+    }
+
+    /// Resumes a paused invocation from the root agent: the agent that paused
+    /// at `paused_branch` takes `decisions` with `take_tool_decisions`, and
+    /// the workflow agents on the way to it skip their other sub-agents,
+    /// which finished before the pause. Shared by every copy of this context.
+    pub fn set_tool_decisions(&self, paused_branch: String, decisions: Vec<ToolDecision>) {
+        *self.paused_calls.lock().unwrap() = Some(PausedCalls { paused_branch, decisions });
+    }
+
+    /// The sub-agent on the way to the agent that paused, while the
+    /// invocation is resumed and that agent has not taken its decisions yet.
+    pub fn resumed_child(&self) -> Option<String> {
+        let paused_calls = self.paused_calls.lock().unwrap();
+        let paused_branch = &paused_calls.as_ref()?.paused_branch;
+        let rest = match self.branch.as_deref().filter(|branch| !branch.is_empty()) {
+            Some(branch) => paused_branch.strip_prefix(branch)?.strip_prefix('.')?,
+            None => paused_branch.as_str(),
+        };
+        rest.split('.').next().map(|child| child.to_string())
+    }
+
+    /// The user's decisions on the calls held by the agent running with this
+    /// context, if it is the one that paused. They are handed out once.
+    pub fn take_tool_decisions(&self) -> Option<Vec<ToolDecision>> {
+        let mut paused_calls = self.paused_calls.lock().unwrap();
+        if paused_calls.as_ref()?.paused_branch.as_str() != self.branch.as_deref().unwrap_or_default() {
+            return None;
+        }
+        paused_calls.take().map(|paused_calls| paused_calls.decisions)
     }
 
     pub fn app_name(&self) -> &str {
@@ -124,15 +261,24 @@ impl InvocationContext {
     }
 }
 
-#[derive(Clone, Debug)]
+/// The decisions a resumed invocation brings to the agent that paused it.
+#[derive(Debug)]
+struct PausedCalls {
+    paused_branch: String,
+    decisions: Vec<ToolDecision>,
+}
+
+/// Counts the LLM calls of the whole invocation; copies of the context share
+/// the counter.
+#[derive(Clone, Debug, Default)]
 struct InvocationCostManager {
-    number_of_llm_calls: i32,
+    number_of_llm_calls: Arc<AtomicI32>,
 }
 
 impl InvocationCostManager {
     fn increment_and_enforce_llm_calls_limit(&mut self, run_config: &RunConfig) -> Result<(), AgentError> {
-        self.number_of_llm_calls += 1;
-        if run_config.max_llm_calls() > 0 && self.number_of_llm_calls > run_config.max_llm_calls() {
+        let number_of_llm_calls = self.number_of_llm_calls.fetch_add(1, Ordering::SeqCst) + 1;
+        if run_config.max_llm_calls() > 0 && number_of_llm_calls > run_config.max_llm_calls() {
             return Err(AgentError::LlmCallsLimitExceeded(
                 format!("Max number of LLM calls limit of {} exceeded", run_config.max_llm_calls())
             ));
@@ -0,0 +1,417 @@
+use crate::agent::Agent;
+use crate::base_agent::{BaseAgent, BaseAgentState};
+use crate::base_tool::BaseTool;
+use crate::agent_metrics;
+use crate::common::{AgentError, Content, Event, FunctionCall, FunctionResponse, Part, ToolConfirmation, ToolDecision};
+use crate::instructions;
+use crate::invocation_context::InvocationContext;
+use crate::models::{BaseLlm, FunctionDeclaration, LlmRequest};
+use crate::planner::Planner;
+use crate::schema::{self, OutputSchema};
+use crate::tool_context::ToolContext;
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::Arc;
+use async_trait::async_trait;
+use uuid::Uuid;
+
+/// Agent backed by a model. Each turn sends the templated instruction, the
+/// session history and the tool declarations to the model, runs the function
+/// calls it asks for and repeats until the model answers without calls.
+/// With an output schema the final answer must be JSON matching it; invalid
+/// answers are sent back with the validation errors up to
+/// `max_output_retries` times. The answer is stored under `output_key`.
+/// With a planner, a reply holding only thoughts is not an answer and the
+/// model is asked again, up to `max_thought_replies` times in a row. Calls
+/// to tools that require confirmation pause the invocation; when it is
+/// resumed the calls run as the user decided.
+#[derive(Clone, Agent)]
+pub struct LlmAgent {
+    #[agent(required)]
+    model: Arc<dyn BaseLlm>,
+    /// Instruction sent as the system prompt. Placeholders are filled from
+    /// the session state, see `instructions::inject_session_state`.
+    instruction: String,
+    tools: Vec<Arc<dyn BaseTool>>,
+    /// JSON Schema the final answer must match.
+    output_schema: Option<serde_json::Value>,
+    /// State key the final answer is written to, as parsed JSON when an
+    /// output schema is set and as a string otherwise.
+    output_key: Option<String>,
+    #[agent(default = 2)]
+    max_output_retries: usize,
+    #[agent(default = 3)]
+    max_thought_replies: usize,
+    /// Makes the model plan before it acts, see `planner`.
+    planner: Option<Arc<dyn Planner>>,
+}
+
+#[async_trait]
+impl Agent for LlmAgent {
+    async fn run_async_impl(&self, state: &BaseAgentState, context: &InvocationContext) -> Result<Vec<Event>, AgentError> {
+        let base = &state.base;
+        let mut context = context.clone();
+        let mut events = Vec::new();
+        let mut output_retries = 0;
+        let mut thought_replies = 0;
+        if let Some((content, decisions)) = self.confirmed_calls(base, &context)? {
+            let response_event = self.run_tools(base, &mut context, &content, &decisions).await?;
+            events.push(response_event);
+        }
+        loop {
+            let request = self.build_request(&context).await?;
+            let response = base.call_llm(self.model.as_ref(), request, &mut context).await?;
+            if let Some(error) = response.error_message {
+                return Err(AgentError::ModelError(error));
+            }
+            let mut content = response.content.unwrap_or_else(|| Content::new("model".to_string(), Vec::new()));
+            for function_call in content.parts.iter_mut().filter_map(|part| part.function_call.as_mut()) {
+                function_call.id.get_or_insert_with(|| format!("call-{}", Uuid::new_v4()));
+            }
+            let (content, state_delta) = match &self.planner {
+                Some(planner) => planner.process_response(&context, content),
+                None => (content, HashMap::new()),
+            };
+            let only_thoughts = content.text().trim().is_empty() && content.parts.iter().any(|part| part.thought.is_some());
+            if content.function_calls().is_empty() && !only_thoughts {
+                let output = match self.parse_output(&content) {
+                    Ok(output) => output,
+                    Err(errors) if output_retries < self.max_output_retries => {
+                        output_retries += 1;
+                        agent_metrics::record_retry(base.name(), "output_schema");
+                        let correction = Content::from_text(
+                            "user",
+                            &format!(
+                                "Your reply does not match the output schema:\n- {}\nReply again with only the corrected JSON.",
+                                errors.join("\n- ")
+                            ),
+                        );
+                        let mut rejected_event = self.event(base, &context, content, false);
+                        rejected_event.actions.state_delta().extend(state_delta);
+                        let correction_event = self.event(base, &context, correction, false);
+                        context.session_mut().append_event(rejected_event);
+                        context.session_mut().append_event(correction_event);
+                        continue;
+                    }
+                    Err(errors) => {
+                        return Err(AgentError::OutputValidationError(format!(
+                            "Agent {} did not produce valid output after {} retries: {}",
+                            base.name(),
+                            output_retries,
+                            errors.join("; ")
+                        )));
+                    }
+                };
+                let mut event = self.event(base, &context, content, true);
+                event.actions.state_delta().extend(state_delta);
+                if let Some(output_key) = &self.output_key {
+                    event.actions.state_delta().insert(output_key.clone(), output);
+                }
+                context.emit_event(&event);
+                events.push(event);
+                return Ok(events);
+            }
+
+            let mut call_event = self.event(base, &context, content.clone(), false);
+            call_event.actions.state_delta().extend(state_delta);
+            context.session_mut().append_event(call_event.clone());
+            context.emit_event(&call_event);
+            events.push(call_event);
+            if content.function_calls().is_empty() {
+                // The model only planned or reasoned; let it go on.
+                thought_replies += 1;
+                if thought_replies > self.max_thought_replies {
+                    return Err(AgentError::AgentFailed(format!(
+                        "Agent {} replied with only thoughts {} times in a row without answering or calling a tool",
+                        base.name(),
+                        thought_replies
+                    )));
+                }
+                continue;
+            }
+            thought_replies = 0;
+
+            let held = self.held_calls(&content);
+            if !held.is_empty() {
+                let event = Event::builder()
+                    .invocation_id(context.invocation_id().to_string())
+                    .author(base.name().to_string())
+                    .branch(context.branch().map(|s| s.to_string()))
+                    .confirmation(Some(ToolConfirmation { function_calls: held }))
+                    .build();
+                context.emit_event(&event);
+                events.push(event);
+                context.set_end_invocation(true);
+                return Ok(events);
+            }
+
+            let response_event = self.run_tools(base, &mut context, &content, &[]).await?;
+            events.push(response_event);
+
+            if context.end_invocation() {
+                return Ok(events);
+            }
+        }
+    }
+}
+
+impl LlmAgent {
+    pub fn model(&self) -> &Arc<dyn BaseLlm> {
+        &self.model
+    }
+
+    pub fn instruction(&self) -> &str {
+        &self.instruction
+    }
+
+    pub fn tools(&self) -> &Vec<Arc<dyn BaseTool>> {
+        &self.tools
+    }
+
+    pub fn output_schema(&self) -> Option<&serde_json::Value> {
+        self.output_schema.as_ref()
+    }
+
+    pub fn output_key(&self) -> Option<&str> {
+        self.output_key.as_deref()
+    }
+
+    pub fn planner(&self) -> Option<&Arc<dyn Planner>> {
+        self.planner.as_ref()
+    }
+
+    pub async fn build_request(&self, context: &InvocationContext) -> Result<LlmRequest, AgentError> {
+        let mut instruction = instructions::inject_session_state(&self.instruction, context).await?;
+        if let Some(output_schema) = &self.output_schema {
+            if !instruction.is_empty() {
+                instruction.push_str("\n\n");
+            }
+            instruction.push_str(&format!(
+                "Reply with only a JSON value that matches this JSON Schema:\n{}",
+                output_schema
+            ));
+        }
+        if let Some(planning) = self.planner.as_ref().and_then(|planner| planner.instruction(context)) {
+            if !instruction.is_empty() {
+                instruction.push_str("\n\n");
+            }
+            instruction.push_str(&planning);
+        }
+        Ok(LlmRequest {
+            model: self.model.model().to_string(),
+            system_instruction: Some(instruction).filter(|instruction| !instruction.is_empty()),
+            contents: context
+                .session()
+                .events()
+                .iter()
+                .filter_map(|event| event.content.clone())
+                .collect(),
+            tools: self
+                .tools
+                .iter()
+                .map(|tool| FunctionDeclaration {
+                    name: tool.name().to_string(),
+                    description: tool.description().to_string(),
+                    parameters: tool.parameters(),
+                })
+                .collect(),
+            response_schema: self.output_schema.clone(),
+        })
+    }
+
+    fn event(&self, base: &BaseAgent, context: &InvocationContext, content: Content, final_response: bool) -> Event {
+        Event::builder()
+            .invocation_id(context.invocation_id().to_string())
+            .author(base.name().to_string())
+            .branch(context.branch().map(|s| s.to_string()))
+            .content(Some(content))
+            .final_response(final_response)
+            .build()
+    }
+
+    /// Parses and validates the final answer against the output schema. The
+    /// answer is kept as a string when there is no schema.
+    fn parse_output(&self, content: &Content) -> Result<serde_json::Value, Vec<String>> {
+        let output_schema = match &self.output_schema {
+            Some(output_schema) => output_schema,
+            None => return Ok(serde_json::Value::String(content.text())),
+        };
+        let value = schema::parse_json_output(&content.text()).map_err(|error| vec![error])?;
+        let errors = schema::validate(output_schema, &value);
+        if errors.is_empty() {
+            Ok(value)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// The calls in `content` to tools that require confirmation.
+    fn held_calls(&self, content: &Content) -> Vec<FunctionCall> {
+        content
+            .function_calls()
+            .into_iter()
+            .filter(|function_call| {
+                self.tools
+                    .iter()
+                    .any(|tool| tool.name() == function_call.name && tool.requires_confirmation())
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// When the invocation resumes after this agent paused for confirmation,
+    /// the held calls and the user's decisions on them. Fails when the calls
+    /// are missing from the session rather than drop the decisions.
+    fn confirmed_calls(
+        &self,
+        base: &BaseAgent,
+        context: &InvocationContext,
+    ) -> Result<Option<(Content, Vec<ToolDecision>)>, AgentError> {
+        let Some(decisions) = context.take_tool_decisions() else {
+            return Ok(None);
+        };
+        let content = context
+            .session()
+            .events()
+            .iter()
+            .rev()
+            .filter(|event| event.author == base.name() && event.invocation_id == context.invocation_id())
+            .find_map(|event| event.content.clone().filter(|content| !content.function_calls().is_empty()))
+            .ok_or_else(|| {
+                AgentError::AgentFailed(format!(
+                    "Agent {} was resumed, but the tool calls it paused on are not in the session",
+                    base.name()
+                ))
+            })?;
+        Ok(Some((content, decisions)))
+    }
+
+    /// Runs the calls in `content` and records their results.
+    async fn run_tools(
+        &self,
+        base: &BaseAgent,
+        context: &mut InvocationContext,
+        content: &Content,
+        decisions: &[ToolDecision],
+    ) -> Result<Event, AgentError> {
+        let response_content = self.run_function_calls(base, context, content, decisions).await?;
+        let state_delta = match &self.planner {
+            Some(planner) => planner.process_tool_results(context, &response_content),
+            None => HashMap::new(),
+        };
+        let mut response_event = self.event(base, context, response_content, false);
+        response_event.actions.state_delta().extend(state_delta);
+        context.session_mut().append_event(response_event.clone());
+        context.emit_event(&response_event);
+        Ok(response_event)
+    }
+
+    async fn run_function_calls(
+        &self,
+        base: &BaseAgent,
+        context: &InvocationContext,
+        content: &Content,
+        decisions: &[ToolDecision],
+    ) -> Result<Content, AgentError> {
+        let mut parts = Vec::new();
+        for function_call in content.function_calls() {
+            let decision = decisions
+                .iter()
+                .find(|decision| function_call.id.as_deref() == Some(decision.function_call_id.as_str()));
+            let response = match self.tools.iter().find(|tool| tool.name() == function_call.name) {
+                Some(_) if decision.is_some_and(|decision| !decision.approved) => {
+                    let reason = decision.and_then(|decision| decision.reason.as_deref());
+                    json!({ "error": format!("The user rejected this call{}", reason.map(|reason| format!(": {}", reason)).unwrap_or_default()) })
+                }
+                Some(tool) => {
+                    let args = decision
+                        .and_then(|decision| decision.args.clone())
+                        .unwrap_or_else(|| function_call.args.clone());
+                    let tool_context = ToolContext::new(context.clone(), function_call.id.clone());
+                    match base.run_tool(tool.clone(), args, tool_context).await {
+                        Ok(response) => response,
+                        Err(error) => json!({ "error": error.to_string() }),
+                    }
+                }
+                None => json!({ "error": format!("Tool '{}' is not available", function_call.name) }),
+            };
+            parts.push(Part::from_function_response(FunctionResponse {
+                id: function_call.id.clone(),
+                name: function_call.name.clone(),
+                response,
+            }));
+        }
+        Ok(Content::new("user".to_string(), parts))
+    }
+
+}
+
+impl LlmAgentBuilder {
+    pub fn tool(mut self, tool: Arc<dyn BaseTool>) -> Self {
+        self.tools.push(tool);
+        self
+    }
+
+    pub fn output_schema_type<T: OutputSchema>(self) -> Self {
+        self.output_schema(T::output_schema())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::Thought;
+    use crate::planner::ReActPlanner;
+    use crate::common::ToolDecision;
+    use crate::testing::{context, run, unique_name, ScriptedLlm};
+
+    fn reply(text: &str) -> Content {
+        Content::from_text("model", text)
+    }
+
+    #[tokio::test]
+    async fn thoughts_are_sent_back_until_the_answer() {
+        let model = ScriptedLlm::new(vec![reply("/*PLANNING*/ 1. add"), reply("/*FINAL_ANSWER*/ 4")]);
+        let agent = LlmAgent::builder(model.clone())
+            .planner(Arc::new(ReActPlanner::new()))
+            .name(unique_name("thinker"))
+            .spawn()
+            .await
+            .unwrap();
+        let events = run(&agent, "2 + 2?").await.unwrap();
+        assert_eq!(events.last().unwrap().content.as_ref().unwrap().text(), "4");
+
+        let requests = model.requests();
+        let plan = requests[1].contents.last().unwrap();
+        assert_eq!(plan.parts[0].thought, Some(Thought::Planning));
+        assert_eq!(plan.parts[0].text.as_deref(), Some("1. add"));
+    }
+
+    #[tokio::test]
+    async fn endless_thoughts_fail_the_run() {
+        let model = ScriptedLlm::new(vec![reply("/*PLANNING*/ 1. add"); 4]);
+        let agent = LlmAgent::builder(model.clone())
+            .planner(Arc::new(ReActPlanner::new()))
+            .max_thought_replies(2)
+            .name(unique_name("overthinker"))
+            .spawn()
+            .await
+            .unwrap();
+        let result = run(&agent, "2 + 2?").await;
+        assert!(matches!(result, Err(AgentError::AgentFailed(error)) if error.contains("only thoughts 3 times")));
+        assert_eq!(model.requests().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn decisions_without_their_paused_calls_fail_the_run() {
+        let model = ScriptedLlm::new(vec![reply("hello again")]);
+        let name = unique_name("resumed");
+        let agent = LlmAgent::builder(model.clone()).name(name.clone()).spawn().await.unwrap();
+        let context = context(&agent).await;
+        context.set_tool_decisions(name, vec![ToolDecision::approve("call-1")]);
+
+        let result = BaseAgent::run_sub_agent(&agent.get_cell(), context).await;
+        assert!(matches!(result, Err(AgentError::AgentFailed(error)) if error.contains("not in the session")));
+        assert!(model.requests().is_empty());
+    }
+}
@@ -0,0 +1,112 @@
+use crate::base_tool::BaseTool;
+use crate::common::AgentError;
+use crate::tool_context::ToolContext;
+use async_trait::async_trait;
+use serde_json::json;
+
+/// Searches the memory service of the current invocation for the `query`
+/// argument and returns the matching memories.
+#[derive(Clone, Debug, Default)]
+pub struct LoadMemoryTool;
+
+impl LoadMemoryTool {
+    pub fn new() -> Self {
+        LoadMemoryTool
+    }
+}
+
+#[async_trait]
+impl BaseTool for LoadMemoryTool {
+    fn name(&self) -> &str {
+        "load_memory"
+    }
+
+    fn description(&self) -> &str {
+        "Loads the memory for the current user from earlier conversations."
+    }
+
+    fn parameters(&self) -> Option<serde_json::Value> {
+        Some(json!({
+            "type": "object",
+            "properties": {
+                "query": { "type": "string" }
+            },
+            "required": ["query"]
+        }))
+    }
+
+    async fn run_async(&self, args: serde_json::Value, tool_context: ToolContext) -> Result<serde_json::Value, AgentError> {
+        let query = args
+            .get("query")
+            .and_then(|query| query.as_str())
+            .ok_or_else(|| AgentError::ToolExecutionFailed("load_memory requires a string 'query' argument".to_string()))?;
+        let context = tool_context.invocation_context();
+        let memory_service = context
+            .memory_service()
+            .ok_or_else(|| AgentError::ToolExecutionFailed("No memory service is available".to_string()))?;
+        let response = memory_service.search_memory(context.app_name(), context.user_id(), query).await?;
+        let memories: Vec<serde_json::Value> = response
+            .memories
+            .iter()
+            .map(|memory| {
+                json!({
+                    "author": memory.author,
+                    "text": memory.content.text(),
+                })
+            })
+            .collect();
+        Ok(json!({ "memories": memories }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::{Content, Event, Session};
+    use crate::memory_service::{BaseMemoryService, InMemoryMemoryService};
+    use crate::testing::{context, unique_name, Reply};
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    async fn tool_context(memory_service: Option<Arc<dyn BaseMemoryService>>) -> ToolContext {
+        let (agent, _) = Reply::spawn_with(Reply::builder("done".to_string()).name(unique_name("agent"))).await;
+        let mut context = context(&agent).await;
+        context.set_memory_service(memory_service);
+        ToolContext::new(context, Some("call-1".to_string()))
+    }
+
+    async fn remembering(text: &str) -> Arc<dyn BaseMemoryService> {
+        let memory = InMemoryMemoryService::new();
+        let mut session = Session::new("test".to_string(), "user".to_string(), "earlier".to_string(), HashMap::new());
+        session.append_event(
+            Event::builder()
+                .author("user".to_string())
+                .content(Some(Content::from_text("user", text)))
+                .build(),
+        );
+        memory.add_session_to_memory(&session).await.unwrap();
+        Arc::new(memory)
+    }
+
+    #[tokio::test]
+    async fn matching_memories_are_returned() {
+        let tool_context = tool_context(Some(remembering("My cat is called Tom.").await)).await;
+        let result = LoadMemoryTool::new().run_async(json!({ "query": "cat" }), tool_context).await.unwrap();
+        assert_eq!(result, json!({ "memories": [{ "author": "user", "text": "My cat is called Tom." }] }));
+    }
+
+    #[tokio::test]
+    async fn misses_return_no_memories() {
+        let tool_context = tool_context(Some(remembering("My cat is called Tom.").await)).await;
+        let result = LoadMemoryTool::new().run_async(json!({ "query": "dog" }), tool_context).await.unwrap();
+        assert_eq!(result, json!({ "memories": [] }));
+    }
+
+    #[tokio::test]
+    async fn calls_without_a_query_or_a_memory_service_fail() {
+        let result = LoadMemoryTool::new().run_async(json!({}), tool_context(Some(remembering("x").await)).await).await;
+        assert!(matches!(result, Err(AgentError::ToolExecutionFailed(error)) if error.contains("'query'")));
+        let result = LoadMemoryTool::new().run_async(json!({ "query": "cat" }), tool_context(None).await).await;
+        assert!(matches!(result, Err(AgentError::ToolExecutionFailed(error)) if error.contains("No memory service")));
+    }
+}
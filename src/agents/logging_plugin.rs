@@ -0,0 +1,158 @@
+use crate::base_tool::BaseTool;
+use crate::callback_context::CallbackContext;
+use crate::common::{AgentError, Content, Event};
+use crate::invocation_context::InvocationContext;
+use crate::models::{LlmRequest, LlmResponse};
+use crate::plugin::Plugin;
+use crate::tool_context::ToolContext;
+use async_trait::async_trait;
+
+/// Logs every lifecycle hook through `tracing` at the info level, with the
+/// plugin name in the `plugin` field.
+#[derive(Clone, Debug)]
+pub struct LoggingPlugin {
+    name: String,
+}
+
+impl LoggingPlugin {
+    pub fn new() -> Self {
+        LoggingPlugin {
+            name: "logging_plugin".to_string(),
+        }
+    }
+
+    pub fn with_name(name: String) -> Self {
+        LoggingPlugin { name }
+    }
+
+    fn log(&self, message: String) {
+        tracing::info!(plugin = %self.name, "{}", message);
+    }
+}
+
+impl Default for LoggingPlugin {
+    fn default() -> Self {
+        LoggingPlugin::new()
+    }
+}
+
+#[async_trait]
+impl Plugin for LoggingPlugin {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn on_user_message(&self, context: &InvocationContext, user_message: &Content) -> Option<Content> {
+        self.log(format!(
+            "user message (invocation {}, session {}): {}",
+            context.invocation_id(),
+            context.session().id(),
+            user_message.text()
+        ));
+        None
+    }
+
+    async fn before_run(&self, context: &InvocationContext) -> Option<Content> {
+        self.log(format!("run started (invocation {})", context.invocation_id()));
+        None
+    }
+
+    async fn after_run(&self, context: &InvocationContext) {
+        self.log(format!("run finished (invocation {})", context.invocation_id()));
+    }
+
+    async fn before_agent(&self, agent_name: &str, callback_context: &CallbackContext) -> Option<Content> {
+        self.log(format!(
+            "agent {} starting (branch {})",
+            agent_name,
+            callback_context.branch().unwrap_or_default()
+        ));
+        None
+    }
+
+    async fn after_agent(&self, agent_name: &str, callback_context: &CallbackContext) -> Option<Content> {
+        self.log(format!(
+            "agent {} finished (branch {})",
+            agent_name,
+            callback_context.branch().unwrap_or_default()
+        ));
+        None
+    }
+
+    async fn before_model(&self, _callback_context: &CallbackContext, request: &mut LlmRequest) -> Option<LlmResponse> {
+        self.log(format!("model {} called with {} contents", request.model, request.contents.len()));
+        None
+    }
+
+    async fn after_model(&self, _callback_context: &CallbackContext, response: &LlmResponse) -> Option<LlmResponse> {
+        match &response.error_message {
+            Some(error) => self.log(format!("model returned an error: {}", error)),
+            None => self.log(format!(
+                "model responded: {}",
+                response.content.as_ref().map(|content| content.text()).unwrap_or_default()
+            )),
+        }
+        None
+    }
+
+    async fn before_tool(
+        &self,
+        tool: &dyn BaseTool,
+        args: &mut serde_json::Value,
+        _tool_context: &ToolContext,
+    ) -> Option<serde_json::Value> {
+        self.log(format!("tool {} called with {}", tool.name(), args));
+        None
+    }
+
+    async fn after_tool(
+        &self,
+        tool: &dyn BaseTool,
+        _args: &serde_json::Value,
+        _tool_context: &ToolContext,
+        result: &serde_json::Value,
+    ) -> Option<serde_json::Value> {
+        self.log(format!("tool {} returned {}", tool.name(), result));
+        None
+    }
+
+    async fn on_event(&self, _context: &InvocationContext, event: &Event) -> Option<Event> {
+        self.log(format!(
+            "event {} from {}{}",
+            event.id,
+            event.author,
+            if event.final_response() { " (final)" } else { "" }
+        ));
+        None
+    }
+
+    async fn on_error(&self, context: &InvocationContext, error: &AgentError) {
+        self.log(format!("error in invocation {}: {}", context.invocation_id(), error));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::run_config::RunConfig;
+    use crate::runner::Runner;
+    use crate::testing::{unique_name, Reply};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn logging_leaves_the_run_unchanged() {
+        let (agent, _) = Reply::spawn_with(Reply::builder("hi".to_string()).name(unique_name("reply"))).await;
+        let runner = Runner::builder("test".to_string(), agent.get_cell())
+            .plugin(Arc::new(LoggingPlugin::with_name("audit".to_string())))
+            .build();
+        assert_eq!(runner.plugin_manager().plugins()[0].name(), "audit");
+        let session = runner.session_service().create_session("test", "user", None, None).await.unwrap();
+
+        let events = runner
+            .run_async("user", session.id(), Content::from_text("user", "hello"), RunConfig::default())
+            .await
+            .unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].content().unwrap().text(), "hi");
+    }
+}
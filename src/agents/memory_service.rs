@@ -0,0 +1,166 @@
+use crate::common::{AgentError, Content, Session};
+use async_trait::async_trait;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+#[derive(Clone, Debug)]
+pub struct MemoryEntry {
+    pub content: Content,
+    pub author: Option<String>,
+    pub session_id: String,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SearchMemoryResponse {
+    pub memories: Vec<MemoryEntry>,
+}
+
+#[async_trait]
+pub trait BaseMemoryService: Send + Sync + std::fmt::Debug {
+    async fn add_session_to_memory(&self, session: &Session) -> Result<(), AgentError>;
+
+    async fn search_memory(&self, app_name: &str, user_id: &str, query: &str) -> Result<SearchMemoryResponse, AgentError>;
+}
+
+type UserKey = (String, String);
+
+/// Keeps every event of the added sessions in memory and returns the ones that
+/// share at least one word with the query, those sharing the most words
+/// first. Meant for prototyping and tests.
+#[derive(Debug, Default)]
+pub struct InMemoryMemoryService {
+    sessions: Mutex<HashMap<UserKey, HashMap<String, Vec<MemoryEntry>>>>,
+}
+
+impl InMemoryMemoryService {
+    pub fn new() -> Self {
+        InMemoryMemoryService::default()
+    }
+
+    fn words(text: &str) -> HashSet<String> {
+        text.split(|c: char| !c.is_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .map(|word| word.to_lowercase())
+            .collect()
+    }
+}
+
+#[async_trait]
+impl BaseMemoryService for InMemoryMemoryService {
+    async fn add_session_to_memory(&self, session: &Session) -> Result<(), AgentError> {
+        let entries = session
+            .events()
+            .iter()
+            .filter_map(|event| {
+                let content = event.content()?;
+                if content.text().is_empty() {
+                    return None;
+                }
+                Some(MemoryEntry {
+                    content: content.clone(),
+                    author: Some(event.author.clone()),
+                    session_id: session.id().to_string(),
+                })
+            })
+            .collect();
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions
+            .entry((session.app_name().to_string(), session.user_id().to_string()))
+            .or_default()
+            .insert(session.id().to_string(), entries);
+        Ok(())
+    }
+
+    async fn search_memory(&self, app_name: &str, user_id: &str, query: &str) -> Result<SearchMemoryResponse, AgentError> {
+        let query_words = Self::words(query);
+        let sessions = self.sessions.lock().unwrap();
+        let mut matches: Vec<(usize, &MemoryEntry)> = sessions
+            .get(&(app_name.to_string(), user_id.to_string()))
+            .map(|user_sessions| {
+                user_sessions
+                    .values()
+                    .flatten()
+                    .map(|entry| (Self::words(&entry.content.text()).intersection(&query_words).count(), entry))
+                    .filter(|(shared, _)| *shared > 0)
+                    .collect()
+            })
+            .unwrap_or_default();
+        matches.sort_by_key(|(shared, _)| std::cmp::Reverse(*shared));
+        Ok(SearchMemoryResponse {
+            memories: matches.into_iter().map(|(_, entry)| entry.clone()).collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::Event;
+
+    fn session(app_name: &str, user_id: &str, id: &str, texts: &[(&str, &str)]) -> Session {
+        let mut session = Session::new(app_name.to_string(), user_id.to_string(), id.to_string(), HashMap::new());
+        for (author, text) in texts {
+            let event = Event::builder()
+                .author(author.to_string())
+                .content(Some(Content::from_text(if *author == "user" { "user" } else { "model" }, text)))
+                .build();
+            session.append_event(event);
+        }
+        session.append_event(Event::builder().author("agent".to_string()).build());
+        session
+    }
+
+    fn texts(response: &SearchMemoryResponse) -> Vec<String> {
+        response.memories.iter().map(|memory| memory.content.text()).collect()
+    }
+
+    #[tokio::test]
+    async fn sessions_are_ingested_event_by_event() {
+        let memory = InMemoryMemoryService::new();
+        memory
+            .add_session_to_memory(&session("app", "ada", "s-1", &[("user", "My cat is called Tom."), ("agent", "Noted!")]))
+            .await
+            .unwrap();
+        let response = memory.search_memory("app", "ada", "what is my CAT called").await.unwrap();
+        assert_eq!(texts(&response), ["My cat is called Tom."]);
+        assert_eq!(response.memories[0].author.as_deref(), Some("user"));
+        assert_eq!(response.memories[0].session_id, "s-1");
+        assert!(memory.search_memory("app", "ada", "dog").await.unwrap().memories.is_empty());
+
+        // Adding a session again replaces what was remembered of it.
+        memory.add_session_to_memory(&session("app", "ada", "s-1", &[("user", "I have a dog.")])).await.unwrap();
+        assert!(memory.search_memory("app", "ada", "cat").await.unwrap().memories.is_empty());
+        assert_eq!(texts(&memory.search_memory("app", "ada", "dog").await.unwrap()), ["I have a dog."]);
+    }
+
+    #[tokio::test]
+    async fn memories_sharing_more_words_come_first() {
+        let memory = InMemoryMemoryService::new();
+        memory
+            .add_session_to_memory(&session("app", "ada", "s-1", &[("user", "I like green tea.")]))
+            .await
+            .unwrap();
+        memory
+            .add_session_to_memory(&session("app", "ada", "s-2", &[("user", "Green tea in the morning, please.")]))
+            .await
+            .unwrap();
+        memory
+            .add_session_to_memory(&session("app", "ada", "s-3", &[("user", "The morning was green.")]))
+            .await
+            .unwrap();
+        let response = memory.search_memory("app", "ada", "green tea in the morning").await.unwrap();
+        assert_eq!(
+            texts(&response),
+            ["Green tea in the morning, please.", "The morning was green.", "I like green tea."]
+        );
+    }
+
+    #[tokio::test]
+    async fn memories_are_kept_per_app_and_user() {
+        let memory = InMemoryMemoryService::new();
+        memory.add_session_to_memory(&session("app", "ada", "s-1", &[("user", "secret plan")])).await.unwrap();
+        assert_eq!(memory.search_memory("app", "ada", "plan").await.unwrap().memories.len(), 1);
+        assert!(memory.search_memory("app", "bob", "plan").await.unwrap().memories.is_empty());
+        assert!(memory.search_memory("other", "ada", "plan").await.unwrap().memories.is_empty());
+    }
+}
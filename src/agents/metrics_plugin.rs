@@ -0,0 +1,203 @@
+use crate::base_tool::BaseTool;
+use crate::callback_context::CallbackContext;
+use crate::common::{AgentError, Content, Event};
+use crate::invocation_context::InvocationContext;
+use crate::models::{LlmRequest, LlmResponse};
+use crate::plugin::Plugin;
+use crate::tool_context::ToolContext;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Counts runs, agent runs, model calls, tool calls, events and errors, and
+/// accumulates the wall-clock time spent in runs.
+#[derive(Debug, Default)]
+pub struct MetricsPlugin {
+    runs: AtomicU64,
+    agent_runs: AtomicU64,
+    model_calls: AtomicU64,
+    tool_calls: AtomicU64,
+    events: AtomicU64,
+    errors: AtomicU64,
+    run_duration_millis: AtomicU64,
+    run_started_at: Mutex<HashMap<String, Instant>>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    pub runs: u64,
+    pub agent_runs: u64,
+    pub model_calls: u64,
+    pub tool_calls: u64,
+    pub events: u64,
+    pub errors: u64,
+    pub run_duration: Duration,
+}
+
+impl MetricsPlugin {
+    pub fn new() -> Self {
+        MetricsPlugin::default()
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            runs: self.runs.load(Ordering::Relaxed),
+            agent_runs: self.agent_runs.load(Ordering::Relaxed),
+            model_calls: self.model_calls.load(Ordering::Relaxed),
+            tool_calls: self.tool_calls.load(Ordering::Relaxed),
+            events: self.events.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            run_duration: Duration::from_millis(self.run_duration_millis.load(Ordering::Relaxed)),
+        }
+    }
+
+    fn finish_run(&self, invocation_id: &str) {
+        let started_at = self.run_started_at.lock().unwrap().remove(invocation_id);
+        if let Some(started_at) = started_at {
+            self.run_duration_millis
+                .fetch_add(started_at.elapsed().as_millis() as u64, Ordering::Relaxed);
+        }
+    }
+}
+
+#[async_trait]
+impl Plugin for MetricsPlugin {
+    fn name(&self) -> &str {
+        "metrics_plugin"
+    }
+
+    async fn before_run(&self, context: &InvocationContext) -> Option<Content> {
+        self.runs.fetch_add(1, Ordering::Relaxed);
+        self.run_started_at
+            .lock()
+            .unwrap()
+            .insert(context.invocation_id().to_string(), Instant::now());
+        None
+    }
+
+    async fn after_run(&self, context: &InvocationContext) {
+        self.finish_run(context.invocation_id());
+    }
+
+    async fn before_agent(&self, _agent_name: &str, _callback_context: &CallbackContext) -> Option<Content> {
+        self.agent_runs.fetch_add(1, Ordering::Relaxed);
+        None
+    }
+
+    async fn before_model(&self, _callback_context: &CallbackContext, _request: &mut LlmRequest) -> Option<LlmResponse> {
+        self.model_calls.fetch_add(1, Ordering::Relaxed);
+        None
+    }
+
+    async fn before_tool(
+        &self,
+        _tool: &dyn BaseTool,
+        _args: &mut serde_json::Value,
+        _tool_context: &ToolContext,
+    ) -> Option<serde_json::Value> {
+        self.tool_calls.fetch_add(1, Ordering::Relaxed);
+        None
+    }
+
+    async fn on_event(&self, _context: &InvocationContext, _event: &Event) -> Option<Event> {
+        self.events.fetch_add(1, Ordering::Relaxed);
+        None
+    }
+
+    async fn on_error(&self, context: &InvocationContext, _error: &AgentError) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+        self.finish_run(context.invocation_id());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::run_config::RunConfig;
+    use crate::runner::Runner;
+    use crate::llm_agent::LlmAgent;
+    use crate::testing::{function_call, unique_name, Echo, Reply, ScriptedLlm};
+    use serde_json::json;
+    use std::sync::Arc;
+
+    /// Answers every run itself.
+    struct Canned;
+
+    #[async_trait]
+    impl Plugin for Canned {
+        fn name(&self) -> &str {
+            "canned"
+        }
+
+        async fn before_run(&self, _context: &InvocationContext) -> Option<Content> {
+            Some(Content::from_text("model", "canned"))
+        }
+    }
+
+    #[tokio::test]
+    async fn counts_a_scripted_run() {
+        let model = ScriptedLlm::new(vec![
+            function_call("echo", json!({ "text": "hi" })),
+            Content::from_text("model", "done"),
+        ]);
+        let agent = LlmAgent::builder(model)
+            .tools(vec![Echo::new()])
+            .name(unique_name("caller"))
+            .spawn()
+            .await
+            .unwrap();
+        let metrics = Arc::new(MetricsPlugin::new());
+        let runner = Runner::builder("test".to_string(), agent.get_cell()).plugin(metrics.clone()).build();
+        let session = runner.session_service().create_session("test", "user", None, None).await.unwrap();
+
+        let events = runner
+            .run_async("user", session.id(), Content::from_text("user", "hello"), RunConfig::default())
+            .await
+            .unwrap();
+        let snapshot = metrics.snapshot();
+        assert_eq!(
+            snapshot,
+            MetricsSnapshot {
+                runs: 1,
+                agent_runs: 1,
+                model_calls: 2,
+                tool_calls: 1,
+                events: events.len() as u64,
+                errors: 0,
+                run_duration: snapshot.run_duration,
+            }
+        );
+        assert_eq!(events.len(), 3);
+        assert!(metrics.run_started_at.lock().unwrap().is_empty());
+
+        let result = runner
+            .run_async("user", session.id(), Content::from_text("user", "again"), RunConfig::default())
+            .await;
+        assert!(result.is_err());
+        assert_eq!((metrics.snapshot().runs, metrics.snapshot().errors), (2, 1));
+        assert!(metrics.run_started_at.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn runs_answered_by_a_plugin_are_finished() {
+        let (agent, runs) = Reply::spawn_with(Reply::builder("hi".to_string()).name(unique_name("reply"))).await;
+        let metrics = Arc::new(MetricsPlugin::new());
+        let runner = Runner::builder("test".to_string(), agent.get_cell())
+            .plugin(metrics.clone())
+            .plugin(Arc::new(Canned))
+            .build();
+        let session = runner.session_service().create_session("test", "user", None, None).await.unwrap();
+
+        let events = runner
+            .run_async("user", session.id(), Content::from_text("user", "hello"), RunConfig::default())
+            .await
+            .unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].content().unwrap().text(), "canned");
+        assert_eq!(runs.load(Ordering::SeqCst), 0);
+        assert_eq!(metrics.snapshot().runs, 1);
+        assert!(metrics.run_started_at.lock().unwrap().is_empty());
+    }
+}
@@ -1,8 +1,41 @@
 pub mod common;
 pub mod invocation_context;
 pub mod callback_context;
+pub mod tool_context;
 pub mod run_config;
+pub mod state;
+pub mod session_service;
+pub mod artifact_service;
+pub mod memory_service;
+pub mod embedder;
+pub mod vector_memory_service;
+pub mod models;
+pub mod openai_llm;
+pub mod instructions;
+pub mod schema;
+pub mod plugin;
+pub mod planner;
+pub mod telemetry;
+pub mod agent_metrics;
+pub mod logging_plugin;
+pub mod metrics_plugin;
+pub mod base_tool;
+pub mod load_memory_tool;
 pub mod base_agent;
+pub mod supervision;
+pub mod agent;
 pub mod sequential_agent;
 pub mod parallel_agent;
-pub mod loop_agent;
\ No newline at end of file
+pub mod loop_agent;
+pub mod graph_agent;
+pub mod router_agent;
+pub mod voting_agent;
+pub mod llm_agent;
+pub mod agent_config;
+pub mod runner;
+#[cfg(feature = "cluster")]
+pub mod remote_agent;
+#[cfg(feature = "server")]
+pub mod server;
+#[cfg(test)]
+pub(crate) mod testing;
@@ -0,0 +1,46 @@
+use crate::common::{AgentError, Content};
+use async_trait::async_trait;
+
+#[derive(Clone, Debug, Default)]
+pub struct LlmRequest {
+    pub model: String,
+    pub system_instruction: Option<String>,
+    pub contents: Vec<Content>,
+    pub tools: Vec<FunctionDeclaration>,
+    /// JSON Schema the reply must follow. Clients that support a JSON mode
+    /// should enable it when this is set.
+    pub response_schema: Option<serde_json::Value>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct FunctionDeclaration {
+    pub name: String,
+    pub description: String,
+    pub parameters: Option<serde_json::Value>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct LlmResponse {
+    pub content: Option<Content>,
+    pub usage_metadata: Option<UsageMetadata>,
+    pub error_message: Option<String>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct UsageMetadata {
+    pub prompt_token_count: i32,
+    pub candidates_token_count: i32,
+    pub total_token_count: i32,
+}
+
+#[async_trait]
+pub trait BaseLlm: Send + Sync {
+    fn model(&self) -> &str;
+
+    /// The provider reported as `gen_ai.system` on model spans, e.g. `openai`.
+    fn provider(&self) -> &str {
+        "_OTHER"
+    }
+
+    async fn generate_content(&self, request: LlmRequest) -> Result<LlmResponse, AgentError>;
+}
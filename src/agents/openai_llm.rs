@@ -0,0 +1,254 @@
+use crate::common::{AgentError, Blob, Content, FunctionCall, Part};
+use crate::models::{BaseLlm, LlmRequest, LlmResponse, UsageMetadata};
+use async_trait::async_trait;
+use base64::Engine;
+use serde_json::json;
+use uuid::Uuid;
+
+/// Client for any server implementing the OpenAI `/chat/completions`
+/// endpoint, including local ones such as llama.cpp, vLLM or Ollama.
+#[derive(Clone, Debug)]
+pub struct OpenAiLlm {
+    client: reqwest::Client,
+    base_url: String,
+    api_key: Option<String>,
+    model: String,
+}
+
+impl OpenAiLlm {
+    pub fn new(base_url: String, api_key: Option<String>, model: String) -> Self {
+        OpenAiLlm {
+            client: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key,
+            model,
+        }
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// Content part for an image or audio blob; other media types are not
+    /// accepted by the chat completions API and are dropped.
+    fn media_part(blob: &Blob) -> Option<serde_json::Value> {
+        let data = base64::engine::general_purpose::STANDARD.encode(&blob.data);
+        match blob.mime_type.as_str() {
+            mime_type if mime_type.starts_with("image/") => Some(json!({
+                "type": "image_url",
+                "image_url": { "url": format!("data:{};base64,{}", mime_type, data) },
+            })),
+            "audio/wav" | "audio/x-wav" => Some(json!({ "type": "input_audio", "input_audio": { "data": data, "format": "wav" } })),
+            "audio/mpeg" | "audio/mp3" => Some(json!({ "type": "input_audio", "input_audio": { "data": data, "format": "mp3" } })),
+            _ => None,
+        }
+    }
+
+    /// The text of a content, with the thought parts of a model reply so that
+    /// the model sees the plan and reasoning it wrote on earlier turns.
+    fn message_text(content: &Content) -> String {
+        if content.role != "model" || content.parts.iter().all(|part| part.thought.is_none()) {
+            return content.text();
+        }
+        content
+            .parts
+            .iter()
+            .filter_map(|part| part.text.as_deref())
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+
+    fn messages(request: &LlmRequest) -> Vec<serde_json::Value> {
+        let mut messages = Vec::new();
+        if let Some(system_instruction) = &request.system_instruction {
+            messages.push(json!({ "role": "system", "content": system_instruction }));
+        }
+        for content in &request.contents {
+            let text = Self::message_text(content);
+            let tool_calls: Vec<serde_json::Value> = content
+                .function_calls()
+                .into_iter()
+                .map(|function_call| {
+                    json!({
+                        "id": function_call.id,
+                        "type": "function",
+                        "function": {
+                            "name": function_call.name,
+                            "arguments": function_call.args.to_string(),
+                        },
+                    })
+                })
+                .collect();
+            let function_responses = content.parts.iter().filter_map(|part| part.function_response.as_ref());
+            for function_response in function_responses {
+                messages.push(json!({
+                    "role": "tool",
+                    "tool_call_id": function_response.id,
+                    "content": function_response.response.to_string(),
+                }));
+            }
+            let media: Vec<serde_json::Value> = content
+                .parts
+                .iter()
+                .filter_map(|part| part.inline_data.as_ref())
+                .filter_map(Self::media_part)
+                .collect();
+            if text.is_empty() && tool_calls.is_empty() && media.is_empty() {
+                continue;
+            }
+            let role = if content.role == "model" { "assistant" } else { "user" };
+            let mut message = if media.is_empty() || role == "assistant" {
+                json!({ "role": role, "content": text })
+            } else {
+                let mut parts = media;
+                if !text.is_empty() {
+                    parts.insert(0, json!({ "type": "text", "text": text }));
+                }
+                json!({ "role": role, "content": parts })
+            };
+            if !tool_calls.is_empty() {
+                message["tool_calls"] = serde_json::Value::Array(tool_calls);
+            }
+            messages.push(message);
+        }
+        messages
+    }
+
+    fn body(&self, request: &LlmRequest) -> serde_json::Value {
+        let model = if request.model.is_empty() { &self.model } else { &request.model };
+        let mut body = json!({
+            "model": model,
+            "messages": Self::messages(request),
+        });
+        if !request.tools.is_empty() {
+            body["tools"] = request
+                .tools
+                .iter()
+                .map(|tool| {
+                    json!({
+                        "type": "function",
+                        "function": {
+                            "name": tool.name,
+                            "description": tool.description,
+                            "parameters": tool.parameters.clone().unwrap_or_else(|| json!({ "type": "object", "properties": {} })),
+                        },
+                    })
+                })
+                .collect();
+        }
+        if let Some(response_schema) = &request.response_schema {
+            body["response_format"] = json!({
+                "type": "json_schema",
+                "json_schema": { "name": "output", "schema": response_schema },
+            });
+        }
+        body
+    }
+
+    fn parse_response(response: &serde_json::Value) -> Result<LlmResponse, AgentError> {
+        if let Some(error) = response.get("error") {
+            return Ok(LlmResponse {
+                error_message: Some(error.get("message").and_then(|message| message.as_str()).unwrap_or("Unknown error").to_string()),
+                ..Default::default()
+            });
+        }
+        let message = response
+            .pointer("/choices/0/message")
+            .ok_or_else(|| AgentError::ModelError("Chat completion response has no message".to_string()))?;
+
+        let mut parts = Vec::new();
+        if let Some(text) = message.get("content").and_then(|content| content.as_str()).filter(|text| !text.is_empty()) {
+            parts.push(Part::from_text(text));
+        }
+        for tool_call in message.get("tool_calls").and_then(|tool_calls| tool_calls.as_array()).into_iter().flatten() {
+            let arguments = tool_call.pointer("/function/arguments").and_then(|arguments| arguments.as_str()).unwrap_or("{}");
+            parts.push(Part::from_function_call(FunctionCall {
+                id: Some(
+                    tool_call
+                        .get("id")
+                        .and_then(|id| id.as_str())
+                        .map(|id| id.to_string())
+                        .unwrap_or_else(|| format!("call_{}", Uuid::new_v4())),
+                ),
+                name: tool_call.pointer("/function/name").and_then(|name| name.as_str()).unwrap_or_default().to_string(),
+                args: serde_json::from_str(arguments).unwrap_or_else(|_| json!({})),
+            }));
+        }
+
+        let usage_metadata = response.get("usage").map(|usage| {
+            let count = |field: &str| usage.get(field).and_then(|count| count.as_i64()).unwrap_or_default() as i32;
+            UsageMetadata {
+                prompt_token_count: count("prompt_tokens"),
+                candidates_token_count: count("completion_tokens"),
+                total_token_count: count("total_tokens"),
+            }
+        });
+
+        Ok(LlmResponse {
+            content: Some(Content::new("model".to_string(), parts)),
+            usage_metadata,
+            error_message: None,
+        })
+    }
+}
+
+#[async_trait]
+impl BaseLlm for OpenAiLlm {
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn provider(&self) -> &str {
+        "openai"
+    }
+
+    async fn generate_content(&self, request: LlmRequest) -> Result<LlmResponse, AgentError> {
+        let mut http_request = self
+            .client
+            .post(format!("{}/chat/completions", self.base_url))
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(self.body(&request).to_string());
+        if let Some(api_key) = &self.api_key {
+            http_request = http_request.bearer_auth(api_key);
+        }
+        let response = http_request.send().await.map_err(|e| AgentError::ModelError(e.to_string()))?;
+        let status = response.status();
+        let bytes = response.bytes().await.map_err(|e| AgentError::ModelError(e.to_string()))?;
+        let body: serde_json::Value = serde_json::from_slice(&bytes).map_err(|_| {
+            AgentError::ModelError(format!(
+                "Chat completion request failed with {}: {}",
+                status,
+                String::from_utf8_lossy(&bytes)
+            ))
+        })?;
+        if !status.is_success() && body.get("error").is_none() {
+            return Err(AgentError::ModelError(format!("Chat completion request failed with {}: {}", status, body)));
+        }
+        Self::parse_response(&body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::Thought;
+
+    #[test]
+    fn model_thoughts_are_sent_back() {
+        let request = LlmRequest {
+            system_instruction: Some("Plan first.".to_string()),
+            contents: vec![
+                Content::from_text("user", "2 + 2?"),
+                Content::new(
+                    "model".to_string(),
+                    vec![Part::from_thought(Thought::Planning, "1. add"), Part::from_text("4")],
+                ),
+            ],
+            ..LlmRequest::default()
+        };
+        let messages = OpenAiLlm::messages(&request);
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[1], json!({ "role": "user", "content": "2 + 2?" }));
+        assert_eq!(messages[2], json!({ "role": "assistant", "content": "1. add\n\n4" }));
+    }
+}
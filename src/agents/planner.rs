@@ -0,0 +1,457 @@
+//! Planners make an `LlmAgent` think before it acts. A planner adds to the
+//! agent's system instruction, asking the model to lay out its reply in
+//! sections headed by markers such as `/*PLANNING*/`, and splits each reply
+//! at those markers into thought parts and the answer. Thought parts are
+//! kept on the agent's events, each marked with its `Thought` kind, and left
+//! out of `Content::text` and so of the answer. Models still get them back
+//! with the history, so the model can follow the plan it wrote.
+
+use crate::common::{Content, Part, Thought};
+use crate::invocation_context::InvocationContext;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+pub trait Planner: Send + Sync {
+    /// Text appended to the agent's system instruction for the next request.
+    fn instruction(&self, context: &InvocationContext) -> Option<String>;
+
+    /// Splits the model's reply into thought parts and the rest. Returns the
+    /// reply with the state changes to record on its event.
+    fn process_response(&self, context: &InvocationContext, content: Content) -> (Content, HashMap<String, Value>);
+
+    /// Sees the results of the tools the model called. Returns the state
+    /// changes to record on their event.
+    fn process_tool_results(&self, _context: &InvocationContext, _results: &Content) -> HashMap<String, Value> {
+        HashMap::new()
+    }
+}
+
+const PLANNING: &str = "/*PLANNING*/";
+const REPLANNING: &str = "/*REPLANNING*/";
+const REASONING: &str = "/*REASONING*/";
+const ACTION: &str = "/*ACTION*/";
+const PLAN: &str = "/*PLAN*/";
+const DONE: &str = "/*DONE*/";
+const FINAL_ANSWER: &str = "/*FINAL_ANSWER*/";
+
+/// Splits `text` at the markers. Every section comes with the marker that
+/// heads it; text before the first marker has none.
+fn sections<'a>(text: &'a str, markers: &[&'static str]) -> Vec<(Option<&'static str>, &'a str)> {
+    let mut sections = Vec::new();
+    let mut marker = None;
+    let mut rest = text;
+    while let Some((index, next)) = markers
+        .iter()
+        .filter_map(|next| rest.find(next).map(|index| (index, *next)))
+        .min_by_key(|(index, _)| *index)
+    {
+        sections.push((marker, &rest[..index]));
+        marker = Some(next);
+        rest = &rest[index + next.len()..];
+    }
+    sections.push((marker, rest));
+    sections
+        .into_iter()
+        .map(|(marker, text)| (marker, text.trim()))
+        .filter(|(_, text)| !text.is_empty())
+        .collect()
+}
+
+/// Rebuilds the reply with every text part split into sections. Sections
+/// that `thought` gives a kind become thought parts.
+fn split_parts(content: Content, markers: &[&'static str], mut thought: impl FnMut(&'static str, &str) -> Option<Thought>) -> Content {
+    let mut parts = Vec::new();
+    for part in content.parts {
+        let text = match &part.text {
+            Some(text) if part.thought.is_none() => text.clone(),
+            _ => {
+                parts.push(part);
+                continue;
+            }
+        };
+        for (marker, section) in sections(&text, markers) {
+            match marker.and_then(|marker| thought(marker, section)) {
+                Some(kind) => parts.push(Part::from_thought(kind, section)),
+                None => parts.push(Part::from_text(section)),
+            }
+        }
+    }
+    Content::new(content.role, parts)
+}
+
+/// Plans in the style of ReAct: the model writes a plan, then reasons about
+/// each step and states the action it takes before calling a tool, and
+/// revises the plan when the results call for it.
+#[derive(Clone, Debug, Default)]
+pub struct ReActPlanner;
+
+impl ReActPlanner {
+    pub fn new() -> Self {
+        ReActPlanner
+    }
+}
+
+impl Planner for ReActPlanner {
+    fn instruction(&self, _context: &InvocationContext) -> Option<String> {
+        Some(format!(
+            "Answer by following these steps, starting each section with its marker.\n\
+             1. Under {PLANNING}, write a numbered plan for answering the request with the tools available.\n\
+             2. For each step, explain your thinking under {REASONING}, then describe what you will do under {ACTION} and \
+             make the tool call it needs.\n\
+             3. If the results show that the plan no longer works, write a revised plan under {REPLANNING}.\n\
+             4. Once you can answer, write the answer under {FINAL_ANSWER}."
+        ))
+    }
+
+    fn process_response(&self, _context: &InvocationContext, content: Content) -> (Content, HashMap<String, Value>) {
+        let markers = [PLANNING, REPLANNING, REASONING, ACTION, FINAL_ANSWER];
+        let content = split_parts(content, &markers, |marker, _| match marker {
+            PLANNING | REPLANNING => Some(Thought::Planning),
+            REASONING => Some(Thought::Reasoning),
+            ACTION => Some(Thought::Action),
+            _ => None,
+        });
+        (content, HashMap::new())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StepStatus {
+    Pending,
+    Done,
+    Failed,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PlanStep {
+    pub task: String,
+    pub status: StepStatus,
+    /// Why the step failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The plan of a `PlanAndExecutePlanner`, as kept in the session state.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Plan {
+    /// The invocation the plan was written for. A new request starts a new
+    /// plan.
+    pub invocation_id: String,
+    pub steps: Vec<PlanStep>,
+    /// How many times the plan was revised.
+    pub revision: usize,
+}
+
+impl Plan {
+    fn current_step(&self) -> Option<usize> {
+        self.steps.iter().position(|step| step.status == StepStatus::Pending)
+    }
+
+    fn failed_step(&self) -> Option<usize> {
+        self.steps.iter().position(|step| step.status == StepStatus::Failed)
+    }
+
+    fn describe(&self) -> String {
+        self.steps
+            .iter()
+            .enumerate()
+            .map(|(index, step)| match (step.status, &step.error) {
+                (StepStatus::Failed, Some(error)) => format!("{}. [failed: {}] {}", index + 1, error, step.task),
+                (StepStatus::Failed, None) => format!("{}. [failed] {}", index + 1, step.task),
+                (StepStatus::Done, _) => format!("{}. [done] {}", index + 1, step.task),
+                (StepStatus::Pending, _) => format!("{}. [pending] {}", index + 1, step.task),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// Has the model write an explicit plan into the session state before it
+/// acts, then carry the plan out step by step. A step whose tool call fails
+/// is marked failed and the model is asked for a revised plan, up to
+/// `max_replans` times.
+#[derive(Clone, Debug)]
+pub struct PlanAndExecutePlanner {
+    state_key: String,
+    max_replans: usize,
+}
+
+impl Default for PlanAndExecutePlanner {
+    fn default() -> Self {
+        PlanAndExecutePlanner {
+            state_key: "plan".to_string(),
+            max_replans: 3,
+        }
+    }
+}
+
+impl PlanAndExecutePlanner {
+    pub fn new() -> Self {
+        PlanAndExecutePlanner::default()
+    }
+
+    /// State key the plan is kept under, `plan` by default.
+    pub fn state_key(mut self, state_key: String) -> Self {
+        self.state_key = state_key;
+        self
+    }
+
+    pub fn max_replans(mut self, max_replans: usize) -> Self {
+        self.max_replans = max_replans;
+        self
+    }
+
+    /// The plan of the current invocation, if one was written.
+    pub fn plan(&self, context: &InvocationContext) -> Option<Plan> {
+        context
+            .session()
+            .state()
+            .get(&self.state_key)
+            .and_then(|plan| serde_json::from_value::<Plan>(plan.clone()).ok())
+            .filter(|plan| plan.invocation_id == context.invocation_id())
+    }
+
+    fn state_delta(&self, plan: &Plan) -> HashMap<String, Value> {
+        let plan = serde_json::to_value(plan).unwrap_or_default();
+        HashMap::from([(self.state_key.clone(), plan)])
+    }
+
+    fn parse_steps(text: &str) -> Vec<PlanStep> {
+        text.lines()
+            .map(|line| line.trim().trim_start_matches(|c: char| c.is_ascii_digit() || matches!(c, '.' | ')' | '-' | '*')))
+            .map(str::trim)
+            .filter(|task| !task.is_empty())
+            .map(|task| PlanStep {
+                task: task.to_string(),
+                status: StepStatus::Pending,
+                error: None,
+            })
+            .collect()
+    }
+}
+
+impl Planner for PlanAndExecutePlanner {
+    fn instruction(&self, context: &InvocationContext) -> Option<String> {
+        let format = format!(
+            "Start each section of your reply with its marker. Write plans under {PLAN} as a numbered list with one step \
+             per line. Explain your thinking under {REASONING}. Use the tools available to carry out the steps in order, \
+             and after finishing steps list their numbers under {DONE}. Once every step is done, write the answer under \
+             {FINAL_ANSWER}."
+        );
+        let plan = match self.plan(context) {
+            Some(plan) => plan,
+            None => return Some(format!("{}\n\nFirst write a plan for the request, then start on step 1.", format)),
+        };
+        let next = match (plan.failed_step(), plan.current_step()) {
+            (Some(failed), _) if plan.revision < self.max_replans => format!(
+                "Step {} failed. Write a revised plan under {PLAN} for the remaining work, without the steps that are \
+                 done, then continue.",
+                failed + 1
+            ),
+            (Some(failed), _) => format!(
+                "Step {} failed and the plan cannot be revised again. Work around it, or explain under {FINAL_ANSWER} \
+                 why the request cannot be completed.",
+                failed + 1
+            ),
+            (None, Some(current)) => format!("Continue with step {}.", current + 1),
+            (None, None) => format!("Every step is done. Write the answer under {FINAL_ANSWER}."),
+        };
+        Some(format!("{}\n\nThe plan:\n{}\n\n{}", format, plan.describe(), next))
+    }
+
+    fn process_response(&self, context: &InvocationContext, content: Content) -> (Content, HashMap<String, Value>) {
+        let mut plan = self.plan(context);
+        let mut changed = false;
+        let markers = [PLAN, REASONING, DONE, FINAL_ANSWER];
+        let content = split_parts(content, &markers, |marker, section| match marker {
+            PLAN => {
+                let steps = Self::parse_steps(section);
+                plan = Some(match plan.take() {
+                    Some(mut plan) => {
+                        plan.steps.retain(|step| step.status == StepStatus::Done);
+                        plan.steps.extend(steps);
+                        plan.revision += 1;
+                        plan
+                    }
+                    None => Plan {
+                        invocation_id: context.invocation_id().to_string(),
+                        steps,
+                        revision: 0,
+                    },
+                });
+                changed = true;
+                Some(Thought::Planning)
+            }
+            DONE => {
+                if let Some(plan) = &mut plan {
+                    let numbers = section.split(|c: char| !c.is_ascii_digit()).filter_map(|number| number.parse::<usize>().ok());
+                    for number in numbers {
+                        if let Some(step) = number.checked_sub(1).and_then(|index| plan.steps.get_mut(index)) {
+                            step.status = StepStatus::Done;
+                            changed = true;
+                        }
+                    }
+                }
+                Some(Thought::Planning)
+            }
+            REASONING => Some(Thought::Reasoning),
+            _ => None,
+        });
+        let state_delta = match plan.filter(|_| changed) {
+            Some(plan) => self.state_delta(&plan),
+            None => HashMap::new(),
+        };
+        (content, state_delta)
+    }
+
+    fn process_tool_results(&self, context: &InvocationContext, results: &Content) -> HashMap<String, Value> {
+        let mut plan = match self.plan(context) {
+            Some(plan) => plan,
+            None => return HashMap::new(),
+        };
+        let errors: Vec<String> = results
+            .parts
+            .iter()
+            .filter_map(|part| part.function_response.as_ref())
+            .filter_map(|response| {
+                let error = response.response.get("error")?;
+                let error = error.as_str().map(|error| error.to_string()).unwrap_or_else(|| error.to_string());
+                Some(format!("{}: {}", response.name, error))
+            })
+            .collect();
+        match plan.current_step() {
+            Some(current) if !errors.is_empty() => {
+                plan.steps[current].status = StepStatus::Failed;
+                plan.steps[current].error = Some(errors.join("; "));
+                self.state_delta(&plan)
+            }
+            _ => HashMap::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::FunctionResponse;
+    use crate::testing::{context, unique_name, Reply};
+    use serde_json::json;
+
+    fn reply(text: &str) -> Content {
+        Content::from_text("model", text)
+    }
+
+    fn thoughts(content: &Content) -> Vec<(Thought, &str)> {
+        content
+            .parts
+            .iter()
+            .filter_map(|part| Some((part.thought?, part.text.as_deref()?)))
+            .collect()
+    }
+
+    /// Records the state changes in the session, as the agent's event would.
+    fn apply(context: &mut InvocationContext, state_delta: HashMap<String, Value>) {
+        context.session_mut().state_mut().extend(state_delta);
+    }
+
+    async fn test_context() -> InvocationContext {
+        let (agent, _) = Reply::spawn_with(Reply::builder("ok".to_string()).name(unique_name("planned"))).await;
+        context(&agent).await
+    }
+
+    #[test]
+    fn sections_split_at_the_first_marker_found() {
+        let text = "intro /*PLAN*/ 1. look /*REASONING*/ why /*PLAN*/\n/*FINAL_ANSWER*/ done";
+        let found = sections(text, &[PLAN, REASONING, FINAL_ANSWER]);
+        assert_eq!(
+            found,
+            vec![
+                (None, "intro"),
+                (Some(PLAN), "1. look"),
+                (Some(REASONING), "why"),
+                (Some(FINAL_ANSWER), "done"),
+            ]
+        );
+        assert_eq!(sections("  plain  ", &[PLAN]), vec![(None, "plain")]);
+        assert!(sections(" /*PLAN*/ ", &[PLAN]).is_empty());
+    }
+
+    #[test]
+    fn plan_steps_drop_their_numbering() {
+        let steps = PlanAndExecutePlanner::parse_steps("1. search\n  2) read\n- summarize\n\n");
+        let tasks: Vec<&str> = steps.iter().map(|step| step.task.as_str()).collect();
+        assert_eq!(tasks, vec!["search", "read", "summarize"]);
+        assert!(steps.iter().all(|step| step.status == StepStatus::Pending));
+    }
+
+    #[tokio::test]
+    async fn react_marks_thoughts_and_keeps_the_answer() {
+        let context = test_context().await;
+        let (content, state_delta) = ReActPlanner::new().process_response(
+            &context,
+            reply("/*PLANNING*/ 1. add /*REASONING*/ easy /*ACTION*/ adding /*FINAL_ANSWER*/ 4"),
+        );
+        assert_eq!(
+            thoughts(&content),
+            vec![(Thought::Planning, "1. add"), (Thought::Reasoning, "easy"), (Thought::Action, "adding")]
+        );
+        assert_eq!(content.text(), "4");
+        assert!(state_delta.is_empty());
+    }
+
+    #[tokio::test]
+    async fn failed_steps_are_replanned_up_to_the_limit() {
+        let mut context = test_context().await;
+        let planner = PlanAndExecutePlanner::new().max_replans(1);
+        assert!(planner.instruction(&context).unwrap().contains("First write a plan"));
+
+        let (_, state_delta) = planner.process_response(&context, reply("/*PLAN*/\n1. search\n2. summarize"));
+        apply(&mut context, state_delta);
+        let plan = planner.plan(&context).unwrap();
+        assert_eq!(plan.steps.len(), 2);
+        assert!(planner.instruction(&context).unwrap().contains("Continue with step 1."));
+
+        let (_, state_delta) = planner.process_response(&context, reply("/*DONE*/ 1"));
+        apply(&mut context, state_delta);
+        let failure = Content::new(
+            "user".to_string(),
+            vec![Part::from_function_response(FunctionResponse {
+                id: None,
+                name: "summarize".to_string(),
+                response: json!({ "error": "too long" }),
+            })],
+        );
+        let state_delta = planner.process_tool_results(&context, &failure);
+        apply(&mut context, state_delta);
+        let plan = planner.plan(&context).unwrap();
+        assert_eq!(plan.steps[1].status, StepStatus::Failed);
+        assert_eq!(plan.steps[1].error.as_deref(), Some("summarize: too long"));
+        assert!(planner.instruction(&context).unwrap().contains("Step 2 failed. Write a revised plan"));
+
+        let (_, state_delta) = planner.process_response(&context, reply("/*PLAN*/\n1. summarize in parts"));
+        apply(&mut context, state_delta);
+        let plan = planner.plan(&context).unwrap();
+        assert_eq!(plan.revision, 1);
+        let tasks: Vec<&str> = plan.steps.iter().map(|step| step.task.as_str()).collect();
+        assert_eq!(tasks, vec!["search", "summarize in parts"]);
+
+        let state_delta = planner.process_tool_results(&context, &failure);
+        apply(&mut context, state_delta);
+        assert!(planner.instruction(&context).unwrap().contains("cannot be revised again"));
+    }
+
+    #[tokio::test]
+    async fn plans_of_other_invocations_are_ignored() {
+        let mut context = test_context().await;
+        let planner = PlanAndExecutePlanner::new();
+        let (_, state_delta) = planner.process_response(&context, reply("/*PLAN*/\n1. search"));
+        apply(&mut context, state_delta);
+        assert!(planner.plan(&context).is_some());
+
+        context.session_mut().state_mut().get_mut("plan").unwrap()["invocation_id"] = json!("earlier");
+        assert!(planner.plan(&context).is_none());
+        assert!(planner.instruction(&context).unwrap().contains("First write a plan"));
+    }
+}
@@ -0,0 +1,360 @@
+use crate::base_tool::BaseTool;
+use crate::callback_context::CallbackContext;
+use crate::common::{AgentError, Content, Event};
+use crate::invocation_context::InvocationContext;
+use crate::models::{LlmRequest, LlmResponse};
+use crate::tool_context::ToolContext;
+use async_trait::async_trait;
+use std::sync::Arc;
+
+/// Cross-cutting hooks registered once on the `Runner` and applied to every
+/// agent in the tree. Returning a value from a `before_*`/`after_*` hook
+/// short-circuits the remaining plugins and the per-agent callbacks.
+#[async_trait]
+pub trait Plugin: Send + Sync {
+    fn name(&self) -> &str;
+
+    async fn on_user_message(&self, _context: &InvocationContext, _user_message: &Content) -> Option<Content> {
+        None
+    }
+
+    async fn before_run(&self, _context: &InvocationContext) -> Option<Content> {
+        None
+    }
+
+    async fn after_run(&self, _context: &InvocationContext) {}
+
+    async fn before_agent(&self, _agent_name: &str, _callback_context: &CallbackContext) -> Option<Content> {
+        None
+    }
+
+    async fn after_agent(&self, _agent_name: &str, _callback_context: &CallbackContext) -> Option<Content> {
+        None
+    }
+
+    async fn before_model(&self, _callback_context: &CallbackContext, _request: &mut LlmRequest) -> Option<LlmResponse> {
+        None
+    }
+
+    async fn after_model(&self, _callback_context: &CallbackContext, _response: &LlmResponse) -> Option<LlmResponse> {
+        None
+    }
+
+    async fn before_tool(
+        &self,
+        _tool: &dyn BaseTool,
+        _args: &mut serde_json::Value,
+        _tool_context: &ToolContext,
+    ) -> Option<serde_json::Value> {
+        None
+    }
+
+    async fn after_tool(
+        &self,
+        _tool: &dyn BaseTool,
+        _args: &serde_json::Value,
+        _tool_context: &ToolContext,
+        _result: &serde_json::Value,
+    ) -> Option<serde_json::Value> {
+        None
+    }
+
+    async fn on_event(&self, _context: &InvocationContext, _event: &Event) -> Option<Event> {
+        None
+    }
+
+    async fn on_error(&self, _context: &InvocationContext, _error: &AgentError) {}
+}
+
+#[derive(Clone, Default)]
+pub struct PluginManager {
+    plugins: Vec<Arc<dyn Plugin>>,
+}
+
+impl std::fmt::Debug for PluginManager {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_list().entries(self.plugins.iter().map(|plugin| plugin.name())).finish()
+    }
+}
+
+impl PluginManager {
+    pub fn new(plugins: Vec<Arc<dyn Plugin>>) -> Self {
+        PluginManager { plugins }
+    }
+
+    pub fn register_plugin(&mut self, plugin: Arc<dyn Plugin>) {
+        self.plugins.push(plugin);
+    }
+
+    pub fn plugins(&self) -> &Vec<Arc<dyn Plugin>> {
+        &self.plugins
+    }
+
+    pub async fn run_on_user_message(&self, context: &InvocationContext, user_message: Content) -> Content {
+        let mut user_message = user_message;
+        for plugin in &self.plugins {
+            if let Some(content) = plugin.on_user_message(context, &user_message).await {
+                user_message = content;
+            }
+        }
+        user_message
+    }
+
+    pub async fn run_before_run(&self, context: &InvocationContext) -> Option<Content> {
+        for plugin in &self.plugins {
+            if let Some(content) = plugin.before_run(context).await {
+                return Some(content);
+            }
+        }
+        None
+    }
+
+    pub async fn run_after_run(&self, context: &InvocationContext) {
+        for plugin in &self.plugins {
+            plugin.after_run(context).await;
+        }
+    }
+
+    pub async fn run_before_agent(&self, agent_name: &str, callback_context: &CallbackContext) -> Option<Content> {
+        for plugin in &self.plugins {
+            if let Some(content) = plugin.before_agent(agent_name, callback_context).await {
+                return Some(content);
+            }
+        }
+        None
+    }
+
+    pub async fn run_after_agent(&self, agent_name: &str, callback_context: &CallbackContext) -> Option<Content> {
+        for plugin in &self.plugins {
+            if let Some(content) = plugin.after_agent(agent_name, callback_context).await {
+                return Some(content);
+            }
+        }
+        None
+    }
+
+    pub async fn run_before_model(&self, callback_context: &CallbackContext, request: &mut LlmRequest) -> Option<LlmResponse> {
+        for plugin in &self.plugins {
+            if let Some(response) = plugin.before_model(callback_context, request).await {
+                return Some(response);
+            }
+        }
+        None
+    }
+
+    pub async fn run_after_model(&self, callback_context: &CallbackContext, response: &LlmResponse) -> Option<LlmResponse> {
+        for plugin in &self.plugins {
+            if let Some(response) = plugin.after_model(callback_context, response).await {
+                return Some(response);
+            }
+        }
+        None
+    }
+
+    pub async fn run_before_tool(
+        &self,
+        tool: &dyn BaseTool,
+        args: &mut serde_json::Value,
+        tool_context: &ToolContext,
+    ) -> Option<serde_json::Value> {
+        for plugin in &self.plugins {
+            if let Some(result) = plugin.before_tool(tool, args, tool_context).await {
+                return Some(result);
+            }
+        }
+        None
+    }
+
+    pub async fn run_after_tool(
+        &self,
+        tool: &dyn BaseTool,
+        args: &serde_json::Value,
+        tool_context: &ToolContext,
+        result: &serde_json::Value,
+    ) -> Option<serde_json::Value> {
+        for plugin in &self.plugins {
+            if let Some(result) = plugin.after_tool(tool, args, tool_context, result).await {
+                return Some(result);
+            }
+        }
+        None
+    }
+
+    pub async fn run_on_event(&self, context: &InvocationContext, event: Event) -> Event {
+        let mut event = event;
+        for plugin in &self.plugins {
+            if let Some(replacement) = plugin.on_event(context, &event).await {
+                event = replacement;
+            }
+        }
+        event
+    }
+
+    pub async fn run_on_error(&self, context: &InvocationContext, error: &AgentError) {
+        for plugin in &self.plugins {
+            plugin.on_error(context, error).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::base_agent::BeforeAgentCallback;
+    use crate::llm_agent::LlmAgent;
+    use crate::run_config::RunConfig;
+    use crate::runner::Runner;
+    use crate::testing::{function_call, function_responses, ready, unique_name, Echo, Reply, ScriptedLlm};
+    use ractor::ActorCell;
+    use serde_json::json;
+    use std::sync::atomic::Ordering;
+    use std::sync::Mutex;
+
+    type Log = Arc<Mutex<Vec<String>>>;
+
+    /// Logs the hooks it sees and answers the ones it was given answers for.
+    #[derive(Default)]
+    struct Recorder {
+        name: String,
+        log: Log,
+        agent_answer: Option<String>,
+        tool_answer: Option<serde_json::Value>,
+        shout: bool,
+    }
+
+    impl Recorder {
+        fn new(name: &str, log: &Log) -> Self {
+            Recorder {
+                name: name.to_string(),
+                log: log.clone(),
+                ..Recorder::default()
+            }
+        }
+
+        fn record(&self, hook: &str) {
+            self.log.lock().unwrap().push(format!("{} {}", self.name, hook));
+        }
+    }
+
+    #[async_trait]
+    impl Plugin for Recorder {
+        fn name(&self) -> &str {
+            &self.name
+        }
+
+        async fn before_agent(&self, _agent_name: &str, _callback_context: &CallbackContext) -> Option<Content> {
+            self.record("before_agent");
+            self.agent_answer.as_ref().map(|answer| Content::from_text("model", answer))
+        }
+
+        async fn before_tool(
+            &self,
+            _tool: &dyn BaseTool,
+            _args: &mut serde_json::Value,
+            _tool_context: &ToolContext,
+        ) -> Option<serde_json::Value> {
+            self.record("before_tool");
+            self.tool_answer.clone()
+        }
+
+        async fn on_event(&self, _context: &InvocationContext, event: &Event) -> Option<Event> {
+            let text = event.content().map(|content| content.text()).filter(|_| self.shout)?;
+            let mut event = event.clone();
+            event.content = Some(Content::from_text("model", &text.to_uppercase()));
+            Some(event)
+        }
+    }
+
+    fn recording_callback(log: &Log) -> BeforeAgentCallback {
+        let log = log.clone();
+        Arc::new(move |_| {
+            log.lock().unwrap().push("callback before_agent".to_string());
+            ready(None)
+        })
+    }
+
+    async fn run(agent: ActorCell, plugins: Vec<Arc<dyn Plugin>>) -> Vec<Event> {
+        let mut builder = Runner::builder("test".to_string(), agent);
+        for plugin in plugins {
+            builder = builder.plugin(plugin);
+        }
+        let runner = builder.build();
+        let session = runner.session_service().create_session("test", "user", None, None).await.unwrap();
+        let events = runner
+            .run_async("user", session.id(), Content::from_text("user", "hello"), RunConfig::default())
+            .await
+            .unwrap();
+        let session = runner.session_service().get_session("test", "user", session.id()).await.unwrap().unwrap();
+        let stored: Vec<String> = session.events()[1..].iter().map(|event| event.id.clone()).collect();
+        assert_eq!(stored, events.iter().map(|event| event.id.clone()).collect::<Vec<_>>());
+        events
+    }
+
+    #[tokio::test]
+    async fn plugins_run_in_order_before_the_agent_callbacks() {
+        let log = Log::default();
+        let (agent, runs) = Reply::spawn_with(
+            Reply::builder("hi".to_string())
+                .before_agent_callback(recording_callback(&log))
+                .name(unique_name("reply")),
+        )
+        .await;
+        run(agent.get_cell(), vec![Arc::new(Recorder::new("first", &log)), Arc::new(Recorder::new("second", &log))]).await;
+        assert_eq!(*log.lock().unwrap(), ["first before_agent", "second before_agent", "callback before_agent"]);
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn a_before_agent_answer_skips_later_plugins_and_the_agent() {
+        let log = Log::default();
+        let (agent, runs) = Reply::spawn_with(
+            Reply::builder("hi".to_string())
+                .before_agent_callback(recording_callback(&log))
+                .name(unique_name("reply")),
+        )
+        .await;
+        let first = Recorder {
+            agent_answer: Some("busy".to_string()),
+            ..Recorder::new("first", &log)
+        };
+        let events = run(agent.get_cell(), vec![Arc::new(first), Arc::new(Recorder::new("second", &log))]).await;
+        assert_eq!(*log.lock().unwrap(), ["first before_agent"]);
+        assert_eq!(runs.load(Ordering::SeqCst), 0);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].content().unwrap().text(), "busy");
+        assert!(events[0].final_response());
+    }
+
+    #[tokio::test]
+    async fn a_before_tool_answer_skips_later_plugins_and_the_tool() {
+        let log = Log::default();
+        let model = ScriptedLlm::new(vec![function_call("echo", json!({ "text": "hi" })), Content::from_text("model", "done")]);
+        let echo = Echo::new();
+        let agent = LlmAgent::builder(model.clone())
+            .tools(vec![echo.clone()])
+            .name(unique_name("caller"))
+            .spawn()
+            .await
+            .unwrap();
+        let first = Recorder {
+            tool_answer: Some(json!({ "cached": true })),
+            ..Recorder::new("first", &log)
+        };
+        run(agent.get_cell(), vec![Arc::new(first), Arc::new(Recorder::new("second", &log))]).await;
+        assert_eq!(*log.lock().unwrap(), ["first before_agent", "second before_agent", "first before_tool"]);
+        assert_eq!(echo.calls.load(Ordering::SeqCst), 0);
+        assert_eq!(function_responses(&model.requests()[1]), [json!({ "cached": true })]);
+    }
+
+    #[tokio::test]
+    async fn on_event_replaces_the_published_event() {
+        let (agent, _) = Reply::spawn_with(Reply::builder("hi".to_string()).name(unique_name("reply"))).await;
+        let shouter = Recorder {
+            shout: true,
+            ..Recorder::new("shouter", &Log::default())
+        };
+        let events = run(agent.get_cell(), vec![Arc::new(shouter)]).await;
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].content().unwrap().text(), "HI");
+    }
+}
@@ -0,0 +1,547 @@
+//! Agent trees spanning several processes, enabled by the `cluster` feature.
+//!
+//! A node serves its agents with `AgentHost`, which joins the agent to the
+//! process group `coagent.agent.<name>`; ractor_cluster shares the group
+//! with every connected node. Elsewhere a `RemoteAgent` with the same name
+//! stands in for it and can be a sub-agent of any orchestrator. Invocations
+//! cross the wire as the versioned JSON of `Session`, `Content` and
+//! `RunConfig`. The remote agent runs against the shipped session and its
+//! events stream back as it emits them, so the caller's runner publishes
+//! them as they happen, as for local agents; state changes travel on the
+//! events. Services and plugins are objects of the process they live in and
+//! do not cross the wire: the hosted agent runs with the session, artifact
+//! and memory services and the plugins its host was built with, so
+//! artifacts it saves stay on its node. Live mode is not available
+//! remotely.
+
+use crate::artifact_service::{BaseArtifactService, InMemoryArtifactService};
+use crate::agent::Agent;
+use crate::base_agent::{BaseAgent, BaseAgentState};
+use crate::common::{AgentError, Content, Event, Session};
+use crate::invocation_context::InvocationContext;
+use crate::memory_service::BaseMemoryService;
+use crate::plugin::{Plugin, PluginManager};
+use crate::run_config::RunConfig;
+use crate::session_service::{BaseSessionService, InMemorySessionService};
+use async_trait::async_trait;
+use ractor::rpc::CallResult;
+use ractor::{Actor, ActorCell, ActorProcessingErr, ActorRef, RpcReplyPort};
+use ractor_cluster::{NodeServer, NodeServerMessage, RactorClusterMessage};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+/// Process group the host of the agent `name` joins.
+pub fn agent_group(name: &str) -> String {
+    format!("coagent.agent.{}", name)
+}
+
+/// Finds a host serving the agent `name` on this node or a connected one.
+pub fn find_agent_host(name: &str) -> Option<ActorRef<RemoteAgentMessage>> {
+    ractor::pg::get_members(&agent_group(name)).into_iter().next().map(ActorRef::from)
+}
+
+/// Messages a host accepts from other nodes. Payloads are JSON strings.
+#[derive(RactorClusterMessage)]
+pub enum RemoteAgentMessage {
+    /// Starts a run of the hosted agent and replies with the run's id.
+    #[rpc]
+    Start(String, RpcReplyPort<String>),
+    /// Waits for the next updates of the run with the given id: the events
+    /// it emitted since the last call and, once it has finished, its result.
+    #[rpc]
+    Next(String, RpcReplyPort<String>),
+    /// Aborts the run with the given id, whose caller gave up on it.
+    Cancel(String),
+}
+
+#[derive(Serialize, Deserialize)]
+struct RemoteInvocation {
+    invocation_id: String,
+    branch: Option<String>,
+    session: Session,
+    user_content: Option<Content>,
+    run_config: RunConfig,
+    end_invocation: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RemoteReply {
+    result: Result<Vec<Event>, AgentError>,
+    end_invocation: bool,
+}
+
+/// What a run sends back, in order: the events it emits, then its reply.
+#[derive(Serialize, Deserialize)]
+enum RemoteUpdate {
+    Event(Box<Event>),
+    Done(RemoteReply),
+}
+
+struct HostedRun {
+    updates: Arc<tokio::sync::Mutex<mpsc::UnboundedReceiver<RemoteUpdate>>>,
+    task: JoinHandle<()>,
+}
+
+/// Serves a local agent to the cluster.
+pub struct AgentHost;
+
+pub struct AgentHostState {
+    agent: ActorCell,
+    services: HostServices,
+    runs: Arc<Mutex<HashMap<String, HostedRun>>>,
+}
+
+/// What the hosted agent runs with, shared by all its runs.
+#[derive(Clone)]
+pub struct HostServices {
+    session_service: Arc<dyn BaseSessionService>,
+    artifact_service: Arc<dyn BaseArtifactService>,
+    memory_service: Option<Arc<dyn BaseMemoryService>>,
+    plugin_manager: PluginManager,
+}
+
+impl AgentHost {
+    pub fn builder(agent: ActorCell) -> AgentHostBuilder {
+        AgentHostBuilder {
+            agent,
+            session_service: None,
+            artifact_service: None,
+            memory_service: None,
+            plugins: Vec::new(),
+        }
+    }
+
+    /// Spawns a host for `agent`, which must have a name, with in-memory
+    /// services and no plugins.
+    pub async fn serve(agent: ActorCell) -> Result<ActorRef<RemoteAgentMessage>, AgentError> {
+        AgentHost::builder(agent).serve().await
+    }
+
+    fn failed(error: AgentError) -> RemoteUpdate {
+        RemoteUpdate::Done(RemoteReply {
+            result: Err(error),
+            end_invocation: false,
+        })
+    }
+
+    fn start(state: &AgentHostState, payload: &str) -> Result<String, AgentError> {
+        let mut invocation: RemoteInvocation = serde_json::from_str(payload)
+            .map_err(|e| AgentError::SerializationError(format!("Invalid remote invocation: {}", e)))?;
+        invocation.session.upgrade()?;
+        let services = &state.services;
+        let mut context = InvocationContext::create(
+            services.session_service.clone(),
+            services.artifact_service.clone(),
+            invocation.invocation_id,
+            Arc::new(state.agent.clone()),
+            invocation.session,
+            invocation.user_content,
+            invocation.run_config,
+        );
+        context.set_memory_service(services.memory_service.clone());
+        context.set_plugin_manager(services.plugin_manager.clone());
+        context.set_branch(invocation.branch);
+        context.set_end_invocation(invocation.end_invocation);
+
+        let (updates, receiver) = mpsc::unbounded_channel();
+        let agent = state.agent.clone();
+        let task = tokio::spawn(async move {
+            let (event_sink, mut emitted_events) = mpsc::unbounded_channel();
+            context.set_event_sink(Some(event_sink));
+            let run = BaseAgent::run_sub_agent(&agent, context.clone());
+            tokio::pin!(run);
+            let result = loop {
+                tokio::select! {
+                    Some(event) = emitted_events.recv() => {
+                        let _ = updates.send(RemoteUpdate::Event(Box::new(event)));
+                    }
+                    result = &mut run => break result,
+                }
+            };
+            while let Ok(event) = emitted_events.try_recv() {
+                let _ = updates.send(RemoteUpdate::Event(Box::new(event)));
+            }
+            let _ = updates.send(RemoteUpdate::Done(RemoteReply {
+                result,
+                end_invocation: context.end_invocation(),
+            }));
+        });
+        let run_id = Uuid::new_v4().to_string();
+        state.runs.lock().unwrap().insert(
+            run_id.clone(),
+            HostedRun {
+                updates: Arc::new(tokio::sync::Mutex::new(receiver)),
+                task,
+            },
+        );
+        Ok(run_id)
+    }
+
+    /// Waits for at least one update of the run, then takes every update
+    /// already there. The run is forgotten once its reply is taken.
+    async fn next(runs: Arc<Mutex<HashMap<String, HostedRun>>>, run_id: String) -> Vec<RemoteUpdate> {
+        let updates = match runs.lock().unwrap().get(&run_id) {
+            Some(run) => run.updates.clone(),
+            None => return vec![Self::failed(AgentError::AgentFailed(format!("No remote run {}", run_id)))],
+        };
+        let mut updates = updates.lock().await;
+        let mut batch = Vec::new();
+        let first = updates
+            .recv()
+            .await
+            .unwrap_or_else(|| Self::failed(AgentError::AgentFailed(format!("Remote run {} stopped", run_id))));
+        batch.push(first);
+        while !matches!(batch.last(), Some(RemoteUpdate::Done(_))) {
+            match updates.try_recv() {
+                Ok(update) => batch.push(update),
+                Err(_) => break,
+            }
+        }
+        if matches!(batch.last(), Some(RemoteUpdate::Done(_))) {
+            runs.lock().unwrap().remove(&run_id);
+        }
+        batch
+    }
+}
+
+#[async_trait]
+impl Actor for AgentHost {
+    type Msg = RemoteAgentMessage;
+    type State = AgentHostState;
+    type Arguments = (ActorCell, HostServices);
+
+    async fn pre_start(
+        &self,
+        this_actor: ActorRef<Self::Msg>,
+        (agent, services): Self::Arguments,
+    ) -> Result<Self::State, ActorProcessingErr> {
+        let name = agent
+            .get_name()
+            .ok_or_else(|| ActorProcessingErr::from("Only named agents can be served to the cluster"))?;
+        ractor::pg::join(agent_group(&name), vec![this_actor.get_cell()]);
+        Ok(AgentHostState {
+            agent,
+            services,
+            runs: Arc::default(),
+        })
+    }
+
+    async fn post_stop(&self, _this_actor: ActorRef<Self::Msg>, state: &mut Self::State) -> Result<(), ActorProcessingErr> {
+        for (_, run) in state.runs.lock().unwrap().drain() {
+            run.task.abort();
+        }
+        Ok(())
+    }
+
+    async fn handle(
+        &self,
+        _this_actor: ActorRef<Self::Msg>,
+        message: Self::Msg,
+        state: &mut Self::State,
+    ) -> Result<(), ActorProcessingErr> {
+        match message {
+            RemoteAgentMessage::Start(payload, reply) => {
+                let body = match AgentHost::start(state, &payload) {
+                    Ok(run_id) => serde_json::json!({ "run_id": run_id }),
+                    Err(error) => serde_json::json!({ "error": error }),
+                };
+                let _ = reply.send(body.to_string());
+            }
+            RemoteAgentMessage::Next(run_id, reply) => {
+                // Waits outside the host so one node can serve several runs
+                // at once.
+                let runs = state.runs.clone();
+                tokio::spawn(async move {
+                    let updates = AgentHost::next(runs, run_id).await;
+                    let body = serde_json::to_string(&updates).unwrap_or_else(|e| {
+                        serde_json::json!([{ "Done": {
+                            "result": { "Err": { "SerializationError": e.to_string() } },
+                            "end_invocation": false,
+                        } }])
+                        .to_string()
+                    });
+                    let _ = reply.send(body);
+                });
+            }
+            RemoteAgentMessage::Cancel(run_id) => {
+                if let Some(run) = state.runs.lock().unwrap().remove(&run_id) {
+                    run.task.abort();
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+pub struct AgentHostBuilder {
+    agent: ActorCell,
+    session_service: Option<Arc<dyn BaseSessionService>>,
+    artifact_service: Option<Arc<dyn BaseArtifactService>>,
+    memory_service: Option<Arc<dyn BaseMemoryService>>,
+    plugins: Vec<Arc<dyn Plugin>>,
+}
+
+impl AgentHostBuilder {
+    pub fn session_service(mut self, session_service: Arc<dyn BaseSessionService>) -> Self {
+        self.session_service = Some(session_service);
+        self
+    }
+
+    pub fn artifact_service(mut self, artifact_service: Arc<dyn BaseArtifactService>) -> Self {
+        self.artifact_service = Some(artifact_service);
+        self
+    }
+
+    pub fn memory_service(mut self, memory_service: Arc<dyn BaseMemoryService>) -> Self {
+        self.memory_service = Some(memory_service);
+        self
+    }
+
+    pub fn plugin(mut self, plugin: Arc<dyn Plugin>) -> Self {
+        self.plugins.push(plugin);
+        self
+    }
+
+    /// Spawns the host. The agent must have a name.
+    pub async fn serve(self) -> Result<ActorRef<RemoteAgentMessage>, AgentError> {
+        let services = HostServices {
+            session_service: self.session_service.unwrap_or_else(|| Arc::new(InMemorySessionService::new())),
+            artifact_service: self.artifact_service.unwrap_or_else(|| Arc::new(InMemoryArtifactService::new())),
+            memory_service: self.memory_service,
+            plugin_manager: PluginManager::new(self.plugins),
+        };
+        let (host, _) = Actor::spawn(None, AgentHost, (self.agent, services))
+            .await
+            .map_err(|e| AgentError::AgentFailed(format!("Failed to start agent host: {}", e)))?;
+        Ok(host)
+    }
+}
+
+/// Local stand-in for an agent hosted on another node, found by name when
+/// it runs. A node that disconnects before replying fails the run with
+/// `AgentError::AgentFailed`. A run cancelled here, such as an interrupted
+/// live turn, is aborted on the host as well.
+///
+/// The agent's name, set with the builder's `name`, is the name of the
+/// agent on the remote node unless `remote_name` gives another.
+#[derive(Clone, Agent)]
+pub struct RemoteAgent {
+    /// Fails the run when the remote agent takes longer than `timeout`.
+    timeout: Option<Duration>,
+    /// Name of the agent on the remote node, when it differs from this one's.
+    remote_name: Option<String>,
+}
+
+/// Cancels the remote run unless it finished, for runs dropped on the way.
+struct RemoteRun {
+    host: ActorRef<RemoteAgentMessage>,
+    run_id: String,
+    finished: bool,
+}
+
+impl Drop for RemoteRun {
+    fn drop(&mut self) {
+        if !self.finished {
+            let _ = self.host.send_message(RemoteAgentMessage::Cancel(self.run_id.clone()));
+        }
+    }
+}
+
+impl RemoteAgent {
+    async fn call(
+        &self,
+        name: &str,
+        host: &ActorRef<RemoteAgentMessage>,
+        message: impl FnOnce(RpcReplyPort<String>) -> RemoteAgentMessage,
+        deadline: Option<tokio::time::Instant>,
+    ) -> Result<String, AgentError> {
+        let timeout = deadline.map(|deadline| deadline.saturating_duration_since(tokio::time::Instant::now()));
+        match host.call(message, timeout).await {
+            Ok(CallResult::Success(reply)) => Ok(reply),
+            Ok(CallResult::Timeout) => Err(AgentError::AgentFailed(format!("Remote agent {} timed out", name))),
+            Ok(CallResult::SenderError) | Err(_) => Err(AgentError::AgentFailed(format!(
+                "Lost the node hosting agent {} before it replied",
+                name
+            ))),
+        }
+    }
+
+    /// Runs the agent `name` on `host`, streaming its events to `context`.
+    async fn run_on(&self, name: &str, host: ActorRef<RemoteAgentMessage>, context: &InvocationContext) -> Result<Vec<Event>, AgentError> {
+        let deadline = self.timeout.map(|timeout| tokio::time::Instant::now() + timeout);
+        let invocation = RemoteInvocation {
+            invocation_id: context.invocation_id().to_string(),
+            // The hosted agent extends the branch with its name again.
+            branch: context.branch().and_then(|branch| branch.rsplit_once('.')).map(|(parent, _)| parent.to_string()),
+            session: context.session().clone(),
+            user_content: context.user_content().cloned(),
+            run_config: context.run_config().clone(),
+            end_invocation: context.end_invocation(),
+        };
+        let payload = serde_json::to_string(&invocation).map_err(|e| AgentError::SerializationError(e.to_string()))?;
+
+        let started = self.call(name, &host, |reply| RemoteAgentMessage::Start(payload, reply), deadline).await?;
+        let started: serde_json::Value = serde_json::from_str(&started)
+            .map_err(|e| AgentError::SerializationError(format!("Invalid reply from remote agent {}: {}", name, e)))?;
+        if let Some(error) = started.get("error") {
+            return Err(serde_json::from_value(error.clone())
+                .unwrap_or_else(|_| AgentError::AgentFailed(format!("Remote agent {} failed to start: {}", name, error))));
+        }
+        let mut run = RemoteRun {
+            host: host.clone(),
+            run_id: started["run_id"].as_str().unwrap_or_default().to_string(),
+            finished: false,
+        };
+
+        loop {
+            let run_id = run.run_id.clone();
+            let updates = self.call(name, &host, |reply| RemoteAgentMessage::Next(run_id, reply), deadline).await?;
+            let updates: Vec<RemoteUpdate> = serde_json::from_str(&updates)
+                .map_err(|e| AgentError::SerializationError(format!("Invalid reply from remote agent {}: {}", name, e)))?;
+            for update in updates {
+                let reply = match update {
+                    RemoteUpdate::Event(mut event) => {
+                        event.upgrade()?;
+                        context.emit_event(&event);
+                        continue;
+                    }
+                    RemoteUpdate::Done(reply) => reply,
+                };
+                run.finished = true;
+                if reply.end_invocation {
+                    context.set_end_invocation(true);
+                }
+                let mut events = reply.result?;
+                for event in &mut events {
+                    event.upgrade()?;
+                }
+                return Ok(events);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Agent for RemoteAgent {
+    async fn run_async_impl(&self, state: &BaseAgentState, context: &InvocationContext) -> Result<Vec<Event>, AgentError> {
+        let name = self.remote_name.as_deref().unwrap_or(state.base.name());
+        let host = find_agent_host(name)
+            .ok_or_else(|| AgentError::AgentFailed(format!("No connected node hosts agent {}", name)))?;
+        self.run_on(name, host, context).await
+    }
+
+    async fn run_live_impl(&self, state: &BaseAgentState, _context: &InvocationContext) -> Result<Vec<Event>, AgentError> {
+        Err(AgentError::UnsupportedOperation(format!("Remote agent {} cannot run live", state.base.name())))
+    }
+}
+
+/// A ractor_cluster node: listens for other nodes and connects to them.
+#[derive(Clone)]
+pub struct ClusterNode {
+    server: ActorRef<NodeServerMessage>,
+}
+
+impl ClusterNode {
+    /// Starts a node listening on `port`. Nodes only accept peers that
+    /// present the same `cookie`.
+    pub async fn start(name: &str, hostname: &str, port: u16, cookie: &str) -> Result<Self, AgentError> {
+        let server = NodeServer::new(port, cookie.to_string(), name.to_string(), hostname.to_string(), None, None);
+        let (server, _) = Actor::spawn(None, server, ())
+            .await
+            .map_err(|e| AgentError::AgentFailed(format!("Failed to start cluster node {}: {}", name, e)))?;
+        Ok(ClusterNode { server })
+    }
+
+    pub fn server(&self) -> &ActorRef<NodeServerMessage> {
+        &self.server
+    }
+
+    /// Connects to the node listening at `address`, e.g. `127.0.0.1:4697`.
+    pub async fn connect(&self, address: &str) -> Result<(), AgentError> {
+        ractor_cluster::client_connect(&self.server, address)
+            .await
+            .map_err(|e| AgentError::AgentFailed(format!("Failed to connect to node at {}: {}", address, e)))
+    }
+
+    /// Waits until some node serves the agent `name`. Hosts become visible
+    /// shortly after the nodes have authenticated each other.
+    pub async fn wait_for_agent(&self, name: &str, timeout: Duration) -> Result<(), AgentError> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        while find_agent_host(name).is_none() {
+            if tokio::time::Instant::now() >= deadline {
+                return Err(AgentError::AgentFailed(format!("No node served agent {} within {:?}", name, timeout)));
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        Ok(())
+    }
+
+    pub fn stop(&self) {
+        self.server.stop(None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{context, run, unique_name, Reply};
+    use std::sync::atomic::Ordering;
+
+    fn free_port() -> u16 {
+        std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+    }
+
+    #[tokio::test]
+    async fn agents_run_across_connected_nodes() {
+        let (port_a, port_b) = (free_port(), free_port());
+        let node_a = ClusterNode::start(&unique_name("node-a"), "localhost", port_a, "cookie").await.unwrap();
+        let node_b = ClusterNode::start(&unique_name("node-b"), "localhost", port_b, "cookie").await.unwrap();
+        let worker_name = unique_name("worker");
+        let (worker, runs) = Reply::spawn_with(Reply::builder("from node a".to_string()).name(worker_name.clone())).await;
+        AgentHost::serve(worker.get_cell()).await.unwrap();
+
+        node_b.connect(&format!("127.0.0.1:{}", port_a)).await.unwrap();
+        // The host shows up through the session with node a once the nodes
+        // have authenticated each other.
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+        let remote_host = loop {
+            let members = ractor::pg::get_members(&agent_group(&worker_name));
+            if let Some(member) = members.into_iter().find(|member| !member.get_id().is_local()) {
+                break ActorRef::<RemoteAgentMessage>::from(member);
+            }
+            assert!(tokio::time::Instant::now() < deadline, "the host never reached node b");
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        };
+        node_b.wait_for_agent(&worker_name, Duration::from_secs(1)).await.unwrap();
+
+        let stand_in = RemoteAgent::builder()
+            .remote_name(worker_name.clone())
+            .timeout(Duration::from_secs(10))
+            .name(unique_name("stand-in"))
+            .spawn()
+            .await
+            .unwrap();
+        let events = run(&stand_in, "hi").await.unwrap();
+        assert_eq!(events.last().unwrap().content.as_ref().unwrap().text(), "from node a");
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+
+        // Both nodes live in this process, so the stand-in may have found the
+        // host directly; run once more through the session between them.
+        let mut context = context(&stand_in).await;
+        context.set_user_content(Some(Content::from_text("user", "hi")));
+        let (event_sink, mut streamed) = mpsc::unbounded_channel();
+        context.set_event_sink(Some(event_sink));
+        let events = RemoteAgent::builder().build().run_on(&worker_name, remote_host, &context).await.unwrap();
+        assert_eq!(events.last().unwrap().content.as_ref().unwrap().text(), "from node a");
+        assert_eq!(streamed.try_recv().unwrap().id, events.last().unwrap().id);
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+
+        node_b.stop();
+        node_a.stop();
+    }
+}
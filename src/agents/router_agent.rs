@@ -0,0 +1,369 @@
+//! An agent that hands each request to one of its sub-agents. The choice is
+//! made by a `RouteStrategy`: a closure over the invocation context, a table
+//! of keyword and regex rules matched against the user's message, or a model
+//! asked to pick from the sub-agents' descriptions. Each choice is recorded
+//! as an event carrying an `AgentRoute` before the chosen sub-agent runs.
+//! When the strategy finds no match the router falls back to its default
+//! sub-agent.
+
+use crate::agent::Agent;
+use crate::agent_metrics;
+use crate::base_agent::{BaseAgent, BaseAgentState};
+use crate::common::{AgentError, AgentRoute, Event};
+use crate::invocation_context::InvocationContext;
+use crate::models::{BaseLlm, LlmRequest};
+use crate::schema;
+use async_trait::async_trait;
+use regex::Regex;
+use serde_json::json;
+use std::sync::Arc;
+
+/// The sub-agent picked for a request and why.
+#[derive(Clone, Debug)]
+pub struct RouteDecision {
+    pub agent: String,
+    pub reason: String,
+}
+
+impl RouteDecision {
+    pub fn new(agent: &str, reason: &str) -> Self {
+        RouteDecision {
+            agent: agent.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+}
+
+type RouteFn = Arc<dyn Fn(&InvocationContext) -> Option<RouteDecision> + Send + Sync>;
+
+#[derive(Clone)]
+enum Matcher {
+    Keywords(Vec<String>),
+    Regex(Regex),
+}
+
+/// Sends requests whose text matches to `agent`.
+#[derive(Clone)]
+pub struct RouteRule {
+    agent: String,
+    matcher: Matcher,
+}
+
+impl RouteRule {
+    /// Matches when the text contains any of `keywords`, ignoring case.
+    pub fn keywords(agent: &str, keywords: &[&str]) -> Self {
+        RouteRule {
+            agent: agent.to_string(),
+            matcher: Matcher::Keywords(keywords.iter().map(|keyword| keyword.to_lowercase()).collect()),
+        }
+    }
+
+    pub fn regex(agent: &str, pattern: &str) -> Result<Self, AgentError> {
+        let regex = Regex::new(pattern).map_err(|e| AgentError::ConfigError(format!("Invalid route pattern '{}': {}", pattern, e)))?;
+        Ok(RouteRule {
+            agent: agent.to_string(),
+            matcher: Matcher::Regex(regex),
+        })
+    }
+
+    pub fn agent(&self) -> &str {
+        &self.agent
+    }
+
+    fn matches(&self, text: &str) -> Option<String> {
+        match &self.matcher {
+            Matcher::Keywords(keywords) => {
+                let text = text.to_lowercase();
+                keywords
+                    .iter()
+                    .find(|keyword| text.contains(keyword.as_str()))
+                    .map(|keyword| format!("matched keyword '{}'", keyword))
+            }
+            Matcher::Regex(regex) => regex.is_match(text).then(|| format!("matched pattern '{}'", regex.as_str())),
+        }
+    }
+}
+
+#[derive(Clone)]
+enum StrategyKind {
+    Closure(RouteFn),
+    Rules(Vec<RouteRule>),
+    Llm(Arc<dyn BaseLlm>),
+}
+
+/// How a `RouterAgent` picks a sub-agent.
+#[derive(Clone)]
+pub struct RouteStrategy {
+    kind: StrategyKind,
+}
+
+impl RouteStrategy {
+    /// Routes with `route`; `None` falls back to the default sub-agent.
+    pub fn closure(route: impl Fn(&InvocationContext) -> Option<RouteDecision> + Send + Sync + 'static) -> Self {
+        RouteStrategy {
+            kind: StrategyKind::Closure(Arc::new(route)),
+        }
+    }
+
+    /// Routes to the agent of the first rule matching the user's message.
+    pub fn rules(rules: Vec<RouteRule>) -> Self {
+        RouteStrategy {
+            kind: StrategyKind::Rules(rules),
+        }
+    }
+
+    /// Asks `model` to classify the user's message, given the name and
+    /// description of every sub-agent.
+    pub fn llm(model: Arc<dyn BaseLlm>) -> Self {
+        RouteStrategy {
+            kind: StrategyKind::Llm(model),
+        }
+    }
+}
+
+/// Runs the one sub-agent its strategy picks for the request.
+#[derive(Clone, Agent)]
+pub struct RouterAgent {
+    #[agent(required)]
+    strategy: RouteStrategy,
+    /// Sub-agent to run when the strategy finds no match.
+    default_agent: Option<String>,
+}
+
+impl RouterAgent {
+    pub fn strategy(&self) -> &RouteStrategy {
+        &self.strategy
+    }
+
+    pub fn default_agent(&self) -> Option<&str> {
+        self.default_agent.as_deref()
+    }
+
+    /// Decides the route, or says why the strategy found none.
+    async fn decide(&self, state: &BaseAgentState, context: &InvocationContext) -> Result<Result<RouteDecision, String>, AgentError> {
+        let text = context.user_content().map(|content| content.text()).unwrap_or_default();
+        match &self.strategy.kind {
+            StrategyKind::Closure(route) => Ok(route(context).ok_or_else(|| "the route function found no match".to_string())),
+            StrategyKind::Rules(rules) => Ok(rules
+                .iter()
+                .find_map(|rule| rule.matches(&text).map(|reason| RouteDecision::new(&rule.agent, &reason)))
+                .ok_or_else(|| "no rule matched the request".to_string())),
+            StrategyKind::Llm(model) => self.classify(state, context, model.as_ref()).await,
+        }
+    }
+
+    async fn classify(
+        &self,
+        state: &BaseAgentState,
+        context: &InvocationContext,
+        model: &dyn BaseLlm,
+    ) -> Result<Result<RouteDecision, String>, AgentError> {
+        let mut agents = Vec::new();
+        let mut names = Vec::new();
+        for child in state.supervisor.children() {
+            let info = BaseAgent::get_info(&child).await?;
+            agents.push(format!("- {}: {}", info.name, info.description));
+            names.push(info.name);
+        }
+        let response_schema = json!({
+            "type": "object",
+            "properties": {
+                "agent": { "enum": names },
+                "reason": { "type": "string" }
+            },
+            "required": ["agent", "reason"]
+        });
+        let request = LlmRequest {
+            model: model.model().to_string(),
+            system_instruction: Some(format!(
+                "Choose the agent best suited to handle the user's request.\n\nAgents:\n{}\n\n\
+                 Reply with only a JSON value that matches this JSON Schema:\n{}",
+                agents.join("\n"),
+                response_schema
+            )),
+            contents: context.user_content().cloned().into_iter().collect(),
+            tools: Vec::new(),
+            response_schema: Some(response_schema.clone()),
+        };
+        let mut context = context.clone();
+        let response = state.base.call_llm(model, request, &mut context).await?;
+        if let Some(error) = response.error_message {
+            return Err(AgentError::ModelError(error));
+        }
+        let text = response.content.map(|content| content.text()).unwrap_or_default();
+        let value = match schema::parse_json_output(&text) {
+            Ok(value) => value,
+            Err(error) => return Ok(Err(format!("the model's choice could not be parsed: {}", error))),
+        };
+        let errors = schema::validate(&response_schema, &value);
+        if !errors.is_empty() {
+            return Ok(Err(format!("the model's choice is invalid: {}", errors.join("; "))));
+        }
+        Ok(Ok(RouteDecision::new(
+            value["agent"].as_str().unwrap_or_default(),
+            value["reason"].as_str().unwrap_or_default(),
+        )))
+    }
+}
+
+#[async_trait]
+impl Agent for RouterAgent {
+    async fn run_async_impl(&self, state: &BaseAgentState, context: &InvocationContext) -> Result<Vec<Event>, AgentError> {
+        let name = state.base.name();
+        // A resumed invocation goes back to the sub-agent that was routed to.
+        if let Some(child) = context.resumed_child().and_then(|resumed| state.supervisor.position(&resumed)) {
+            return state.supervisor.run_child(child, context).await;
+        }
+        let (decision, fallback) = match self.decide(state, context).await? {
+            Ok(decision) => (decision, false),
+            Err(reason) => match &self.default_agent {
+                Some(default_agent) => (RouteDecision::new(default_agent, &reason), true),
+                None => {
+                    return Err(AgentError::AgentFailed(format!(
+                        "Router {} found no sub-agent for the request: {}",
+                        name, reason
+                    )));
+                }
+            },
+        };
+        let child = state
+            .supervisor
+            .position(&decision.agent)
+            .ok_or_else(|| AgentError::ConfigError(format!("Router {} has no sub-agent '{}'", name, decision.agent)))?;
+        agent_metrics::record_transfer(name, &decision.agent);
+
+        let event = Event::builder()
+            .invocation_id(context.invocation_id().to_string())
+            .author(name.to_string())
+            .branch(context.branch().map(|s| s.to_string()))
+            .route(Some(AgentRoute {
+                agent: decision.agent,
+                reason: decision.reason,
+                fallback,
+            }))
+            .build();
+        context.emit_event(&event);
+        let mut context = context.clone();
+        context.session_mut().append_event(event.clone());
+
+        let mut events = vec![event];
+        events.extend(state.supervisor.run_child(child, &context).await?);
+        Ok(events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::base_agent::BaseAgentMessage;
+    use crate::common::Content;
+    use crate::testing::{run, unique_name, Reply, ScriptedLlm};
+    use ractor::ActorRef;
+
+    #[test]
+    fn keyword_rules_ignore_case() {
+        let rule = RouteRule::keywords("billing", &["Invoice", "refund"]);
+        assert_eq!(rule.matches("Where is my INVOICE?").as_deref(), Some("matched keyword 'invoice'"));
+        assert_eq!(rule.matches("I want a Refund").as_deref(), Some("matched keyword 'refund'"));
+        assert_eq!(rule.matches("My password expired"), None);
+    }
+
+    #[test]
+    fn regex_rules_match_their_pattern() {
+        let rule = RouteRule::regex("orders", r"order #\d+").unwrap();
+        assert_eq!(rule.matches("What about order #123?").as_deref(), Some(r"matched pattern 'order #\d+'"));
+        assert_eq!(rule.matches("What about my order?"), None);
+        assert!(matches!(RouteRule::regex("orders", "order (#"), Err(AgentError::ConfigError(_))));
+    }
+
+    /// A router over a billing and a support agent, given their names, that
+    /// falls back to support when `fallback` is set.
+    async fn router(strategy: impl FnOnce(&str, &str) -> RouteStrategy, fallback: bool) -> ActorRef<BaseAgentMessage> {
+        let (billing, support) = (unique_name("billing"), unique_name("support"));
+        let (billing_agent, _) = Reply::spawn_with(Reply::builder("billing here".to_string()).name(billing.clone())).await;
+        let (support_agent, _) = Reply::spawn_with(Reply::builder("support here".to_string()).name(support.clone())).await;
+        let mut builder = RouterAgent::builder(strategy(&billing, &support))
+            .name(unique_name("router"))
+            .sub_agents(vec![Arc::new(billing_agent.get_cell()), Arc::new(support_agent.get_cell())]);
+        if fallback {
+            builder = builder.default_agent(support);
+        }
+        builder.spawn().await.unwrap()
+    }
+
+    fn route(events: &[Event]) -> &AgentRoute {
+        events[0].route.as_ref().unwrap()
+    }
+
+    fn answers(events: &[Event]) -> Vec<String> {
+        events.iter().filter_map(|event| event.content()).map(|content| content.text()).collect()
+    }
+
+    #[tokio::test]
+    async fn the_first_matching_rule_picks_the_agent() {
+        let router = router(
+            |billing, support| {
+                RouteStrategy::rules(vec![
+                    RouteRule::keywords(billing, &["invoice"]),
+                    RouteRule::keywords(support, &["invoice", "password"]),
+                ])
+            },
+            false,
+        )
+        .await;
+
+        let events = run(&router, "My invoice is wrong").await.unwrap();
+        assert!(route(&events).agent.starts_with("billing"));
+        assert_eq!(route(&events).reason, "matched keyword 'invoice'");
+        assert!(!route(&events).fallback);
+        assert_eq!(answers(&events), ["billing here"]);
+
+        let events = run(&router, "I forgot my password").await.unwrap();
+        assert!(route(&events).agent.starts_with("support"));
+        assert_eq!(answers(&events), ["support here"]);
+    }
+
+    #[tokio::test]
+    async fn unmatched_requests_fail_without_a_default_agent() {
+        let router = router(|billing, _| RouteStrategy::rules(vec![RouteRule::keywords(billing, &["invoice"])]), false).await;
+        let Err(AgentError::AgentFailed(message)) = run(&router, "Hello").await else {
+            panic!("an unmatched request without a default agent was routed");
+        };
+        assert!(message.contains("no rule matched the request"), "{}", message);
+    }
+
+    #[tokio::test]
+    async fn unmatched_requests_fall_back_to_the_default_agent() {
+        let router = router(|_, _| RouteStrategy::closure(|_| None), true).await;
+        let events = run(&router, "Hello").await.unwrap();
+        assert!(route(&events).agent.starts_with("support"));
+        assert!(route(&events).fallback);
+        assert_eq!(route(&events).reason, "the route function found no match");
+        assert_eq!(answers(&events), ["support here"]);
+    }
+
+    #[tokio::test]
+    async fn the_model_picks_an_agent_or_falls_back_when_its_choice_is_invalid() {
+        let router = router(
+            |billing, _| {
+                let reply = json!({ "agent": billing, "reason": "asks about money" }).to_string();
+                RouteStrategy::llm(ScriptedLlm::new(vec![
+                    Content::from_text("model", &reply),
+                    Content::from_text("model", r#"{"agent": "nobody", "reason": "?"}"#),
+                ]))
+            },
+            true,
+        )
+        .await;
+
+        let events = run(&router, "How much do I owe?").await.unwrap();
+        assert!(route(&events).agent.starts_with("billing"));
+        assert_eq!(route(&events).reason, "asks about money");
+        assert!(!route(&events).fallback);
+
+        let events = run(&router, "How much do I owe?").await.unwrap();
+        assert!(route(&events).fallback);
+        assert!(route(&events).reason.starts_with("the model's choice is invalid"), "{}", route(&events).reason);
+        assert_eq!(answers(&events), ["support here"]);
+    }
+}
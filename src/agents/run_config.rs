@@ -1,23 +1,55 @@
-#[derive(Clone, Debug)]
+use crate::common::{check_schema_version, AgentError, SCHEMA_VERSION};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
 pub struct RunConfig {
+    schema_version: u32,
     max_llm_calls: i32,
+    capture_tool_arguments: bool,
 }
 
 impl RunConfig {
     pub fn builder() -> RunConfigBuilder {
         RunConfigBuilder {
             max_llm_calls: 500,
+            capture_tool_arguments: false,
         }
     }
 
     pub fn max_llm_calls(&self) -> i32 {
         self.max_llm_calls
     }
+
+    /// Whether tool spans record the full call arguments.
+    pub fn capture_tool_arguments(&self) -> bool {
+        self.capture_tool_arguments
+    }
+
+    pub fn to_json(&self) -> Result<String, AgentError> {
+        serde_json::to_string(self).map_err(|e| AgentError::SerializationError(e.to_string()))
+    }
+
+    /// Reads a run config; missing fields take their default values.
+    pub fn from_json(json: &str) -> Result<Self, AgentError> {
+        let mut run_config: RunConfig = serde_json::from_str(json)
+            .map_err(|e| AgentError::SerializationError(format!("Invalid run config: {}", e)))?;
+        check_schema_version("RunConfig", run_config.schema_version)?;
+        run_config.schema_version = SCHEMA_VERSION;
+        Ok(run_config)
+    }
+}
+
+impl Default for RunConfig {
+    fn default() -> Self {
+        RunConfig::builder().build()
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct RunConfigBuilder {
     max_llm_calls: i32,
+    capture_tool_arguments: bool,
 }
 
 impl RunConfigBuilder {
@@ -26,9 +58,32 @@ impl RunConfigBuilder {
         self
     }
 
+    /// Records the full call arguments on tool spans. They may hold user data,
+    /// so this is off by default.
+    pub fn set_capture_tool_arguments(mut self, capture_tool_arguments: bool) -> Self {
+        self.capture_tool_arguments = capture_tool_arguments;
+        self
+    }
+
     pub fn build(self) -> RunConfig {
         RunConfig {
+            schema_version: SCHEMA_VERSION,
             max_llm_calls: self.max_llm_calls,
+            capture_tool_arguments: self.capture_tool_arguments,
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tool_arguments_are_only_captured_when_asked() {
+        assert!(!RunConfig::default().capture_tool_arguments());
+        assert!(!RunConfig::from_json(r#"{"max_llm_calls": 10}"#).unwrap().capture_tool_arguments());
+        let run_config = RunConfig::builder().set_capture_tool_arguments(true).build();
+        let run_config = RunConfig::from_json(&run_config.to_json().unwrap()).unwrap();
+        assert!(run_config.capture_tool_arguments());
+    }
+}
@@ -0,0 +1,423 @@
+use crate::artifact_service::{BaseArtifactService, InMemoryArtifactService};
+use crate::base_agent::BaseAgent;
+use crate::common::{AgentError, Content, Event, LiveRequestQueue, Session, ToolDecision};
+use crate::invocation_context::InvocationContext;
+use crate::memory_service::BaseMemoryService;
+use crate::plugin::{Plugin, PluginManager};
+use crate::run_config::RunConfig;
+use crate::session_service::{BaseSessionService, InMemorySessionService};
+use crate::telemetry;
+use ractor::ActorCell;
+use std::collections::HashSet;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tracing::Instrument;
+
+/// What starts an invocation: one user message, a live session reading its
+/// user turns from a queue, or the user's decisions on the tool calls an
+/// invocation paused for.
+enum InvocationInput {
+    Message(Content),
+    Live(LiveRequestQueue),
+    Resume(Vec<ToolDecision>),
+}
+
+#[derive(Clone, Debug)]
+pub struct Runner {
+    app_name: String,
+    agent: ActorCell,
+    session_service: Arc<dyn BaseSessionService>,
+    artifact_service: Arc<dyn BaseArtifactService>,
+    memory_service: Option<Arc<dyn BaseMemoryService>>,
+    plugin_manager: PluginManager,
+}
+
+impl Runner {
+    pub fn builder(app_name: String, agent: ActorCell) -> RunnerBuilder {
+        RunnerBuilder {
+            app_name,
+            agent,
+            session_service: None,
+            artifact_service: None,
+            memory_service: None,
+            plugins: Vec::new(),
+        }
+    }
+
+    pub fn app_name(&self) -> &str {
+        &self.app_name
+    }
+
+    pub fn agent(&self) -> &ActorCell {
+        &self.agent
+    }
+
+    pub fn session_service(&self) -> &Arc<dyn BaseSessionService> {
+        &self.session_service
+    }
+
+    pub fn artifact_service(&self) -> &Arc<dyn BaseArtifactService> {
+        &self.artifact_service
+    }
+
+    pub fn memory_service(&self) -> Option<&Arc<dyn BaseMemoryService>> {
+        self.memory_service.as_ref()
+    }
+
+    pub fn plugin_manager(&self) -> &PluginManager {
+        &self.plugin_manager
+    }
+
+    pub async fn run_async(
+        &self,
+        user_id: &str,
+        session_id: &str,
+        new_message: Content,
+        run_config: RunConfig,
+    ) -> Result<Vec<Event>, AgentError> {
+        Self::collect(self.run_stream(user_id, session_id, new_message, run_config)).await
+    }
+
+    async fn collect(mut stream: mpsc::Receiver<Result<Event, AgentError>>) -> Result<Vec<Event>, AgentError> {
+        let mut events = Vec::new();
+        while let Some(event) = stream.recv().await {
+            events.push(event?);
+        }
+        Ok(events)
+    }
+
+    /// Runs the invocation in the background and yields its events as soon
+    /// as the agents produce them, each already appended to the session. An
+    /// error ends the stream.
+    pub fn run_stream(
+        &self,
+        user_id: &str,
+        session_id: &str,
+        new_message: Content,
+        run_config: RunConfig,
+    ) -> mpsc::Receiver<Result<Event, AgentError>> {
+        self.spawn_invocation(user_id, session_id, InvocationInput::Message(new_message), run_config)
+    }
+
+    /// Runs the agent tree live: the agents read user turns from
+    /// `live_request_queue` until the client closes it, and their events
+    /// (including the user turns) are yielded as in `run_stream`.
+    pub fn run_live(
+        &self,
+        user_id: &str,
+        session_id: &str,
+        live_request_queue: LiveRequestQueue,
+        run_config: RunConfig,
+    ) -> mpsc::Receiver<Result<Event, AgentError>> {
+        self.spawn_invocation(user_id, session_id, InvocationInput::Live(live_request_queue), run_config)
+    }
+
+    pub async fn resume_async(
+        &self,
+        user_id: &str,
+        session_id: &str,
+        decisions: Vec<ToolDecision>,
+        run_config: RunConfig,
+    ) -> Result<Vec<Event>, AgentError> {
+        Self::collect(self.resume_stream(user_id, session_id, decisions, run_config)).await
+    }
+
+    /// Resumes the invocation that paused in the session for tool calls to
+    /// be confirmed, given the user's decision on every held call. The agent
+    /// tree runs again from the root: workflow agents skip the sub-agents
+    /// that finished before the pause and go down to the agent that paused.
+    /// Its approved calls run, with the decision's arguments when it has
+    /// some, rejected ones are reported to the model as errors, and the
+    /// invocation carries on from there. Events are yielded as in
+    /// `run_stream`.
+    pub fn resume_stream(
+        &self,
+        user_id: &str,
+        session_id: &str,
+        decisions: Vec<ToolDecision>,
+        run_config: RunConfig,
+    ) -> mpsc::Receiver<Result<Event, AgentError>> {
+        self.spawn_invocation(user_id, session_id, InvocationInput::Resume(decisions), run_config)
+    }
+
+    fn spawn_invocation(
+        &self,
+        user_id: &str,
+        session_id: &str,
+        input: InvocationInput,
+        run_config: RunConfig,
+    ) -> mpsc::Receiver<Result<Event, AgentError>> {
+        let (sender, receiver) = mpsc::channel(64);
+        let runner = self.clone();
+        let user_id = user_id.to_string();
+        let session_id = session_id.to_string();
+        tokio::spawn(
+            async move {
+                if let Err(error) = runner.start_invocation(&user_id, &session_id, input, run_config, &sender).await {
+                    let _ = sender.send(Err(error)).await;
+                }
+            }
+            .in_current_span(),
+        );
+        receiver
+    }
+
+    async fn start_invocation(
+        &self,
+        user_id: &str,
+        session_id: &str,
+        input: InvocationInput,
+        run_config: RunConfig,
+        output: &mpsc::Sender<Result<Event, AgentError>>,
+    ) -> Result<(), AgentError> {
+        let session = self
+            .session_service
+            .get_session(&self.app_name, user_id, session_id)
+            .await?
+            .ok_or_else(|| AgentError::SessionNotFound(format!("Session {} not found", session_id)))?;
+
+        let invocation_id = match (&input, session.pending_confirmation()) {
+            (InvocationInput::Resume(_), Some(paused)) => paused.invocation_id.clone(),
+            (InvocationInput::Resume(_), None) => {
+                return Err(AgentError::AgentFailed(format!(
+                    "Session {} has no invocation waiting for confirmation",
+                    session_id
+                )));
+            }
+            (_, Some(paused)) => {
+                return Err(AgentError::AgentFailed(format!(
+                    "Session {} is waiting for the user to confirm the tool calls of {}",
+                    session_id, paused.author
+                )));
+            }
+            (_, None) => InvocationContext::new_invocation_context_id(),
+        };
+
+        let mut context = InvocationContext::create(
+            self.session_service.clone(),
+            self.artifact_service.clone(),
+            invocation_id,
+            Arc::new(self.agent.clone()),
+            session.clone(),
+            None,
+            run_config,
+        );
+        context.set_memory_service(self.memory_service.clone());
+        context.set_plugin_manager(self.plugin_manager.clone());
+        let span = telemetry::invocation_span(&context);
+        context.set_span(span.clone());
+
+        self.run_invocation(session, context, input, output).instrument(span).await
+    }
+
+    /// Checks that the decisions answer exactly the calls held by `paused`.
+    fn check_decisions(paused: &Event, decisions: &[ToolDecision]) -> Result<(), AgentError> {
+        let held = paused.confirmation.as_ref().map(|confirmation| confirmation.function_calls.as_slice()).unwrap_or_default();
+        for function_call in held {
+            if !decisions.iter().any(|decision| function_call.id.as_deref() == Some(decision.function_call_id.as_str())) {
+                return Err(AgentError::AgentFailed(format!(
+                    "No decision for the call to {} ({})",
+                    function_call.name,
+                    function_call.id.as_deref().unwrap_or_default()
+                )));
+            }
+        }
+        for decision in decisions {
+            if !held.iter().any(|function_call| function_call.id.as_deref() == Some(decision.function_call_id.as_str())) {
+                return Err(AgentError::AgentFailed(format!(
+                    "Tool call {} is not waiting for confirmation",
+                    decision.function_call_id
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    async fn run_invocation(
+        &self,
+        mut session: Session,
+        mut context: InvocationContext,
+        input: InvocationInput,
+        output: &mpsc::Sender<Result<Event, AgentError>>,
+    ) -> Result<(), AgentError> {
+        let live = match input {
+            InvocationInput::Message(new_message) => {
+                let new_message = self.plugin_manager.run_on_user_message(&context, new_message).await;
+                let user_event = Event::builder()
+                    .invocation_id(context.invocation_id().to_string())
+                    .author("user".to_string())
+                    .content(Some(new_message.clone()))
+                    .build();
+                self.session_service.append_event(&mut session, user_event).await?;
+                context.set_user_content(Some(new_message));
+                context.set_session(session.clone());
+                false
+            }
+            InvocationInput::Live(live_request_queue) => {
+                context.set_live_request_queue(Some(live_request_queue));
+                true
+            }
+            InvocationInput::Resume(decisions) => {
+                let paused = session
+                    .pending_confirmation()
+                    .cloned()
+                    .ok_or_else(|| AgentError::AgentFailed("The invocation is no longer waiting for confirmation".to_string()))?;
+                Self::check_decisions(&paused, &decisions)?;
+                let user_event = Event::builder()
+                    .invocation_id(context.invocation_id().to_string())
+                    .author("user".to_string())
+                    .decisions(Some(decisions.clone()))
+                    .build();
+                self.session_service.append_event(&mut session, user_event).await?;
+                let user_content = session
+                    .events()
+                    .iter()
+                    .find(|event| event.invocation_id == paused.invocation_id && event.author == "user")
+                    .and_then(|event| event.content.clone());
+                context.set_user_content(user_content);
+                context.set_tool_decisions(paused.branch.clone().unwrap_or_default(), decisions);
+                context.set_session(session.clone());
+                false
+            }
+        };
+
+        let result = match self.plugin_manager.run_before_run(&context).await {
+            Some(content) => self.answer_for_plugin(&context, &mut session, content, output).await,
+            None => self.run_agent(&mut context, &mut session, live, output).await,
+        };
+        // Plugins that saw the run start see it end, however it ended.
+        match &result {
+            Ok(()) => self.plugin_manager.run_after_run(&context).await,
+            Err(error) => self.plugin_manager.run_on_error(&context, error).await,
+        }
+        result
+    }
+
+    /// Answers with the content a plugin returned from `before_run` instead
+    /// of running the agent.
+    async fn answer_for_plugin(
+        &self,
+        context: &InvocationContext,
+        session: &mut Session,
+        content: Content,
+        output: &mpsc::Sender<Result<Event, AgentError>>,
+    ) -> Result<(), AgentError> {
+        let event = Event::builder()
+            .invocation_id(context.invocation_id().to_string())
+            .author(self.agent.get_name().unwrap_or_default())
+            .content(Some(content))
+            .final_response(true)
+            .build();
+        self.session_service.append_event(session, event.clone()).await?;
+        let _ = output.send(Ok(event)).await;
+        Ok(())
+    }
+
+    async fn run_agent(
+        &self,
+        context: &mut InvocationContext,
+        session: &mut Session,
+        live: bool,
+        output: &mpsc::Sender<Result<Event, AgentError>>,
+    ) -> Result<(), AgentError> {
+        let (event_sink, mut emitted_events) = mpsc::unbounded_channel();
+        context.set_event_sink(Some(event_sink));
+        let context = &*context;
+        let mut published = HashSet::new();
+
+        let run = async {
+            if live {
+                BaseAgent::run_sub_agent_live(&self.agent, context.clone()).await
+            } else {
+                BaseAgent::run_sub_agent(&self.agent, context.clone()).await
+            }
+        };
+        tokio::pin!(run);
+        let result = loop {
+            tokio::select! {
+                Some(event) = emitted_events.recv() => {
+                    self.publish(context, session, event, &mut published, output).await?;
+                }
+                result = &mut run => break result,
+            }
+        };
+        // Decisions nobody took mean the agent that paused is no longer in
+        // the tree, and the tree skipped everything looking for it.
+        if context.resumed_child().is_some() && result.is_ok() {
+            return Err(AgentError::AgentFailed(
+                "The agent that paused the invocation is no longer part of the agent tree".to_string(),
+            ));
+        }
+        let agent_events = result?;
+
+        while let Ok(event) = emitted_events.try_recv() {
+            self.publish(context, session, event, &mut published, output).await?;
+        }
+        for event in agent_events {
+            self.publish(context, session, event, &mut published, output).await?;
+        }
+        Ok(())
+    }
+
+    /// Appends an event to the session and sends it to the caller, once per
+    /// event id. Agents can stream an event and also return it.
+    async fn publish(
+        &self,
+        context: &InvocationContext,
+        session: &mut Session,
+        event: Event,
+        published: &mut HashSet<String>,
+        output: &mpsc::Sender<Result<Event, AgentError>>,
+    ) -> Result<(), AgentError> {
+        if !published.insert(event.id.clone()) {
+            return Ok(());
+        }
+        let event = self.plugin_manager.run_on_event(context, event).await;
+        self.session_service.append_event(session, event.clone()).await?;
+        let _ = output.send(Ok(event)).await;
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub struct RunnerBuilder {
+    app_name: String,
+    agent: ActorCell,
+    session_service: Option<Arc<dyn BaseSessionService>>,
+    artifact_service: Option<Arc<dyn BaseArtifactService>>,
+    memory_service: Option<Arc<dyn BaseMemoryService>>,
+    plugins: Vec<Arc<dyn Plugin>>,
+}
+
+impl RunnerBuilder {
+    pub fn session_service(mut self, session_service: Arc<dyn BaseSessionService>) -> Self {
+        self.session_service = Some(session_service);
+        self
+    }
+
+    pub fn artifact_service(mut self, artifact_service: Arc<dyn BaseArtifactService>) -> Self {
+        self.artifact_service = Some(artifact_service);
+        self
+    }
+
+    pub fn memory_service(mut self, memory_service: Arc<dyn BaseMemoryService>) -> Self {
+        self.memory_service = Some(memory_service);
+        self
+    }
+
+    pub fn plugin(mut self, plugin: Arc<dyn Plugin>) -> Self {
+        self.plugins.push(plugin);
+        self
+    }
+
+    pub fn build(self) -> Runner {
+        Runner {
+            app_name: self.app_name,
+            agent: self.agent,
+            session_service: self.session_service.unwrap_or_else(|| Arc::new(InMemorySessionService::new())),
+            artifact_service: self.artifact_service.unwrap_or_else(|| Arc::new(InMemoryArtifactService::new())),
+            memory_service: self.memory_service,
+            plugin_manager: PluginManager::new(self.plugins),
+        }
+    }
+}
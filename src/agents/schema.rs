@@ -0,0 +1,208 @@
+//! Structured output for LLM agents. Schemas are plain JSON Schema values;
+//! `validate` checks the subset of keywords the models are asked to follow:
+//! `type`, `enum`, `const`, `properties`, `required`, `additionalProperties`,
+//! `items`, the length and size bounds and `minimum`/`maximum`.
+
+use serde_json::Value;
+
+/// Implemented by Rust types that describe the JSON an agent must produce.
+pub trait OutputSchema {
+    fn output_schema() -> Value;
+}
+
+fn type_matches(expected: &str, value: &Value) -> bool {
+    match expected {
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "number" => value.is_number(),
+        "integer" => value.is_i64() || value.is_u64() || value.as_f64().is_some_and(|number| number.fract() == 0.0),
+        "boolean" => value.is_boolean(),
+        "null" => value.is_null(),
+        _ => true,
+    }
+}
+
+fn validate_at(schema: &Value, value: &Value, path: &str, errors: &mut Vec<String>) {
+    let schema = match schema.as_object() {
+        Some(schema) => schema,
+        None => return,
+    };
+
+    if let Some(expected) = schema.get("type") {
+        let types: Vec<&str> = match expected {
+            Value::String(expected) => vec![expected.as_str()],
+            Value::Array(expected) => expected.iter().filter_map(|expected| expected.as_str()).collect(),
+            _ => Vec::new(),
+        };
+        if !types.is_empty() && !types.iter().any(|expected| type_matches(expected, value)) {
+            errors.push(format!("{}: expected {}, got {}", path, types.join(" or "), value));
+            return;
+        }
+    }
+
+    if let Some(allowed) = schema.get("enum").and_then(|allowed| allowed.as_array()) {
+        if !allowed.contains(value) {
+            errors.push(format!("{}: {} is not one of {}", path, value, Value::Array(allowed.clone())));
+        }
+    }
+    if let Some(expected) = schema.get("const") {
+        if expected != value {
+            errors.push(format!("{}: expected {}, got {}", path, expected, value));
+        }
+    }
+
+    match value {
+        Value::Object(object) => {
+            let properties = schema.get("properties").and_then(|properties| properties.as_object());
+            if let Some(required) = schema.get("required").and_then(|required| required.as_array()) {
+                for key in required.iter().filter_map(|key| key.as_str()) {
+                    if !object.contains_key(key) {
+                        errors.push(format!("{}: missing required property '{}'", path, key));
+                    }
+                }
+            }
+            for (key, field) in object {
+                match properties.and_then(|properties| properties.get(key)) {
+                    Some(field_schema) => validate_at(field_schema, field, &format!("{}.{}", path, key), errors),
+                    None => match schema.get("additionalProperties") {
+                        Some(Value::Bool(false)) => errors.push(format!("{}: unexpected property '{}'", path, key)),
+                        Some(additional) => validate_at(additional, field, &format!("{}.{}", path, key), errors),
+                        None => {}
+                    },
+                }
+            }
+        }
+        Value::Array(items) => {
+            if let Some(min_items) = schema.get("minItems").and_then(|min| min.as_u64()) {
+                if (items.len() as u64) < min_items {
+                    errors.push(format!("{}: expected at least {} items, got {}", path, min_items, items.len()));
+                }
+            }
+            if let Some(max_items) = schema.get("maxItems").and_then(|max| max.as_u64()) {
+                if items.len() as u64 > max_items {
+                    errors.push(format!("{}: expected at most {} items, got {}", path, max_items, items.len()));
+                }
+            }
+            if let Some(item_schema) = schema.get("items") {
+                for (index, item) in items.iter().enumerate() {
+                    validate_at(item_schema, item, &format!("{}[{}]", path, index), errors);
+                }
+            }
+        }
+        Value::String(text) => {
+            let length = text.chars().count() as u64;
+            if let Some(min_length) = schema.get("minLength").and_then(|min| min.as_u64()) {
+                if length < min_length {
+                    errors.push(format!("{}: expected at least {} characters", path, min_length));
+                }
+            }
+            if let Some(max_length) = schema.get("maxLength").and_then(|max| max.as_u64()) {
+                if length > max_length {
+                    errors.push(format!("{}: expected at most {} characters", path, max_length));
+                }
+            }
+        }
+        Value::Number(number) => {
+            let number = number.as_f64().unwrap_or_default();
+            if let Some(minimum) = schema.get("minimum").and_then(|min| min.as_f64()) {
+                if number < minimum {
+                    errors.push(format!("{}: {} is less than the minimum {}", path, number, minimum));
+                }
+            }
+            if let Some(maximum) = schema.get("maximum").and_then(|max| max.as_f64()) {
+                if number > maximum {
+                    errors.push(format!("{}: {} is greater than the maximum {}", path, number, maximum));
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Validates `value` against `schema` and returns one message per violation.
+pub fn validate(schema: &Value, value: &Value) -> Vec<String> {
+    let mut errors = Vec::new();
+    validate_at(schema, value, "$", &mut errors);
+    errors
+}
+
+/// Parses a model reply as JSON, accepting replies wrapped in a Markdown
+/// code fence.
+pub fn parse_json_output(text: &str) -> Result<Value, String> {
+    let trimmed = text.trim();
+    let unfenced = trimmed
+        .strip_prefix("```")
+        .and_then(|rest| rest.strip_suffix("```"))
+        .map(|rest| rest.trim_start_matches("json").trim())
+        .unwrap_or(trimmed);
+    serde_json::from_str(unfenced).map_err(|e| format!("reply is not valid JSON: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn person() -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "name": { "type": "string", "minLength": 1, "maxLength": 5 },
+                "age": { "type": "integer", "minimum": 0, "maximum": 150 },
+                "role": { "enum": ["admin", "user"] },
+                "tags": { "type": "array", "items": { "type": "string" }, "maxItems": 2 }
+            },
+            "required": ["name", "age"],
+            "additionalProperties": false
+        })
+    }
+
+    #[test]
+    fn matching_values_have_no_errors() {
+        assert!(validate(&person(), &json!({ "name": "Ada", "age": 36, "role": "admin", "tags": ["x"] })).is_empty());
+        assert!(validate(&person(), &json!({ "name": "Ada", "age": 36.0 })).is_empty());
+        assert!(validate(&json!({ "type": ["string", "null"] }), &Value::Null).is_empty());
+    }
+
+    #[test]
+    fn every_violation_is_reported_with_its_path() {
+        let errors = validate(&person(), &json!({ "name": "", "age": -1, "role": "root", "tags": ["a", 2, "c"], "extra": true }));
+        assert_eq!(
+            errors,
+            [
+                "$.age: -1 is less than the minimum 0",
+                "$: unexpected property 'extra'",
+                "$.name: expected at least 1 characters",
+                "$.role: \"root\" is not one of [\"admin\",\"user\"]",
+                "$.tags: expected at most 2 items, got 3",
+                "$.tags[1]: expected string, got 2",
+            ]
+        );
+        assert_eq!(validate(&person(), &json!({ "name": "Ada" })), ["$: missing required property 'age'"]);
+        assert_eq!(
+            validate(&person(), &json!({ "name": "Adalbert", "age": 1.5 })),
+            ["$.age: expected integer, got 1.5", "$.name: expected at most 5 characters"]
+        );
+    }
+
+    #[test]
+    fn type_mismatches_stop_further_checks() {
+        assert_eq!(validate(&person(), &json!([1])), ["$: expected object, got [1]"]);
+        assert_eq!(validate(&json!({ "const": 3 }), &json!(4)), ["$: expected 3, got 4"]);
+    }
+
+    #[test]
+    fn additional_properties_can_have_a_schema() {
+        let schema = json!({ "type": "object", "additionalProperties": { "type": "number" } });
+        assert!(validate(&schema, &json!({ "a": 1, "b": 2.5 })).is_empty());
+        assert_eq!(validate(&schema, &json!({ "a": "1" })), ["$.a: expected number, got \"1\""]);
+    }
+
+    #[test]
+    fn fenced_replies_are_parsed() {
+        assert_eq!(parse_json_output("```json\n{\"a\": 1}\n```").unwrap(), json!({ "a": 1 }));
+        assert_eq!(parse_json_output(" [1, 2] ").unwrap(), json!([1, 2]));
+        assert!(parse_json_output("not json").unwrap_err().starts_with("reply is not valid JSON"));
+    }
+}
@@ -1,112 +1,119 @@
-use crate::base_agent::{BaseAgent, BaseAgentMessage, BaseAgentState, BaseAgentArguments, BeforeAgentCallback, AfterAgentCallback};
+use crate::agent::Agent;
+use crate::base_agent::BaseAgentState;
 use crate::common::{AgentError, Event};
 use crate::invocation_context::InvocationContext;
-use ractor::{Actor, ActorRef, ActorCell, ActorProcessingErr};
-use tokio::sync::mpsc;
-use std::sync::Arc;
 use async_trait::async_trait;
 
-#[derive(Clone, Debug)]
-pub struct SequentialAgent {
-    base: BaseAgent,
-}
+#[derive(Clone, Agent)]
+pub struct SequentialAgent;
 
 #[async_trait]
-impl Actor for SequentialAgent {
-    type Msg = BaseAgentMessage;
-    type State = BaseAgentState;
-    type Arguments = BaseAgentArguments;
-
-    async fn pre_start(&self, this_actor: ActorRef<Self>, args: Self::Arguments) -> Result<Self::State, ActorProcessingErr> {
-        self.base.pre_start(this_actor, args).await
-    }
-
-    async fn handle(
-        &self,
-        this_actor: ActorRef<Self>,
-        message: Self::Msg,
-        state: &mut Self::State,
-    ) -> Result<(), ActorProcessingErr> {
-        self.base.handle(this_actor, message, state).await
-    }
-}
-
-impl SequentialAgent {
-    pub fn builder() -> SequentialAgentBuilder {
-        SequentialAgentBuilder {
-            name: None,
-            description: None,
-            sub_agents: Vec::new(),
-            before_agent_callback: None,
-            after_agent_callback: None,
-        }
-    }
-
-    async fn run_async_impl(&self, context: &InvocationContext) -> Result<Vec<Event>, AgentError> {
-        let mut events = Vec::new();
-        for sub_agent in self.base.sub_agents() {
-            let sub_events = self.base.run_async(context.clone()).await?;
-            events.extend(sub_events);
-        }
-        Ok(events)
-    }
-
-    async fn run_live_impl(&self, context: &InvocationContext) -> Result<Vec<Event>, AgentError> {
+impl Agent for SequentialAgent {
+    async fn run_async_impl(&self, state: &BaseAgentState, context: &InvocationContext) -> Result<Vec<Event>, AgentError> {
+        let supervisor = &state.supervisor;
+        let mut context = context.clone();
         let mut events = Vec::new();
-        for sub_agent in self.base.sub_agents() {
-            let sub_events = self.base.run_async(context.clone()).await?;
+        for index in 0..supervisor.len() {
+            let sub_events = supervisor.run_child(index, &context).await?;
+            for event in &sub_events {
+                context.session_mut().append_event(event.clone());
+            }
             events.extend(sub_events);
+            if context.end_invocation() {
+                break;
+            }
         }
         Ok(events)
     }
 }
 
-#[derive(Clone, Debug)]
-pub struct SequentialAgentBuilder {
-    name: Option<String>,
-    description: Option<String>,
-    sub_agents: Vec<Arc<ActorCell>>,
-    before_agent_callback: Option<Vec<BeforeAgentCallback>>,
-    after_agent_callback: Option<Vec<AfterAgentCallback>>,
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::base_tool::{require_confirmation, BaseTool};
+    use crate::common::{Content, FunctionCall, Part, ToolDecision};
+    use crate::llm_agent::LlmAgent;
+    use crate::run_config::RunConfig;
+    use crate::testing::{runner_with_session, unique_name, Reply, ScriptedLlm};
+    use crate::tool_context::ToolContext;
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
 
-impl SequentialAgentBuilder {
-    pub fn name(mut self, name: String) -> Self {
-        self.name = Some(name);
-        self
+    struct Send {
+        calls: Arc<AtomicUsize>,
     }
 
-    pub fn description(mut self, description: String) -> Self {
-        self.description = Some(description);
-        self
-    }
+    #[async_trait]
+    impl BaseTool for Send {
+        fn name(&self) -> &str {
+            "send"
+        }
 
-    pub fn sub_agents(mut self, sub_agents: Vec<Arc<ActorCell>>) -> Self {
-        self.sub_agents = sub_agents;
-        self
-    }
+        fn description(&self) -> &str {
+            "Sends the message."
+        }
 
-    pub fn before_agent_callback(mut self, callback: BeforeAgentCallback) -> Self {
-        self.before_agent_callback = Some(vec![callback]);
-        self
+        async fn run_async(&self, _args: serde_json::Value, _tool_context: ToolContext) -> Result<serde_json::Value, AgentError> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            Ok(json!({ "sent": true }))
+        }
     }
 
-    pub fn after_agent_callback(mut self, callback: AfterAgentCallback) -> Self {
-        self.after_agent_callback = Some(vec![callback]);
-        self
-    }
+    #[tokio::test]
+    async fn resumed_pause_runs_the_rest_of_the_sequence() {
+        let (before, before_runs) = Reply::spawn_with(Reply::builder("drafted".to_string()).name(unique_name("before"))).await;
+        let (after, after_runs) = Reply::spawn_with(Reply::builder("logged".to_string()).name(unique_name("after"))).await;
+        let call = FunctionCall {
+            id: Some("call-1".to_string()),
+            name: "send".to_string(),
+            args: json!({}),
+        };
+        let model = ScriptedLlm::new(vec![
+            Content::new("model".to_string(), vec![Part::from_function_call(call)]),
+            Content::from_text("model", "sent"),
+        ]);
+        let calls = Arc::new(AtomicUsize::new(0));
+        let sender = LlmAgent::builder(model)
+            .tools(vec![require_confirmation(Arc::new(Send { calls: calls.clone() }))])
+            .name(unique_name("sender"))
+            .spawn()
+            .await
+            .unwrap();
+        let sequence = SequentialAgent::builder()
+            .name(unique_name("sequence"))
+            .sub_agents(vec![Arc::new(before.get_cell()), Arc::new(sender.get_cell()), Arc::new(after.get_cell())])
+            .spawn()
+            .await
+            .unwrap();
+        let (runner, session_id) = runner_with_session(&sequence).await;
 
-    pub fn build(self) -> SequentialAgent {
-        let name = self.name.unwrap_or_default();
-        let description = self.description.unwrap_or_default();
-        SequentialAgent {
-            base: BaseAgent::new(
-                name,
-                description,
-                self.sub_agents,
-                self.before_agent_callback,
-                self.after_agent_callback,
-            ),
-        }
+        let events = runner
+            .run_async("user", &session_id, Content::from_text("user", "send it"), RunConfig::default())
+            .await
+            .unwrap();
+        assert!(events.last().unwrap().confirmation.is_some());
+        assert_eq!((before_runs.load(Ordering::SeqCst), after_runs.load(Ordering::SeqCst)), (1, 0));
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+
+        let decision = ToolDecision {
+            function_call_id: "call-1".to_string(),
+            approved: true,
+            args: None,
+            reason: None,
+        };
+        let events = runner
+            .resume_async("user", &session_id, vec![decision], RunConfig::default())
+            .await
+            .unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!((before_runs.load(Ordering::SeqCst), after_runs.load(Ordering::SeqCst)), (1, 1));
+        let texts: Vec<String> = events
+            .iter()
+            .filter_map(|event| event.content.as_ref())
+            .map(|content| content.text())
+            .filter(|text| !text.is_empty())
+            .collect();
+        assert_eq!(texts, vec!["sent", "logged"]);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,570 @@
+//! HTTP API around a `Runner`, enabled by the `server` feature.
+//!
+//! | Method | Path | |
+//! |---|---|---|
+//! | GET | `/health`, `/ready` | liveness and readiness |
+//! | POST, GET | `/apps/{app}/users/{user}/sessions` | create or list sessions |
+//! | GET, DELETE | `/apps/{app}/users/{user}/sessions/{session}` | get or delete a session |
+//! | POST | `/apps/{app}/users/{user}/sessions/{session}/run_sse` | run the agent, streaming events as SSE |
+//! | POST | `/apps/{app}/users/{user}/sessions/{session}/resume_sse` | resume a run paused for tool confirmation, as `run_sse` |
+//! | GET | `/apps/{app}/users/{user}/sessions/{session}/run_live` | WebSocket live session |
+//! | GET | `/apps/{app}/users/{user}/sessions/{session}/artifacts` | list artifact names |
+//! | GET | `/apps/{app}/users/{user}/sessions/{session}/artifacts/{name}` | download an artifact, `?version=` optional |
+//!
+//! On the live socket the client sends JSON text frames tagged by `type`:
+//! `{"type": "content", "content": {...}}`,
+//! `{"type": "realtime", "mime_type": "audio/pcm", "data": "<base64>"}`,
+//! `{"type": "activity_start"}`, `{"type": "activity_end"}` and
+//! `{"type": "close"}`. Binary frames are taken as `audio/pcm` chunks. The
+//! server answers with one text frame per event and `{"error": ...}` on
+//! failure.
+
+use crate::common::{AgentError, Blob, Content, Event, LiveRequest, LiveRequestQueue, ToolDecision};
+use crate::run_config::RunConfig;
+use crate::runner::Runner;
+use axum::body::Bytes;
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Path, Query, State};
+use axum::http::{header, StatusCode};
+use axum::response::sse::{Event as SseEvent, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use base64::Engine;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::{mpsc, Notify};
+use tokio_stream::wrappers::ReceiverStream;
+
+/// Counts running invocations so shutdown can wait for them.
+#[derive(Debug, Default)]
+struct InFlight {
+    count: AtomicUsize,
+    shutting_down: AtomicBool,
+    idle: Notify,
+    shutdown: Notify,
+}
+
+impl InFlight {
+    fn start(self: &Arc<Self>) -> InFlightGuard {
+        self.count.fetch_add(1, Ordering::SeqCst);
+        InFlightGuard(self.clone())
+    }
+
+    async fn wait_idle(&self) {
+        loop {
+            let idle = self.idle.notified();
+            if self.count.load(Ordering::SeqCst) == 0 {
+                return;
+            }
+            idle.await;
+        }
+    }
+}
+
+struct InFlightGuard(Arc<InFlight>);
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        if self.0.count.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.0.idle.notify_waiters();
+        }
+    }
+}
+
+#[derive(Clone)]
+struct ServerState {
+    runner: Runner,
+    in_flight: Arc<InFlight>,
+}
+
+#[derive(Clone)]
+pub struct ApiServer {
+    state: ServerState,
+}
+
+struct ApiError(StatusCode, String);
+
+impl From<AgentError> for ApiError {
+    fn from(error: AgentError) -> Self {
+        let status = match error {
+            AgentError::SessionNotFound(_) => StatusCode::NOT_FOUND,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        ApiError(status, error.to_string())
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(json!({ "error": self.1 }))).into_response()
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct CreateSessionRequest {
+    session_id: Option<String>,
+    state: Option<HashMap<String, serde_json::Value>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RunRequest {
+    new_message: Content,
+    max_llm_calls: Option<i32>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ResumeRequest {
+    decisions: Vec<ToolDecision>,
+    max_llm_calls: Option<i32>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LiveQuery {
+    max_llm_calls: Option<i32>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum LiveMessage {
+    Content { content: Content },
+    Realtime { mime_type: String, data: String },
+    ActivityStart,
+    ActivityEnd,
+    Close,
+}
+
+impl LiveMessage {
+    fn into_request(self) -> Result<LiveRequest, String> {
+        Ok(match self {
+            LiveMessage::Content { content } => LiveRequest::Content(content),
+            LiveMessage::Realtime { mime_type, data } => LiveRequest::Realtime(Blob {
+                mime_type,
+                data: base64::engine::general_purpose::STANDARD.decode(data).map_err(|e| e.to_string())?,
+            }),
+            LiveMessage::ActivityStart => LiveRequest::ActivityStart,
+            LiveMessage::ActivityEnd => LiveRequest::ActivityEnd,
+            LiveMessage::Close => LiveRequest::Close,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ArtifactQuery {
+    version: Option<i32>,
+}
+
+impl ServerState {
+    fn check_app(&self, app_name: &str) -> Result<(), ApiError> {
+        if app_name == self.runner.app_name() {
+            Ok(())
+        } else {
+            Err(ApiError(StatusCode::NOT_FOUND, format!("App {} not found", app_name)))
+        }
+    }
+}
+
+async fn health() -> Json<serde_json::Value> {
+    Json(json!({ "status": "ok" }))
+}
+
+async fn ready(State(state): State<ServerState>) -> Response {
+    if state.in_flight.shutting_down.load(Ordering::SeqCst) {
+        (StatusCode::SERVICE_UNAVAILABLE, Json(json!({ "status": "shutting_down" }))).into_response()
+    } else {
+        Json(json!({ "status": "ready" })).into_response()
+    }
+}
+
+async fn create_session(
+    State(state): State<ServerState>,
+    Path((app_name, user_id)): Path<(String, String)>,
+    body: Bytes,
+) -> Result<Response, ApiError> {
+    state.check_app(&app_name)?;
+    let request: CreateSessionRequest = if body.is_empty() {
+        CreateSessionRequest::default()
+    } else {
+        serde_json::from_slice(&body).map_err(|e| ApiError(StatusCode::BAD_REQUEST, e.to_string()))?
+    };
+    let session = state
+        .runner
+        .session_service()
+        .create_session(&app_name, &user_id, request.state, request.session_id)
+        .await?;
+    Ok((StatusCode::CREATED, Json(session)).into_response())
+}
+
+async fn list_sessions(
+    State(state): State<ServerState>,
+    Path((app_name, user_id)): Path<(String, String)>,
+) -> Result<Response, ApiError> {
+    state.check_app(&app_name)?;
+    let sessions = state.runner.session_service().list_sessions(&app_name, &user_id).await?;
+    Ok(Json(sessions).into_response())
+}
+
+async fn get_session(
+    State(state): State<ServerState>,
+    Path((app_name, user_id, session_id)): Path<(String, String, String)>,
+) -> Result<Response, ApiError> {
+    state.check_app(&app_name)?;
+    let session = state
+        .runner
+        .session_service()
+        .get_session(&app_name, &user_id, &session_id)
+        .await?
+        .ok_or_else(|| AgentError::SessionNotFound(format!("Session {} not found", session_id)))?;
+    Ok(Json(session).into_response())
+}
+
+async fn delete_session(
+    State(state): State<ServerState>,
+    Path((app_name, user_id, session_id)): Path<(String, String, String)>,
+) -> Result<Response, ApiError> {
+    state.check_app(&app_name)?;
+    state.runner.session_service().delete_session(&app_name, &user_id, &session_id).await?;
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+async fn run_sse(
+    State(state): State<ServerState>,
+    Path((app_name, user_id, session_id)): Path<(String, String, String)>,
+    Json(request): Json<RunRequest>,
+) -> Result<Response, ApiError> {
+    state.check_app(&app_name)?;
+    if state.in_flight.shutting_down.load(Ordering::SeqCst) {
+        return Err(ApiError(StatusCode::SERVICE_UNAVAILABLE, "Server is shutting down".to_string()));
+    }
+    state
+        .runner
+        .session_service()
+        .get_session(&app_name, &user_id, &session_id)
+        .await?
+        .ok_or_else(|| AgentError::SessionNotFound(format!("Session {} not found", session_id)))?;
+
+    let events = state
+        .runner
+        .run_stream(&user_id, &session_id, request.new_message, run_config(request.max_llm_calls));
+    Ok(stream_sse(&state, events))
+}
+
+async fn resume_sse(
+    State(state): State<ServerState>,
+    Path((app_name, user_id, session_id)): Path<(String, String, String)>,
+    Json(request): Json<ResumeRequest>,
+) -> Result<Response, ApiError> {
+    state.check_app(&app_name)?;
+    if state.in_flight.shutting_down.load(Ordering::SeqCst) {
+        return Err(ApiError(StatusCode::SERVICE_UNAVAILABLE, "Server is shutting down".to_string()));
+    }
+    state
+        .runner
+        .session_service()
+        .get_session(&app_name, &user_id, &session_id)
+        .await?
+        .ok_or_else(|| AgentError::SessionNotFound(format!("Session {} not found", session_id)))?;
+
+    let events = state
+        .runner
+        .resume_stream(&user_id, &session_id, request.decisions, run_config(request.max_llm_calls));
+    Ok(stream_sse(&state, events))
+}
+
+fn stream_sse(state: &ServerState, mut events: mpsc::Receiver<Result<Event, AgentError>>) -> Response {
+    // The invocation keeps running when the client goes away, so the guard
+    // lives in a task that drains the runner rather than in the response.
+    let guard = state.in_flight.start();
+    let (sender, receiver) = mpsc::channel::<Result<SseEvent, Infallible>>(64);
+    tokio::spawn(async move {
+        let _guard = guard;
+        while let Some(event) = events.recv().await {
+            let sse_event = match event {
+                Ok(event) => SseEvent::default()
+                    .json_data(&event)
+                    .unwrap_or_else(|e| SseEvent::default().event("error").data(e.to_string())),
+                Err(error) => SseEvent::default().event("error").data(error.to_string()),
+            };
+            let _ = sender.send(Ok(sse_event)).await;
+        }
+    });
+
+    Sse::new(ReceiverStream::new(receiver))
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+fn run_config(max_llm_calls: Option<i32>) -> RunConfig {
+    let mut run_config = RunConfig::builder();
+    if let Some(max_llm_calls) = max_llm_calls {
+        run_config = run_config.set_max_llm_calls(max_llm_calls);
+    }
+    run_config.build()
+}
+
+async fn run_live(
+    State(state): State<ServerState>,
+    Path((app_name, user_id, session_id)): Path<(String, String, String)>,
+    Query(query): Query<LiveQuery>,
+    upgrade: WebSocketUpgrade,
+) -> Result<Response, ApiError> {
+    state.check_app(&app_name)?;
+    if state.in_flight.shutting_down.load(Ordering::SeqCst) {
+        return Err(ApiError(StatusCode::SERVICE_UNAVAILABLE, "Server is shutting down".to_string()));
+    }
+    state
+        .runner
+        .session_service()
+        .get_session(&app_name, &user_id, &session_id)
+        .await?
+        .ok_or_else(|| AgentError::SessionNotFound(format!("Session {} not found", session_id)))?;
+
+    let guard = state.in_flight.start();
+    Ok(upgrade.on_upgrade(move |socket| async move {
+        let _guard = guard;
+        let queue = LiveRequestQueue::new();
+        let events = state
+            .runner
+            .run_live(&user_id, &session_id, queue.clone(), run_config(query.max_llm_calls));
+        serve_live_socket(socket, queue, events, &state.in_flight).await;
+    }))
+}
+
+/// Pumps client frames into the queue and events out to the client. The
+/// queue is closed when the client goes away or the server shuts down; the
+/// socket stays open until the agents have finished their last reply.
+async fn serve_live_socket(
+    mut socket: WebSocket,
+    queue: LiveRequestQueue,
+    mut events: mpsc::Receiver<Result<Event, AgentError>>,
+    in_flight: &InFlight,
+) {
+    let shutdown = in_flight.shutdown.notified();
+    tokio::pin!(shutdown);
+    shutdown.as_mut().enable();
+    let mut reading = !in_flight.shutting_down.load(Ordering::SeqCst);
+    if !reading {
+        queue.close();
+    }
+    loop {
+        tokio::select! {
+            _ = &mut shutdown, if reading => {
+                queue.close();
+                reading = false;
+            }
+            message = socket.recv(), if reading => match message {
+                Some(Ok(Message::Text(text))) => {
+                    let request = serde_json::from_str::<LiveMessage>(&text)
+                        .map_err(|e| e.to_string())
+                        .and_then(LiveMessage::into_request);
+                    match request {
+                        Ok(request) => queue.send(request),
+                        Err(error) => {
+                            let error = json!({ "error": format!("Invalid live message: {}", error) });
+                            let _ = socket.send(Message::Text(error.to_string().into())).await;
+                        }
+                    }
+                }
+                Some(Ok(Message::Binary(data))) => queue.send_realtime(Blob {
+                    mime_type: "audio/pcm".to_string(),
+                    data: data.to_vec(),
+                }),
+                Some(Ok(_)) => {}
+                Some(Err(_)) | None => {
+                    queue.close();
+                    reading = false;
+                }
+            },
+            event = events.recv() => {
+                let frame = match event {
+                    Some(Ok(event)) => serde_json::to_string(&event).unwrap_or_else(|e| json!({ "error": e.to_string() }).to_string()),
+                    Some(Err(error)) => json!({ "error": error.to_string() }).to_string(),
+                    None => break,
+                };
+                if socket.send(Message::Text(frame.into())).await.is_err() && reading {
+                    queue.close();
+                    reading = false;
+                }
+            }
+        }
+    }
+    let _ = socket.send(Message::Close(None)).await;
+}
+
+async fn list_artifacts(
+    State(state): State<ServerState>,
+    Path((app_name, user_id, session_id)): Path<(String, String, String)>,
+) -> Result<Response, ApiError> {
+    state.check_app(&app_name)?;
+    let keys = state
+        .runner
+        .artifact_service()
+        .list_artifact_keys(&app_name, &user_id, &session_id)
+        .await?;
+    Ok(Json(keys).into_response())
+}
+
+async fn get_artifact(
+    State(state): State<ServerState>,
+    Path((app_name, user_id, session_id, filename)): Path<(String, String, String, String)>,
+    Query(query): Query<ArtifactQuery>,
+) -> Result<Response, ApiError> {
+    state.check_app(&app_name)?;
+    let artifact = state
+        .runner
+        .artifact_service()
+        .load_artifact(&app_name, &user_id, &session_id, &filename, query.version)
+        .await?
+        .ok_or_else(|| ApiError(StatusCode::NOT_FOUND, format!("Artifact {} not found", filename)))?;
+    let disposition = format!("attachment; filename=\"{}\"", filename.replace('"', ""));
+    match artifact.text {
+        Some(text) => Ok((
+            [(header::CONTENT_TYPE, "text/plain; charset=utf-8".to_string()), (header::CONTENT_DISPOSITION, disposition)],
+            text,
+        )
+            .into_response()),
+        None => Ok(([(header::CONTENT_DISPOSITION, disposition)], Json(artifact)).into_response()),
+    }
+}
+
+impl ApiServer {
+    pub fn new(runner: Runner) -> Self {
+        ApiServer {
+            state: ServerState {
+                runner,
+                in_flight: Arc::new(InFlight::default()),
+            },
+        }
+    }
+
+    pub fn router(&self) -> Router {
+        Router::new()
+            .route("/health", get(health))
+            .route("/ready", get(ready))
+            .route("/apps/{app_name}/users/{user_id}/sessions", post(create_session).get(list_sessions))
+            .route(
+                "/apps/{app_name}/users/{user_id}/sessions/{session_id}",
+                get(get_session).delete(delete_session),
+            )
+            .route("/apps/{app_name}/users/{user_id}/sessions/{session_id}/run_sse", post(run_sse))
+            .route("/apps/{app_name}/users/{user_id}/sessions/{session_id}/resume_sse", post(resume_sse))
+            .route("/apps/{app_name}/users/{user_id}/sessions/{session_id}/run_live", get(run_live))
+            .route("/apps/{app_name}/users/{user_id}/sessions/{session_id}/artifacts", get(list_artifacts))
+            .route(
+                "/apps/{app_name}/users/{user_id}/sessions/{session_id}/artifacts/{filename}",
+                get(get_artifact),
+            )
+            .with_state(self.state.clone())
+    }
+
+    /// Serves until `shutdown` resolves, then stops accepting runs, closes
+    /// live sessions and waits for the open connections and in-flight
+    /// invocations to finish.
+    pub async fn serve<F>(self, listener: tokio::net::TcpListener, shutdown: F) -> Result<(), AgentError>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let in_flight = self.state.in_flight.clone();
+        let signal = {
+            let in_flight = in_flight.clone();
+            async move {
+                shutdown.await;
+                in_flight.shutting_down.store(true, Ordering::SeqCst);
+                in_flight.shutdown.notify_waiters();
+            }
+        };
+        axum::serve(listener, self.router())
+            .with_graceful_shutdown(signal)
+            .await
+            .map_err(|e| AgentError::AgentFailed(format!("Server failed: {}", e)))?;
+        in_flight.wait_idle().await;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{runner_with_session, unique_name, Reply};
+    use axum::body::Body;
+    use axum::http::Request;
+    use std::time::Duration;
+    use tower::ServiceExt;
+
+    fn run_request(session_id: &str, text: &str) -> Request<Body> {
+        let body = json!({ "new_message": Content::from_text("user", text) });
+        Request::post(format!("/apps/test/users/user/sessions/{}/run_sse", session_id))
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    /// The events of an SSE response, read until the run ends.
+    async fn sse_events(response: Response) -> Vec<Event> {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(body.to_vec())
+            .unwrap()
+            .lines()
+            .filter_map(|line| line.strip_prefix("data: "))
+            .map(|data| serde_json::from_str(data).unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn runs_stream_their_events() {
+        let (agent, _) = Reply::spawn_with(Reply::builder("hi".to_string()).name(unique_name("reply"))).await;
+        let (runner, session_id) = runner_with_session(&agent).await;
+        let server = ApiServer::new(runner);
+
+        let response = server.router().oneshot(run_request(&session_id, "hello")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/event-stream");
+        let events = sse_events(response).await;
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].content().unwrap().text(), "hi");
+        server.state.in_flight.wait_idle().await;
+
+        let response = server.router().oneshot(run_request("missing", "hello")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn shutdown_rejects_new_runs_and_waits_for_running_ones() {
+        let (agent, runs) = Reply::spawn_with(
+            Reply::builder("late".to_string())
+                .delay(Duration::from_millis(300))
+                .name(unique_name("slow")),
+        )
+        .await;
+        let (runner, session_id) = runner_with_session(&agent).await;
+        let server = ApiServer::new(runner.clone());
+        let router = server.router();
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let serving = tokio::spawn(server.serve(listener, async {
+            let _ = stopped.await;
+        }));
+
+        let running = router.clone().oneshot(run_request(&session_id, "first")).await.unwrap();
+        assert_eq!(running.status(), StatusCode::OK);
+        stop.send(()).unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let response = router.clone().oneshot(run_request(&session_id, "second")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let response = router.clone().oneshot(Request::get("/ready").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(!serving.is_finished());
+
+        tokio::time::timeout(Duration::from_secs(5), serving).await.unwrap().unwrap().unwrap();
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        let session = runner.session_service().get_session("test", "user", &session_id).await.unwrap().unwrap();
+        assert_eq!(session.events().last().unwrap().content().unwrap().text(), "late");
+        assert_eq!(sse_events(running).await.len(), 1);
+    }
+}
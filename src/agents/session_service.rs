@@ -0,0 +1,400 @@
+use crate::common::{check_schema_version, AgentError, Event, Session, SCHEMA_VERSION};
+use crate::state::{ScopedState, StateScope};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use uuid::Uuid;
+
+#[async_trait]
+pub trait BaseSessionService: Send + Sync + std::fmt::Debug {
+    async fn create_session(
+        &self,
+        app_name: &str,
+        user_id: &str,
+        state: Option<HashMap<String, serde_json::Value>>,
+        session_id: Option<String>,
+    ) -> Result<Session, AgentError>;
+
+    async fn get_session(&self, app_name: &str, user_id: &str, session_id: &str) -> Result<Option<Session>, AgentError>;
+
+    async fn list_sessions(&self, app_name: &str, user_id: &str) -> Result<Vec<Session>, AgentError>;
+
+    async fn delete_session(&self, app_name: &str, user_id: &str, session_id: &str) -> Result<(), AgentError>;
+
+    async fn append_event(&self, session: &mut Session, event: Event) -> Result<Event, AgentError>;
+}
+
+type SessionKey = (String, String, String);
+type UserKey = (String, String);
+type StateMap = HashMap<String, serde_json::Value>;
+
+/// Stores sessions in memory. Only session-scoped keys are kept on the stored
+/// session and in the state deltas of its stored events; `app:` and `user:`
+/// keys live in shared maps that are merged back in whenever a session is
+/// returned, and `temp:` keys are never stored.
+#[derive(Debug, Default)]
+pub struct InMemorySessionService {
+    sessions: Mutex<HashMap<SessionKey, Session>>,
+    app_state: Mutex<HashMap<String, StateMap>>,
+    user_state: Mutex<HashMap<UserKey, StateMap>>,
+}
+
+impl InMemorySessionService {
+    pub fn new() -> Self {
+        InMemorySessionService::default()
+    }
+
+    fn key(app_name: &str, user_id: &str, session_id: &str) -> SessionKey {
+        (app_name.to_string(), user_id.to_string(), session_id.to_string())
+    }
+
+    fn to_store(&self) -> SessionStore {
+        SessionStore {
+            schema_version: SCHEMA_VERSION,
+            sessions: self.sessions.lock().unwrap().values().cloned().collect(),
+            app_state: self.app_state.lock().unwrap().clone(),
+            user_state: self
+                .user_state
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|((app_name, user_id), state)| (app_name.clone(), user_id.clone(), state.clone()))
+                .collect(),
+        }
+    }
+
+    fn from_store(store: SessionStore) -> Self {
+        InMemorySessionService {
+            sessions: Mutex::new(
+                store
+                    .sessions
+                    .into_iter()
+                    .map(|session| (Self::key(session.app_name(), session.user_id(), session.id()), session))
+                    .collect(),
+            ),
+            app_state: Mutex::new(store.app_state),
+            user_state: Mutex::new(
+                store
+                    .user_state
+                    .into_iter()
+                    .map(|(app_name, user_id, state)| ((app_name, user_id), state))
+                    .collect(),
+            ),
+        }
+    }
+
+    fn store_scoped_state(&self, app_name: &str, user_id: &str, scoped: &ScopedState) {
+        if !scoped.app.is_empty() {
+            let mut app_state = self.app_state.lock().unwrap();
+            app_state.entry(app_name.to_string()).or_default().extend(scoped.app.clone());
+        }
+        if !scoped.user.is_empty() {
+            let mut user_state = self.user_state.lock().unwrap();
+            user_state
+                .entry((app_name.to_string(), user_id.to_string()))
+                .or_default()
+                .extend(scoped.user.clone());
+        }
+    }
+
+    fn merge_state(&self, session: &Session) -> Session {
+        let app_state = self.app_state.lock().unwrap();
+        let user_state = self.user_state.lock().unwrap();
+        let mut merged = session.clone();
+        *merged.state_mut() = ScopedState::merge(
+            app_state.get(session.app_name()),
+            user_state.get(&(session.app_name().to_string(), session.user_id().to_string())),
+            session.state(),
+        );
+        merged
+    }
+}
+
+#[async_trait]
+impl BaseSessionService for InMemorySessionService {
+    async fn create_session(
+        &self,
+        app_name: &str,
+        user_id: &str,
+        state: Option<HashMap<String, serde_json::Value>>,
+        session_id: Option<String>,
+    ) -> Result<Session, AgentError> {
+        let session_id = session_id.unwrap_or_else(|| Uuid::new_v4().to_string());
+        let scoped = ScopedState::split(&state.unwrap_or_default());
+        self.store_scoped_state(app_name, user_id, &scoped);
+        let session = Session::new(app_name.to_string(), user_id.to_string(), session_id.clone(), scoped.session);
+        self.sessions
+            .lock()
+            .unwrap()
+            .insert(Self::key(app_name, user_id, &session_id), session.clone());
+        Ok(self.merge_state(&session))
+    }
+
+    async fn get_session(&self, app_name: &str, user_id: &str, session_id: &str) -> Result<Option<Session>, AgentError> {
+        let session = self.sessions.lock().unwrap().get(&Self::key(app_name, user_id, session_id)).cloned();
+        Ok(session.map(|session| self.merge_state(&session)))
+    }
+
+    async fn list_sessions(&self, app_name: &str, user_id: &str) -> Result<Vec<Session>, AgentError> {
+        let sessions: Vec<Session> = self
+            .sessions
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|session| session.app_name() == app_name && session.user_id() == user_id)
+            .cloned()
+            .collect();
+        Ok(sessions.iter().map(|session| self.merge_state(session)).collect())
+    }
+
+    async fn delete_session(&self, app_name: &str, user_id: &str, session_id: &str) -> Result<(), AgentError> {
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.remove(&Self::key(app_name, user_id, session_id));
+        Ok(())
+    }
+
+    async fn append_event(&self, session: &mut Session, event: Event) -> Result<Event, AgentError> {
+        session.append_event(event.clone());
+        {
+            let mut sessions = self.sessions.lock().unwrap();
+            let key = Self::key(session.app_name(), session.user_id(), session.id());
+            match sessions.get_mut(&key) {
+                Some(stored) => {
+                    let mut stored_event = event.clone();
+                    stored_event
+                        .actions
+                        .state_delta()
+                        .retain(|key, _| StateScope::of(key) == StateScope::Session);
+                    stored.append_event(stored_event);
+                }
+                None => return Err(AgentError::SessionNotFound(format!("Session {} not found", session.id()))),
+            }
+        }
+        let mut actions = event.actions.clone();
+        let scoped = ScopedState::split(actions.state_delta());
+        self.store_scoped_state(session.app_name(), session.user_id(), &scoped);
+        Ok(event)
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SessionStore {
+    #[serde(default)]
+    schema_version: u32,
+    sessions: Vec<Session>,
+    app_state: HashMap<String, StateMap>,
+    user_state: Vec<(String, String, StateMap)>,
+}
+
+/// Session service that keeps everything in a single JSON file, rewritten
+/// after every change. Meant for local tools such as the CLI.
+#[derive(Debug)]
+pub struct FileSessionService {
+    path: PathBuf,
+    sessions: InMemorySessionService,
+    /// Held from the snapshot to the rename, so concurrent changes write the
+    /// file one at a time and the last write has the latest snapshot.
+    write_lock: tokio::sync::Mutex<()>,
+}
+
+impl FileSessionService {
+    /// Opens the store at `path`, starting empty when the file does not exist.
+    pub fn open(path: PathBuf) -> Result<Self, AgentError> {
+        let store = if path.exists() {
+            let bytes = std::fs::read(&path).map_err(|e| AgentError::StorageError(e.to_string()))?;
+            let mut store: SessionStore = serde_json::from_slice(&bytes)
+                .map_err(|e| AgentError::StorageError(format!("{}: {}", path.display(), e)))?;
+            check_schema_version("Session store", store.schema_version)?;
+            for session in &mut store.sessions {
+                session.upgrade()?;
+            }
+            store
+        } else {
+            SessionStore::default()
+        };
+        Ok(FileSessionService {
+            path,
+            sessions: InMemorySessionService::from_store(store),
+            write_lock: tokio::sync::Mutex::new(()),
+        })
+    }
+
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+
+    async fn persist(&self) -> Result<(), AgentError> {
+        let _write = self.write_lock.lock().await;
+        let body = serde_json::to_vec(&self.sessions.to_store()).map_err(|e| AgentError::StorageError(e.to_string()))?;
+        if let Some(parent) = self.path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .map_err(|e| AgentError::StorageError(e.to_string()))?;
+        }
+        let temp_path = self.path.with_extension("tmp");
+        tokio::fs::write(&temp_path, body)
+            .await
+            .map_err(|e| AgentError::StorageError(e.to_string()))?;
+        tokio::fs::rename(&temp_path, &self.path)
+            .await
+            .map_err(|e| AgentError::StorageError(e.to_string()))
+    }
+}
+
+#[async_trait]
+impl BaseSessionService for FileSessionService {
+    async fn create_session(
+        &self,
+        app_name: &str,
+        user_id: &str,
+        state: Option<HashMap<String, serde_json::Value>>,
+        session_id: Option<String>,
+    ) -> Result<Session, AgentError> {
+        let session = self.sessions.create_session(app_name, user_id, state, session_id).await?;
+        self.persist().await?;
+        Ok(session)
+    }
+
+    async fn get_session(&self, app_name: &str, user_id: &str, session_id: &str) -> Result<Option<Session>, AgentError> {
+        self.sessions.get_session(app_name, user_id, session_id).await
+    }
+
+    async fn list_sessions(&self, app_name: &str, user_id: &str) -> Result<Vec<Session>, AgentError> {
+        self.sessions.list_sessions(app_name, user_id).await
+    }
+
+    async fn delete_session(&self, app_name: &str, user_id: &str, session_id: &str) -> Result<(), AgentError> {
+        self.sessions.delete_session(app_name, user_id, session_id).await?;
+        self.persist().await
+    }
+
+    async fn append_event(&self, session: &mut Session, event: Event) -> Result<Event, AgentError> {
+        let event = self.sessions.append_event(session, event).await?;
+        self.persist().await?;
+        Ok(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::EventActions;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    fn event_with_delta(delta: &[(&str, serde_json::Value)]) -> Event {
+        let mut actions = EventActions::builder().build();
+        for (key, value) in delta {
+            actions.state_delta().insert(key.to_string(), value.clone());
+        }
+        Event::builder()
+            .invocation_id("e-1".to_string())
+            .author("agent".to_string())
+            .actions(actions)
+            .build()
+    }
+
+    fn scoped_event() -> Event {
+        event_with_delta(&[
+            ("topic", json!("rust")),
+            ("app:greeting", json!("hi")),
+            ("user:name", json!("Ada")),
+            ("temp:draft", json!("scratch")),
+        ])
+    }
+
+    fn delta_keys(event: &Event) -> Vec<String> {
+        let mut actions = event.actions.clone();
+        let mut keys: Vec<String> = actions.state_delta().keys().cloned().collect();
+        keys.sort();
+        keys
+    }
+
+    fn temp_path() -> PathBuf {
+        std::env::temp_dir().join(format!("coagent-sessions-{}.json", Uuid::new_v4()))
+    }
+
+    #[tokio::test]
+    async fn stored_events_keep_only_session_keys() {
+        let service = InMemorySessionService::new();
+        let mut session = service.create_session("app", "user", None, None).await.unwrap();
+        service.append_event(&mut session, scoped_event()).await.unwrap();
+
+        // The caller's session sees every key for the rest of the invocation.
+        assert_eq!(session.state()["temp:draft"], json!("scratch"));
+        assert_eq!(delta_keys(&session.events()[0]).len(), 4);
+
+        let stored = service.get_session("app", "user", session.id()).await.unwrap().unwrap();
+        assert_eq!(delta_keys(&stored.events()[0]), vec!["topic".to_string()]);
+        assert_eq!(stored.state()["topic"], json!("rust"));
+        assert_eq!(stored.state()["app:greeting"], json!("hi"));
+        assert_eq!(stored.state()["user:name"], json!("Ada"));
+        assert!(!stored.state().contains_key("temp:draft"));
+    }
+
+    #[tokio::test]
+    async fn app_and_user_state_is_shared_across_sessions() {
+        let service = InMemorySessionService::new();
+        let mut first = service.create_session("app", "user", None, None).await.unwrap();
+        service.append_event(&mut first, scoped_event()).await.unwrap();
+
+        let other_session = service.create_session("app", "user", None, None).await.unwrap();
+        assert_eq!(other_session.state()["app:greeting"], json!("hi"));
+        assert_eq!(other_session.state()["user:name"], json!("Ada"));
+        assert!(!other_session.state().contains_key("topic"));
+
+        let other_user = service.create_session("app", "someone", None, None).await.unwrap();
+        assert_eq!(other_user.state()["app:greeting"], json!("hi"));
+        assert!(!other_user.state().contains_key("user:name"));
+    }
+
+    #[tokio::test]
+    async fn file_service_reloads_what_it_stored() {
+        let path = temp_path();
+        let service = FileSessionService::open(path.clone()).unwrap();
+        let mut session = service.create_session("app", "user", None, None).await.unwrap();
+        service.append_event(&mut session, scoped_event()).await.unwrap();
+
+        let reopened = FileSessionService::open(path.clone()).unwrap();
+        let stored = reopened.get_session("app", "user", session.id()).await.unwrap().unwrap();
+        assert_eq!(stored.events().len(), 1);
+        assert_eq!(delta_keys(&stored.events()[0]), vec!["topic".to_string()]);
+        assert_eq!(stored.state()["user:name"], json!("Ada"));
+        assert!(!stored.state().contains_key("temp:draft"));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn concurrent_appends_all_reach_the_file() {
+        let path = temp_path();
+        let service = Arc::new(FileSessionService::open(path.clone()).unwrap());
+        let mut sessions = Vec::new();
+        for _ in 0..8 {
+            sessions.push(service.create_session("app", "user", None, None).await.unwrap());
+        }
+        let mut appends = tokio::task::JoinSet::new();
+        for mut session in sessions {
+            let service = service.clone();
+            appends.spawn(async move {
+                for index in 0..10 {
+                    let event = event_with_delta(&[("count", json!(index))]);
+                    service.append_event(&mut session, event).await.unwrap();
+                }
+            });
+        }
+        while let Some(append) = appends.join_next().await {
+            append.unwrap();
+        }
+
+        let reopened = FileSessionService::open(path.clone()).unwrap();
+        let stored = reopened.list_sessions("app", "user").await.unwrap();
+        assert_eq!(stored.len(), 8);
+        for session in stored {
+            assert_eq!(session.events().len(), 10);
+            assert_eq!(session.state()["count"], json!(9));
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,67 @@
+//! Scoping of session state keys. `app:` keys are shared by every user of an
+//! app, `user:` keys by all sessions of one user and `temp:` keys only live
+//! for the current invocation. Keys without a prefix belong to the session.
+
+use std::collections::HashMap;
+
+pub const APP_PREFIX: &str = "app:";
+pub const USER_PREFIX: &str = "user:";
+pub const TEMP_PREFIX: &str = "temp:";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StateScope {
+    App,
+    User,
+    Session,
+    Temp,
+}
+
+impl StateScope {
+    pub fn of(key: &str) -> Self {
+        if key.starts_with(APP_PREFIX) {
+            StateScope::App
+        } else if key.starts_with(USER_PREFIX) {
+            StateScope::User
+        } else if key.starts_with(TEMP_PREFIX) {
+            StateScope::Temp
+        } else {
+            StateScope::Session
+        }
+    }
+}
+
+/// State split by the scope it is persisted in. `temp:` keys are dropped.
+#[derive(Clone, Debug, Default)]
+pub struct ScopedState {
+    pub app: HashMap<String, serde_json::Value>,
+    pub user: HashMap<String, serde_json::Value>,
+    pub session: HashMap<String, serde_json::Value>,
+}
+
+impl ScopedState {
+    pub fn split(state: &HashMap<String, serde_json::Value>) -> Self {
+        let mut scoped = ScopedState::default();
+        for (key, value) in state {
+            let target = match StateScope::of(key) {
+                StateScope::App => &mut scoped.app,
+                StateScope::User => &mut scoped.user,
+                StateScope::Session => &mut scoped.session,
+                StateScope::Temp => continue,
+            };
+            target.insert(key.clone(), value.clone());
+        }
+        scoped
+    }
+
+    pub fn merge(
+        app: Option<&HashMap<String, serde_json::Value>>,
+        user: Option<&HashMap<String, serde_json::Value>>,
+        session: &HashMap<String, serde_json::Value>,
+    ) -> HashMap<String, serde_json::Value> {
+        let mut state = session.clone();
+        for scope in [app, user].into_iter().flatten() {
+            state.extend(scope.iter().map(|(key, value)| (key.clone(), value.clone())));
+        }
+        state
+    }
+}
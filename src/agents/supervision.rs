@@ -0,0 +1,576 @@
+//! Supervision of sub-agents. Sub-agents given to a parent as `ChildSpec`s
+//! are spawned by the parent, linked to it as ractor children and restarted
+//! from their spec when they fail, following the parent's
+//! `SupervisionConfig`. A sub-agent that fails during a run is restarted in
+//! place and its step is retried, with an event recording the restart.
+//! The retry runs the step again from the start, so whatever the failed
+//! attempt already did happens twice: its streamed events are emitted again
+//! and its tool calls are made again. Sub-agents whose steps must not be
+//! repeated opt out with `ChildSpec::retry_runs`. Sub-agents passed as
+//! running actors are used as they are.
+
+use crate::agent_metrics;
+use crate::base_agent::{BaseAgentArguments, BaseAgentMessage};
+use crate::common::{AgentError, AgentRestart, Event};
+use crate::invocation_context::InvocationContext;
+use ractor::{Actor, ActorCell, ActorProcessingErr, ActorStatus, SupervisionEvent};
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+/// Which sub-agents are restarted when one fails.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RestartStrategy {
+    OneForOne,
+    /// Restarts every sub-agent that has a spec, for siblings that share
+    /// assumptions about each other's state.
+    OneForAll,
+}
+
+/// What happens once the restart intensity is exceeded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Escalation {
+    /// The parent fails as well and its own supervisor handles the failure.
+    Escalate,
+    /// The parent stays up and only the current run fails.
+    FailRun,
+}
+
+#[derive(Clone, Debug)]
+pub struct SupervisionConfig {
+    strategy: RestartStrategy,
+    max_restarts: usize,
+    within: Duration,
+    escalation: Escalation,
+}
+
+impl SupervisionConfig {
+    pub fn builder() -> SupervisionConfigBuilder {
+        SupervisionConfigBuilder {
+            strategy: RestartStrategy::OneForOne,
+            max_restarts: 3,
+            within: Duration::from_secs(60),
+            escalation: Escalation::Escalate,
+        }
+    }
+
+    pub fn strategy(&self) -> RestartStrategy {
+        self.strategy
+    }
+
+    pub fn max_restarts(&self) -> usize {
+        self.max_restarts
+    }
+
+    pub fn within(&self) -> Duration {
+        self.within
+    }
+
+    pub fn escalation(&self) -> Escalation {
+        self.escalation
+    }
+}
+
+impl Default for SupervisionConfig {
+    fn default() -> Self {
+        SupervisionConfig::builder().build()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SupervisionConfigBuilder {
+    strategy: RestartStrategy,
+    max_restarts: usize,
+    within: Duration,
+    escalation: Escalation,
+}
+
+impl SupervisionConfigBuilder {
+    pub fn strategy(mut self, strategy: RestartStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Allows at most `max_restarts` restarts in any `within` window before
+    /// the escalation policy applies.
+    pub fn intensity(mut self, max_restarts: usize, within: Duration) -> Self {
+        self.max_restarts = max_restarts;
+        self.within = within;
+        self
+    }
+
+    pub fn escalation(mut self, escalation: Escalation) -> Self {
+        self.escalation = escalation;
+        self
+    }
+
+    pub fn build(self) -> SupervisionConfig {
+        SupervisionConfig {
+            strategy: self.strategy,
+            max_restarts: self.max_restarts,
+            within: self.within,
+            escalation: self.escalation,
+        }
+    }
+}
+
+type SpawnChild = Arc<dyn Fn(ActorCell) -> Pin<Box<dyn Future<Output = Result<ActorCell, AgentError>> + Send>> + Send + Sync>;
+
+/// How to start a sub-agent: every start spawns a fresh copy of the built
+/// agent with its arguments, so a restarted sub-agent keeps nothing from
+/// the failed one.
+#[derive(Clone)]
+pub struct ChildSpec {
+    name: String,
+    spawn: SpawnChild,
+    retry_runs: bool,
+}
+
+impl ChildSpec {
+    pub fn new<A>(agent: A, args: BaseAgentArguments) -> Self
+    where
+        A: Actor<Msg = BaseAgentMessage, Arguments = BaseAgentArguments> + Clone,
+    {
+        let name = args.name.clone();
+        let spawn: SpawnChild = Arc::new(move |supervisor: ActorCell| {
+            let agent = agent.clone();
+            let mut args = args.clone();
+            args.parent_agent = Some(Arc::new(supervisor.clone()));
+            Box::pin(async move {
+                let name = args.name.clone();
+                // A registered actor with this name is a previous incarnation
+                // whose parent has failed; it is already being torn down.
+                if let Some(previous) = ractor::registry::where_is(name.clone()) {
+                    stop_child(&previous).await;
+                }
+                let (actor, _) = Actor::spawn_linked(Some(name.clone()), agent, args, supervisor)
+                    .await
+                    .map_err(|e| AgentError::AgentFailed(format!("Failed to start agent {}: {}", name, e)))?;
+                Ok(actor.get_cell())
+            })
+        });
+        ChildSpec {
+            name,
+            spawn,
+            retry_runs: true,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Whether a run of the sub-agent that fails midway is retried after the
+    /// restart, `true` by default. Turn it off for sub-agents whose steps have
+    /// side effects that must not happen twice; their runs then fail instead,
+    /// while the sub-agent is still restarted for the next run.
+    pub fn retry_runs(mut self, retry_runs: bool) -> Self {
+        self.retry_runs = retry_runs;
+        self
+    }
+
+    async fn spawn(&self, supervisor: ActorCell) -> Result<ActorCell, AgentError> {
+        (self.spawn)(supervisor).await
+    }
+}
+
+async fn stop_child(child: &ActorCell) {
+    child.kill();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while child.get_status() != ActorStatus::Stopped && Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+struct SupervisedChild {
+    cell: Arc<ActorCell>,
+    spec: Option<ChildSpec>,
+}
+
+struct Children {
+    children: Vec<SupervisedChild>,
+    restarts: VecDeque<Instant>,
+    escalated: Option<String>,
+}
+
+/// A parent's sub-agents, in order: the running actors it was given, then
+/// the ones it spawned from specs.
+#[derive(Clone)]
+pub struct Supervisor {
+    name: String,
+    parent: ActorCell,
+    config: SupervisionConfig,
+    children: Arc<Mutex<Children>>,
+    // Serializes restarts; parallel runs may notice the same failure.
+    restart_lock: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl Supervisor {
+    pub(crate) async fn start(
+        name: String,
+        parent: ActorCell,
+        config: SupervisionConfig,
+        sub_agents: &[Arc<ActorCell>],
+        child_specs: &[ChildSpec],
+    ) -> Result<Self, AgentError> {
+        let mut children: Vec<SupervisedChild> = sub_agents
+            .iter()
+            .map(|cell| SupervisedChild { cell: cell.clone(), spec: None })
+            .collect();
+        for spec in child_specs {
+            let cell = spec.spawn(parent.clone()).await?;
+            children.push(SupervisedChild {
+                cell: Arc::new(cell),
+                spec: Some(spec.clone()),
+            });
+        }
+        Ok(Supervisor {
+            name,
+            parent,
+            config,
+            children: Arc::new(Mutex::new(Children {
+                children,
+                restarts: VecDeque::new(),
+                escalated: None,
+            })),
+            restart_lock: Arc::new(tokio::sync::Mutex::new(())),
+        })
+    }
+
+    pub fn config(&self) -> &SupervisionConfig {
+        &self.config
+    }
+
+    pub fn children(&self) -> Vec<Arc<ActorCell>> {
+        self.children.lock().unwrap().children.iter().map(|child| child.cell.clone()).collect()
+    }
+
+    pub fn find_child(&self, name: &str) -> Option<Arc<ActorCell>> {
+        self.children().into_iter().find(|child| child.get_name().as_deref() == Some(name))
+    }
+
+    /// Index of the sub-agent named `name`, for `run_child`.
+    pub fn position(&self, name: &str) -> Option<usize> {
+        self.children().iter().position(|child| child.get_name().as_deref() == Some(name))
+    }
+
+    pub fn len(&self) -> usize {
+        self.children.lock().unwrap().children.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Runs the sub-agent at `index`. If it dies before replying it is
+    /// restarted and the run is retried, unless its spec turned retries off;
+    /// the restart events come first in the returned events. While a paused
+    /// invocation is resumed, sub-agents off the way to the agent that paused
+    /// finished before the pause and are skipped.
+    pub async fn run_child(&self, index: usize, context: &InvocationContext) -> Result<Vec<Event>, AgentError> {
+        let mut events = Vec::new();
+        loop {
+            let (child, spec) = {
+                let children = self.children.lock().unwrap();
+                let child = children
+                    .children
+                    .get(index)
+                    .ok_or_else(|| AgentError::AgentFailed(format!("Agent {} has no sub-agent {}", self.name, index)))?;
+                (child.cell.clone(), child.spec.clone())
+            };
+            if context.resumed_child().is_some_and(|resumed| child.get_name().as_deref() != Some(resumed.as_str())) {
+                return Ok(events);
+            }
+            if context.is_cancelled() {
+                return Err(AgentError::AgentFailed(format!("Agent {} was cancelled", self.name)));
+            }
+            let (sender, mut receiver) = mpsc::channel(1);
+            let reply = match child.send_message(BaseAgentMessage::RunAsync { context: context.clone(), sender }) {
+                Ok(()) => tokio::select! {
+                    reply = receiver.recv() => reply,
+                    _ = context.cancellation().cancelled() => {
+                        return Err(AgentError::AgentFailed(format!("Agent {} was cancelled", self.name)));
+                    }
+                },
+                Err(_) => None,
+            };
+            match reply {
+                Some(result) => {
+                    events.extend(result?);
+                    return Ok(events);
+                }
+                None if spec.is_some() => {
+                    if let Some(restart) = self.restart(&child, "stopped before replying").await? {
+                        let event = Event::builder()
+                            .invocation_id(context.invocation_id().to_string())
+                            .author(self.name.clone())
+                            .branch(context.branch().map(|s| s.to_string()))
+                            .restart(Some(restart))
+                            .build();
+                        context.emit_event(&event);
+                        events.push(event);
+                    }
+                    if let Some(spec) = spec.filter(|spec| !spec.retry_runs) {
+                        return Err(AgentError::AgentFailed(format!(
+                            "Sub-agent {} of {} stopped before replying and its runs are not retried",
+                            spec.name(),
+                            self.name
+                        )));
+                    }
+                }
+                None => {
+                    return Err(AgentError::AgentFailed("Sub-agent stopped before sending its events".to_string()));
+                }
+            }
+        }
+    }
+
+    /// Restarts the sub-agents affected by the failure of `failed`. Returns
+    /// `None` when another caller already replaced it.
+    async fn restart(&self, failed: &ActorCell, reason: &str) -> Result<Option<AgentRestart>, AgentError> {
+        let _restarting = self.restart_lock.lock().await;
+        let (failed_agent, targets, restart_count) = {
+            let mut children = self.children.lock().unwrap();
+            let index = match children.children.iter().position(|child| child.cell.get_id() == failed.get_id()) {
+                Some(index) => index,
+                None => return Ok(None),
+            };
+            let failed_agent = children.children[index].spec.as_ref().map(|spec| spec.name().to_string()).unwrap_or_default();
+
+            let now = Instant::now();
+            while children.restarts.front().is_some_and(|at| now.duration_since(*at) > self.config.within) {
+                children.restarts.pop_front();
+            }
+            if children.restarts.len() >= self.config.max_restarts {
+                let message = format!(
+                    "Agent {} gave up on sub-agent {} after {} restarts within {:?}: {}",
+                    self.name, failed_agent, self.config.max_restarts, self.config.within, reason
+                );
+                if self.config.escalation == Escalation::Escalate {
+                    children.escalated = Some(message.clone());
+                }
+                return Err(AgentError::AgentFailed(message));
+            }
+            children.restarts.push_back(now);
+
+            let targets: Vec<usize> = match self.config.strategy {
+                RestartStrategy::OneForOne => vec![index],
+                RestartStrategy::OneForAll => (0..children.children.len())
+                    .filter(|i| children.children[*i].spec.is_some())
+                    .collect(),
+            };
+            (failed_agent, targets, children.restarts.len())
+        };
+
+        let mut restarted_agents = Vec::new();
+        for index in targets {
+            let (cell, spec) = {
+                let children = self.children.lock().unwrap();
+                let child = &children.children[index];
+                (child.cell.clone(), child.spec.clone().expect("restart targets have specs"))
+            };
+            stop_child(&cell).await;
+            let new_cell = spec.spawn(self.parent.clone()).await?;
+            self.children.lock().unwrap().children[index].cell = Arc::new(new_cell);
+            agent_metrics::record_restart(&self.name, spec.name());
+            restarted_agents.push(spec.name().to_string());
+        }
+
+        Ok(Some(AgentRestart {
+            failed_agent,
+            reason: reason.to_string(),
+            restarted_agents,
+            restart_count,
+        }))
+    }
+
+    /// Restarts sub-agents that fail outside of a run. Fails the parent when
+    /// the failure escalates.
+    pub async fn handle_supervisor_evt(&self, event: SupervisionEvent) -> Result<(), ActorProcessingErr> {
+        if let SupervisionEvent::ActorFailed(cell, error) = event {
+            let restartable = self
+                .children
+                .lock()
+                .unwrap()
+                .children
+                .iter()
+                .any(|child| child.cell.get_id() == cell.get_id() && child.spec.is_some());
+            if restartable {
+                if let Err(error) = self.restart(&cell, &error.to_string()).await {
+                    tracing::warn!("{}", error);
+                }
+            }
+        }
+        self.check_escalation()
+    }
+
+    /// Fails once a restart has been refused under `Escalation::Escalate`,
+    /// so the parent's handlers can end the actor after replying.
+    pub fn check_escalation(&self) -> Result<(), ActorProcessingErr> {
+        match &self.children.lock().unwrap().escalated {
+            Some(message) => Err(ActorProcessingErr::from(message.clone())),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::Agent;
+    use crate::base_agent::BaseAgentState;
+    use crate::sequential_agent::SequentialAgent;
+    use crate::testing::{run, unique_name, Reply};
+    use async_trait::async_trait;
+    use ractor::ActorRef;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Panics in every run, which drops the run's reply.
+    #[derive(Clone, Agent)]
+    struct Crash {
+        runs: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl Agent for Crash {
+        async fn run_async_impl(&self, _state: &BaseAgentState, _context: &InvocationContext) -> Result<Vec<Event>, AgentError> {
+            self.runs.fetch_add(1, Ordering::SeqCst);
+            panic!("crashed");
+        }
+    }
+
+    async fn wait_for(condition: impl Fn() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !condition() {
+            assert!(Instant::now() < deadline, "timed out");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    fn cell(name: &str) -> Option<ActorCell> {
+        ractor::registry::where_is(name.to_string())
+    }
+
+    /// Fails the running sub-agent `name` through its `Fail` message.
+    fn fail(name: &str) -> ActorCell {
+        let cell = cell(name).unwrap();
+        ActorRef::<BaseAgentMessage>::from(cell.clone())
+            .send_message(BaseAgentMessage::Fail("boom".to_string()))
+            .unwrap();
+        cell
+    }
+
+    async fn wait_for_restart(name: &str, failed: &ActorCell) {
+        wait_for(|| cell(name).is_some_and(|cell| cell.get_id() != failed.get_id())).await;
+    }
+
+    async fn wait_for_stop(cell: &ActorCell) {
+        wait_for(|| cell.get_status() == ActorStatus::Stopped).await;
+    }
+
+    async fn parent(config: SupervisionConfig, specs: Vec<ChildSpec>) -> ActorRef<BaseAgentMessage> {
+        let mut builder = SequentialAgent::builder().name(unique_name("parent")).supervision(config);
+        for spec in specs {
+            builder = builder.sub_agent_spec(spec);
+        }
+        builder.spawn().await.unwrap()
+    }
+
+    fn reply_spec(name: &str) -> ChildSpec {
+        Reply::builder("ok".to_string()).name(name.to_string()).spec()
+    }
+
+    #[tokio::test]
+    async fn restarts_are_limited_within_the_window() {
+        let child = unique_name("child");
+        let config = SupervisionConfig::builder()
+            .intensity(1, Duration::from_millis(200))
+            .escalation(Escalation::FailRun)
+            .build();
+        let parent = parent(config, vec![reply_spec(&child)]).await;
+
+        let failed = fail(&child);
+        wait_for_restart(&child, &failed).await;
+        // A second failure within the window is not restarted.
+        let failed = fail(&child);
+        wait_for_stop(&failed).await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(cell(&child).is_none());
+
+        // Once the window has passed, the next run restarts it again.
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        let events = run(&parent, "hi").await.unwrap();
+        assert_eq!(events[0].restart.as_ref().unwrap().restart_count, 1);
+        assert_eq!(events[1].content.as_ref().unwrap().text(), "ok");
+    }
+
+    #[tokio::test]
+    async fn one_for_all_restarts_the_siblings() {
+        let (first, second) = (unique_name("first"), unique_name("second"));
+        let config = SupervisionConfig::builder().strategy(RestartStrategy::OneForAll).build();
+        let _parent = parent(config, vec![reply_spec(&first), reply_spec(&second)]).await;
+
+        let sibling = cell(&second).unwrap();
+        let failed = fail(&first);
+        wait_for_restart(&first, &failed).await;
+        wait_for_restart(&second, &sibling).await;
+    }
+
+    #[tokio::test]
+    async fn escalation_fails_the_parent() {
+        let child = unique_name("child");
+        let config = SupervisionConfig::builder().intensity(0, Duration::from_secs(60)).build();
+        let parent = parent(config, vec![reply_spec(&child)]).await;
+
+        fail(&child);
+        wait_for_stop(&parent.get_cell()).await;
+    }
+
+    #[tokio::test]
+    async fn fail_run_keeps_the_parent_up() {
+        let child = unique_name("child");
+        let config = SupervisionConfig::builder()
+            .intensity(0, Duration::from_secs(60))
+            .escalation(Escalation::FailRun)
+            .build();
+        let parent = parent(config, vec![reply_spec(&child)]).await;
+
+        let failed = fail(&child);
+        wait_for_stop(&failed).await;
+        let result = run(&parent, "hi").await;
+        assert!(matches!(result, Err(AgentError::AgentFailed(error)) if error.contains("gave up")));
+        assert_eq!(parent.get_status(), ActorStatus::Running);
+    }
+
+    #[tokio::test]
+    async fn failed_runs_are_retried_up_to_the_intensity() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let spec = Crash::builder().runs(runs.clone()).name(unique_name("crash")).spec();
+        let config = SupervisionConfig::builder()
+            .intensity(2, Duration::from_secs(60))
+            .escalation(Escalation::FailRun)
+            .build();
+        let parent = parent(config, vec![spec]).await;
+
+        let result = run(&parent, "hi").await;
+        assert!(matches!(result, Err(AgentError::AgentFailed(error)) if error.contains("gave up")));
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn runs_without_retries_fail_after_the_restart() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let name = unique_name("crash");
+        let spec = Crash::builder().runs(runs.clone()).name(name.clone()).spec().retry_runs(false);
+        let parent = parent(SupervisionConfig::default(), vec![spec]).await;
+        let crashed = cell(&name).unwrap();
+
+        let result = run(&parent, "hi").await;
+        assert!(matches!(result, Err(AgentError::AgentFailed(error)) if error.contains("not retried")));
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        wait_for_restart(&name, &crashed).await;
+    }
+}
@@ -0,0 +1,111 @@
+//! Span constructors for the invocation lifecycle. Attribute names follow the
+//! OpenTelemetry GenAI semantic conventions; `otel.name` sets the exported span
+//! name when the spans are bridged to OpenTelemetry.
+
+use crate::invocation_context::InvocationContext;
+use tracing::{field, info_span, Span};
+
+/// The instrumentation scope of the exported spans.
+pub const TRACER_NAME: &str = "coagent";
+
+pub fn invocation_span(context: &InvocationContext) -> Span {
+    info_span!(
+        parent: context.span(),
+        "invocation",
+        otel.name = "invocation",
+        gen_ai.conversation.id = %context.session().id(),
+        coagent.app.name = %context.app_name(),
+        coagent.invocation.id = %context.invocation_id(),
+        enduser.id = %context.user_id(),
+    )
+}
+
+pub fn agent_span(context: &InvocationContext, agent_name: &str, agent_description: &str) -> Span {
+    let branch = context.branch().unwrap_or(agent_name);
+    info_span!(
+        parent: context.span(),
+        "invoke_agent",
+        otel.name = %format!("invoke_agent {}", branch),
+        gen_ai.operation.name = "invoke_agent",
+        gen_ai.agent.name = %agent_name,
+        gen_ai.agent.description = %agent_description,
+        gen_ai.conversation.id = %context.session().id(),
+        coagent.branch = %branch,
+        coagent.invocation.id = %context.invocation_id(),
+    )
+}
+
+/// `provider` is the `gen_ai.system` of the model, e.g. `openai`.
+pub fn model_span(context: &InvocationContext, provider: &str, model: &str) -> Span {
+    info_span!(
+        parent: context.span(),
+        "chat",
+        otel.name = %format!("chat {}", model),
+        gen_ai.operation.name = "chat",
+        gen_ai.system = %provider,
+        gen_ai.request.model = %model,
+        gen_ai.usage.input_tokens = field::Empty,
+        gen_ai.usage.output_tokens = field::Empty,
+        error.type = field::Empty,
+    )
+}
+
+/// The call arguments may hold user data, so they are only recorded when the
+/// run config captures them.
+pub fn tool_span(
+    context: &InvocationContext,
+    tool_name: &str,
+    tool_description: &str,
+    function_call_id: Option<&str>,
+    args: &serde_json::Value,
+) -> Span {
+    let span = info_span!(
+        parent: context.span(),
+        "execute_tool",
+        otel.name = %format!("execute_tool {}", tool_name),
+        gen_ai.operation.name = "execute_tool",
+        gen_ai.tool.name = %tool_name,
+        gen_ai.tool.description = %tool_description,
+        gen_ai.tool.call.id = function_call_id.unwrap_or_default(),
+        gen_ai.tool.call.arguments = field::Empty,
+        coagent.tool.result_size = field::Empty,
+        error.type = field::Empty,
+    );
+    if context.run_config().capture_tool_arguments() {
+        span.record("gen_ai.tool.call.arguments", field::display(args));
+    }
+    span
+}
+
+/// Installs a global subscriber that exports spans over OTLP/HTTP, e.g. to
+/// `http://localhost:4318/v1/traces`. Call `shutdown` on the returned provider
+/// before exiting to flush pending spans.
+#[cfg(feature = "otlp")]
+pub fn init_otlp_tracing(
+    endpoint: &str,
+    service_name: &str,
+) -> Result<opentelemetry_sdk::trace::SdkTracerProvider, crate::common::AgentError> {
+    use crate::common::AgentError;
+    use opentelemetry::trace::TracerProvider;
+    use opentelemetry_otlp::{WithExportConfig, SpanExporter};
+    use opentelemetry_sdk::trace::SdkTracerProvider;
+    use opentelemetry_sdk::Resource;
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::util::SubscriberInitExt;
+
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(endpoint)
+        .build()
+        .map_err(|e| AgentError::TelemetryError(e.to_string()))?;
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(Resource::builder().with_service_name(service_name.to_string()).build())
+        .build();
+    let tracer = provider.tracer(TRACER_NAME);
+    tracing_subscriber::registry()
+        .with(tracing_opentelemetry::layer().with_tracer(tracer))
+        .try_init()
+        .map_err(|e| AgentError::TelemetryError(e.to_string()))?;
+    Ok(provider)
+}
@@ -0,0 +1,200 @@
+//! Agents, models and helpers shared by the unit tests.
+
+use crate::agent::Agent;
+use crate::artifact_service::InMemoryArtifactService;
+use crate::base_agent::{BaseAgentMessage, BaseAgentState};
+use crate::base_tool::BaseTool;
+use crate::common::{AgentError, Content, Event, FunctionCall, Part};
+use crate::invocation_context::InvocationContext;
+use crate::models::{BaseLlm, LlmRequest, LlmResponse};
+use crate::run_config::RunConfig;
+use crate::runner::Runner;
+use crate::session_service::{BaseSessionService, InMemorySessionService};
+use crate::tool_context::ToolContext;
+use async_trait::async_trait;
+use ractor::ActorRef;
+use serde_json::json;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::oneshot;
+use uuid::Uuid;
+
+/// Agents are registered by name, so every test spawns its own.
+pub(crate) fn unique_name(name: &str) -> String {
+    format!("{}-{}", name, Uuid::new_v4())
+}
+
+/// Answers every run with `answer`, after `delay` when one is set, or fails
+/// when `fail` is set. Counts its runs in `runs`.
+#[derive(Clone, Agent)]
+pub(crate) struct Reply {
+    #[agent(required)]
+    answer: String,
+    delay: Option<Duration>,
+    fail: bool,
+    runs: Arc<AtomicUsize>,
+}
+
+impl Reply {
+    /// Spawns the agent built by `builder` and returns it with its run
+    /// counter.
+    pub(crate) async fn spawn_with(builder: ReplyBuilder) -> (ActorRef<BaseAgentMessage>, Arc<AtomicUsize>) {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let agent = builder.runs(runs.clone()).spawn().await.unwrap();
+        (agent, runs)
+    }
+}
+
+#[async_trait]
+impl Agent for Reply {
+    async fn run_async_impl(&self, state: &BaseAgentState, context: &InvocationContext) -> Result<Vec<Event>, AgentError> {
+        self.runs.fetch_add(1, Ordering::SeqCst);
+        if let Some(delay) = self.delay {
+            tokio::time::sleep(delay).await;
+        }
+        if self.fail {
+            return Err(AgentError::AgentFailed(format!("{} failed", state.base.name())));
+        }
+        let event = Event::builder()
+            .invocation_id(context.invocation_id().to_string())
+            .author(state.base.name().to_string())
+            .branch(context.branch().map(|s| s.to_string()))
+            .content(Some(Content::from_text("model", &self.answer)))
+            .final_response(true)
+            .build();
+        context.emit_event(&event);
+        Ok(vec![event])
+    }
+}
+
+/// A model that gives its scripted replies in order and keeps the requests
+/// it was sent.
+pub(crate) struct ScriptedLlm {
+    replies: Mutex<VecDeque<Content>>,
+    requests: Mutex<Vec<LlmRequest>>,
+}
+
+impl ScriptedLlm {
+    pub(crate) fn new(replies: Vec<Content>) -> Arc<Self> {
+        Arc::new(ScriptedLlm {
+            replies: Mutex::new(replies.into()),
+            requests: Mutex::new(Vec::new()),
+        })
+    }
+
+    pub(crate) fn requests(&self) -> Vec<LlmRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl BaseLlm for ScriptedLlm {
+    fn model(&self) -> &str {
+        "scripted"
+    }
+
+    async fn generate_content(&self, request: LlmRequest) -> Result<LlmResponse, AgentError> {
+        self.requests.lock().unwrap().push(request);
+        let content = self
+            .replies
+            .lock()
+            .unwrap()
+            .pop_front()
+            .ok_or_else(|| AgentError::ModelError("No scripted reply left".to_string()))?;
+        Ok(LlmResponse {
+            content: Some(content),
+            ..LlmResponse::default()
+        })
+    }
+}
+
+/// A model reply calling `tool` with `args`.
+pub(crate) fn function_call(tool: &str, args: serde_json::Value) -> Content {
+    let call = FunctionCall {
+        id: Some("call-1".to_string()),
+        name: tool.to_string(),
+        args,
+    };
+    Content::new("model".to_string(), vec![Part::from_function_call(call)])
+}
+
+/// The tool results sent back to the model in `request`.
+pub(crate) fn function_responses(request: &LlmRequest) -> Vec<serde_json::Value> {
+    request
+        .contents
+        .iter()
+        .flat_map(|content| &content.parts)
+        .filter_map(|part| part.function_response.as_ref())
+        .map(|function_response| function_response.response.clone())
+        .collect()
+}
+
+/// A tool that answers with its arguments and counts its calls.
+pub(crate) struct Echo {
+    pub(crate) calls: AtomicUsize,
+}
+
+impl Echo {
+    pub(crate) fn new() -> Arc<Self> {
+        Arc::new(Echo { calls: AtomicUsize::new(0) })
+    }
+}
+
+#[async_trait]
+impl BaseTool for Echo {
+    fn name(&self) -> &str {
+        "echo"
+    }
+
+    fn description(&self) -> &str {
+        "Answers with its arguments."
+    }
+
+    async fn run_async(&self, args: serde_json::Value, _tool_context: ToolContext) -> Result<serde_json::Value, AgentError> {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        Ok(json!({ "echo": args }))
+    }
+}
+
+/// The receiver callbacks return, already holding `value`.
+pub(crate) fn ready<T>(value: T) -> oneshot::Receiver<T> {
+    let (sender, receiver) = oneshot::channel();
+    let _ = sender.send(value);
+    receiver
+}
+
+/// A runner for `agent` over in-memory services, with a new session.
+pub(crate) async fn runner_with_session(agent: &ActorRef<BaseAgentMessage>) -> (Runner, String) {
+    let runner = Runner::builder("test".to_string(), agent.get_cell()).build();
+    let session = runner
+        .session_service()
+        .create_session("test", "user", None, None)
+        .await
+        .unwrap();
+    (runner, session.id().to_string())
+}
+
+/// Runs `agent` on `text` in a new session.
+pub(crate) async fn run(agent: &ActorRef<BaseAgentMessage>, text: &str) -> Result<Vec<Event>, AgentError> {
+    let (runner, session_id) = runner_with_session(agent).await;
+    runner
+        .run_async("user", &session_id, Content::from_text("user", text), RunConfig::default())
+        .await
+}
+
+/// A context for a new invocation of `agent` in a new in-memory session.
+pub(crate) async fn context(agent: &ActorRef<BaseAgentMessage>) -> InvocationContext {
+    let session_service = Arc::new(InMemorySessionService::new());
+    let session = session_service.create_session("test", "user", None, None).await.unwrap();
+    InvocationContext::create(
+        session_service,
+        Arc::new(InMemoryArtifactService::new()),
+        InvocationContext::new_invocation_context_id(),
+        Arc::new(agent.get_cell()),
+        session,
+        None,
+        RunConfig::default(),
+    )
+}
@@ -0,0 +1,39 @@
+use crate::callback_context::CallbackContext;
+use crate::common::EventActions;
+use crate::invocation_context::InvocationContext;
+use std::collections::HashMap;
+
+#[derive(Clone, Debug)]
+pub struct ToolContext {
+    callback_context: CallbackContext,
+    function_call_id: Option<String>,
+}
+
+impl ToolContext {
+    pub fn new(invocation_context: InvocationContext, function_call_id: Option<String>) -> Self {
+        ToolContext {
+            callback_context: CallbackContext::new(invocation_context, None),
+            function_call_id,
+        }
+    }
+
+    pub fn callback_context(&self) -> &CallbackContext {
+        &self.callback_context
+    }
+
+    pub fn invocation_context(&self) -> &InvocationContext {
+        self.callback_context.invocation_context()
+    }
+
+    pub fn function_call_id(&self) -> Option<&str> {
+        self.function_call_id.as_deref()
+    }
+
+    pub fn state(&self) -> &HashMap<String, serde_json::Value> {
+        self.callback_context.state()
+    }
+
+    pub fn actions(&mut self) -> &mut EventActions {
+        self.callback_context.event_actions()
+    }
+}
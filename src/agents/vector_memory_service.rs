@@ -0,0 +1,390 @@
+use crate::common::{AgentError, Content, Session};
+use crate::embedder::Embedder;
+use crate::memory_service::{BaseMemoryService, MemoryEntry, SearchMemoryResponse};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct VectorRecord {
+    app_name: String,
+    user_id: String,
+    session_id: String,
+    #[serde(default)]
+    author: Option<String>,
+    text: String,
+    embedding: Vec<f32>,
+}
+
+/// The layout of the JSON file the records are persisted to.
+#[derive(Serialize, Deserialize)]
+struct VectorStore {
+    records: Vec<VectorRecord>,
+}
+
+/// Memory service that splits sessions into text chunks, embeds them with an
+/// `Embedder` and answers queries by cosine-similarity top-k search. With a
+/// path the records are persisted to a local JSON file, so the whole store
+/// runs on-device. All embeddings in a store share one dimension, so
+/// embeddings of another dimension are rejected rather than compared.
+#[derive(Debug)]
+pub struct VectorMemoryService {
+    embedder: Arc<dyn Embedder>,
+    path: Option<PathBuf>,
+    top_k: usize,
+    chunk_size: usize,
+    records: Mutex<Vec<VectorRecord>>,
+    /// Held from the snapshot to the rename, so concurrent writers cannot
+    /// replace a newer file with an older snapshot.
+    write_lock: tokio::sync::Mutex<()>,
+}
+
+impl VectorMemoryService {
+    pub fn builder(embedder: Arc<dyn Embedder>) -> VectorMemoryServiceBuilder {
+        VectorMemoryServiceBuilder {
+            embedder,
+            path: None,
+            top_k: 5,
+            chunk_size: 1000,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn chunk_session(&self, session: &Session) -> Vec<(Option<String>, String)> {
+        let mut chunks = Vec::new();
+        let mut text = String::new();
+        let mut authors: Vec<String> = Vec::new();
+
+        let mut flush = |text: &mut String, authors: &mut Vec<String>| {
+            if !text.is_empty() {
+                let author = if authors.len() == 1 { authors.pop() } else { None };
+                chunks.push((author, std::mem::take(text)));
+            }
+            authors.clear();
+        };
+
+        for event in session.events() {
+            let event_text = match event.content() {
+                Some(content) => content.text(),
+                None => continue,
+            };
+            if event_text.trim().is_empty() {
+                continue;
+            }
+            let line = format!("{}: {}", event.author, event_text.trim());
+            if !text.is_empty() && text.chars().count() + line.chars().count() + 1 > self.chunk_size {
+                flush(&mut text, &mut authors);
+            }
+            let chars: Vec<char> = line.chars().collect();
+            for piece in chars.chunks(self.chunk_size) {
+                if !text.is_empty() {
+                    if text.chars().count() + piece.len() + 1 > self.chunk_size {
+                        flush(&mut text, &mut authors);
+                    } else {
+                        text.push('\n');
+                    }
+                }
+                text.extend(piece);
+                if !authors.contains(&event.author) {
+                    authors.push(event.author.clone());
+                }
+            }
+        }
+        flush(&mut text, &mut authors);
+        chunks
+    }
+
+    fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+        let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+        let norm_a = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+        let norm_b = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+        if norm_a == 0.0 || norm_b == 0.0 {
+            return 0.0;
+        }
+        dot / (norm_a * norm_b)
+    }
+
+    fn check_dimension(expected: usize, embedding: &[f32]) -> Result<(), AgentError> {
+        if embedding.len() != expected {
+            return Err(AgentError::EmbeddingError(format!(
+                "Embedding has {} dimensions but the store holds {}-dimensional embeddings",
+                embedding.len(),
+                expected
+            )));
+        }
+        Ok(())
+    }
+
+    fn load(path: &PathBuf) -> Result<Vec<VectorRecord>, AgentError> {
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        let bytes = std::fs::read(path).map_err(|e| AgentError::StorageError(e.to_string()))?;
+        let store: VectorStore = serde_json::from_slice(&bytes)
+            .map_err(|e| AgentError::StorageError(format!("Invalid memory store {}: {}", path.display(), e)))?;
+        if let Some(first) = store.records.first() {
+            for record in &store.records {
+                Self::check_dimension(first.embedding.len(), &record.embedding).map_err(|e| {
+                    AgentError::StorageError(format!("Invalid memory store {}: {}", path.display(), e))
+                })?;
+            }
+        }
+        Ok(store.records)
+    }
+
+    async fn persist(&self) -> Result<(), AgentError> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let _write = self.write_lock.lock().await;
+        let body = {
+            let records = self.records.lock().unwrap().clone();
+            serde_json::to_vec(&VectorStore { records }).map_err(|e| AgentError::StorageError(e.to_string()))?
+        };
+        let temp_path = path.with_extension("tmp");
+        tokio::fs::write(&temp_path, body)
+            .await
+            .map_err(|e| AgentError::StorageError(e.to_string()))?;
+        tokio::fs::rename(&temp_path, path)
+            .await
+            .map_err(|e| AgentError::StorageError(e.to_string()))
+    }
+}
+
+#[async_trait]
+impl BaseMemoryService for VectorMemoryService {
+    async fn add_session_to_memory(&self, session: &Session) -> Result<(), AgentError> {
+        let chunks = self.chunk_session(session);
+        let texts: Vec<String> = chunks.iter().map(|(_, text)| text.clone()).collect();
+        let embeddings = self.embedder.embed(&texts).await?;
+        if embeddings.len() != texts.len() {
+            return Err(AgentError::EmbeddingError(format!(
+                "Embedder returned {} embeddings for {} chunks",
+                embeddings.len(),
+                texts.len()
+            )));
+        }
+        {
+            let mut records = self.records.lock().unwrap();
+            let is_this_session = |record: &VectorRecord| {
+                record.app_name == session.app_name()
+                    && record.user_id == session.user_id()
+                    && record.session_id == session.id()
+            };
+            // The records this session replaces don't count, so re-adding the
+            // only session in a store may change its dimension.
+            let expected = records
+                .iter()
+                .find(|record| !is_this_session(record))
+                .map(|record| record.embedding.len())
+                .or_else(|| embeddings.first().map(|embedding| embedding.len()));
+            if let Some(expected) = expected {
+                for embedding in &embeddings {
+                    Self::check_dimension(expected, embedding)?;
+                }
+            }
+            records.retain(|record| !is_this_session(record));
+            for ((author, text), embedding) in chunks.into_iter().zip(embeddings) {
+                records.push(VectorRecord {
+                    app_name: session.app_name().to_string(),
+                    user_id: session.user_id().to_string(),
+                    session_id: session.id().to_string(),
+                    author,
+                    text,
+                    embedding,
+                });
+            }
+        }
+        self.persist().await
+    }
+
+    async fn search_memory(&self, app_name: &str, user_id: &str, query: &str) -> Result<SearchMemoryResponse, AgentError> {
+        let query_embedding = self
+            .embedder
+            .embed(&[query.to_string()])
+            .await?
+            .pop()
+            .ok_or_else(|| AgentError::EmbeddingError("Embedder returned no embedding for the query".to_string()))?;
+        let records = self.records.lock().unwrap();
+        if let Some(first) = records.first() {
+            Self::check_dimension(first.embedding.len(), &query_embedding)?;
+        }
+        let mut scored: Vec<(f32, &VectorRecord)> = records
+            .iter()
+            .filter(|record| record.app_name == app_name && record.user_id == user_id)
+            .map(|record| (Self::cosine_similarity(&query_embedding, &record.embedding), record))
+            .filter(|(score, _)| *score > 0.0)
+            .collect();
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        let memories = scored
+            .into_iter()
+            .take(self.top_k)
+            .map(|(_, record)| MemoryEntry {
+                content: Content::from_text("user", &record.text),
+                author: record.author.clone(),
+                session_id: record.session_id.clone(),
+            })
+            .collect();
+        Ok(SearchMemoryResponse { memories })
+    }
+}
+
+pub struct VectorMemoryServiceBuilder {
+    embedder: Arc<dyn Embedder>,
+    path: Option<PathBuf>,
+    top_k: usize,
+    chunk_size: usize,
+}
+
+impl VectorMemoryServiceBuilder {
+    pub fn path(mut self, path: PathBuf) -> Self {
+        self.path = Some(path);
+        self
+    }
+
+    pub fn top_k(mut self, top_k: usize) -> Self {
+        self.top_k = top_k;
+        self
+    }
+
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    pub fn build(self) -> Result<VectorMemoryService, AgentError> {
+        let records = match &self.path {
+            Some(path) => VectorMemoryService::load(path)?,
+            None => Vec::new(),
+        };
+        Ok(VectorMemoryService {
+            embedder: self.embedder,
+            path: self.path,
+            top_k: self.top_k,
+            chunk_size: self.chunk_size,
+            records: Mutex::new(records),
+            write_lock: tokio::sync::Mutex::new(()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::Event;
+    use crate::embedder::HashingEmbedder;
+    use std::collections::HashMap;
+    use std::path::Path;
+    use uuid::Uuid;
+
+    fn session(id: &str, texts: &[&str]) -> Session {
+        let mut session = Session::new("app".to_string(), "user".to_string(), id.to_string(), HashMap::new());
+        for text in texts {
+            let event = Event::builder()
+                .author("user".to_string())
+                .content(Some(Content::from_text("user", text)))
+                .build();
+            session.append_event(event);
+        }
+        session
+    }
+
+    fn service(dimensions: usize, path: &Path) -> VectorMemoryService {
+        VectorMemoryService::builder(Arc::new(HashingEmbedder::new(dimensions)))
+            .path(path.to_path_buf())
+            .build()
+            .unwrap()
+    }
+
+    fn temp_path() -> PathBuf {
+        std::env::temp_dir().join(format!("coagent-memory-{}.json", Uuid::new_v4()))
+    }
+
+    #[tokio::test]
+    async fn search_finds_what_was_stored_after_reload() {
+        let path = temp_path();
+        let memory = service(64, &path);
+        memory
+            .add_session_to_memory(&session("first", &["The cat sleeps on the mat"]))
+            .await
+            .unwrap();
+        memory
+            .add_session_to_memory(&session("second", &["Rust compiles to native code"]))
+            .await
+            .unwrap();
+
+        let reopened = service(64, &path);
+        assert_eq!(reopened.len(), 2);
+        let found = reopened.search_memory("app", "user", "where does the cat sleep").await.unwrap();
+        assert_eq!(found.memories[0].session_id, "first");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn malformed_records_fail_the_load() {
+        let path = temp_path();
+        std::fs::write(
+            &path,
+            r#"{"records":[{"app_name":"app","user_id":"user","session_id":"s","text":"hi","embedding":["x"]}]}"#,
+        )
+        .unwrap();
+        let result = VectorMemoryService::builder(Arc::new(HashingEmbedder::new(4))).path(path.clone()).build();
+        assert!(matches!(result, Err(AgentError::StorageError(_))));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn embeddings_of_another_dimension_are_rejected() {
+        let path = temp_path();
+        service(64, &path)
+            .add_session_to_memory(&session("first", &["The cat sleeps"]))
+            .await
+            .unwrap();
+
+        let resized = service(32, &path);
+        let added = resized.add_session_to_memory(&session("second", &["A dog barks"])).await;
+        assert!(matches!(added, Err(AgentError::EmbeddingError(_))));
+        let searched = resized.search_memory("app", "user", "cat").await;
+        assert!(matches!(searched, Err(AgentError::EmbeddingError(_))));
+        assert_eq!(resized.len(), 1);
+
+        // Replacing the only session in the store may change the dimension.
+        resized
+            .add_session_to_memory(&session("first", &["The cat sleeps"]))
+            .await
+            .unwrap();
+        assert_eq!(resized.search_memory("app", "user", "cat").await.unwrap().memories.len(), 1);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn concurrent_adds_all_reach_the_file() {
+        let path = temp_path();
+        let memory = Arc::new(service(64, &path));
+        let mut adds = tokio::task::JoinSet::new();
+        for index in 0..16 {
+            let memory = memory.clone();
+            adds.spawn(async move {
+                let text = format!("note number {}", index);
+                memory
+                    .add_session_to_memory(&session(&index.to_string(), &[&text]))
+                    .await
+                    .unwrap();
+            });
+        }
+        while let Some(add) = adds.join_next().await {
+            add.unwrap();
+        }
+        assert_eq!(service(64, &path).len(), 16);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,517 @@
+//! An agent that runs its sub-agents in parallel, like `ParallelAgent`, and
+//! combines their answers into one. Each sub-agent can be run several times
+//! to sample it. The answer of a run is the text of its last event with text;
+//! the answers are combined by majority vote, by a model judging them, or by
+//! a merge function. A quorum lets some runs fail, and an `EarlyExit` stops
+//! waiting for the slower runs once enough answers are in. The final event
+//! records every candidate with its score in an `AgentVote`.
+
+use crate::agent::Agent;
+use crate::base_agent::BaseAgentState;
+use crate::common::{AgentError, AgentVote, Content, Event, EventActions, VoteCandidate};
+use crate::invocation_context::InvocationContext;
+use crate::models::{BaseLlm, LlmRequest};
+use crate::parallel_agent::FanOut;
+use crate::schema;
+use async_trait::async_trait;
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+type Normalizer = Arc<dyn Fn(&str) -> String + Send + Sync>;
+type MergeFn = Arc<dyn Fn(&[VoteCandidate]) -> String + Send + Sync>;
+
+#[derive(Clone)]
+enum AggregationKind {
+    Majority(Normalizer),
+    Judge(Arc<dyn BaseLlm>),
+    Merge(MergeFn),
+}
+
+/// How a `VotingAgent` turns the candidate answers into one.
+#[derive(Clone)]
+pub struct Aggregation {
+    kind: AggregationKind,
+}
+
+impl Aggregation {
+    /// Picks the most common answer, compared after `normalize_answer`.
+    /// Ties go to the answer that arrived first.
+    pub fn majority() -> Self {
+        Aggregation::majority_by(normalize_answer)
+    }
+
+    /// Picks the most common answer, compared after `normalize`.
+    pub fn majority_by(normalize: impl Fn(&str) -> String + Send + Sync + 'static) -> Self {
+        Aggregation {
+            kind: AggregationKind::Majority(Arc::new(normalize)),
+        }
+    }
+
+    /// Asks `model` to score every answer and pick the best one.
+    pub fn judge(model: Arc<dyn BaseLlm>) -> Self {
+        Aggregation {
+            kind: AggregationKind::Judge(model),
+        }
+    }
+
+    /// Combines the answers with `merge`.
+    pub fn merge(merge: impl Fn(&[VoteCandidate]) -> String + Send + Sync + 'static) -> Self {
+        Aggregation {
+            kind: AggregationKind::Merge(Arc::new(merge)),
+        }
+    }
+
+    fn normalize(&self, answer: &str) -> String {
+        match &self.kind {
+            AggregationKind::Majority(normalize) => normalize(answer),
+            _ => normalize_answer(answer),
+        }
+    }
+}
+
+/// Lowercases the answer, collapses whitespace and drops trailing periods.
+pub fn normalize_answer(answer: &str) -> String {
+    answer
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .trim_end_matches('.')
+        .to_lowercase()
+}
+
+/// Scores every candidate with the share of answers agreeing with it and
+/// picks the most common answer, the earliest one on a tie.
+fn majority_vote(normalize: &(dyn Fn(&str) -> String + Send + Sync), mut candidates: Vec<VoteCandidate>) -> AgentVote {
+    let keys: Vec<String> = candidates.iter().map(|candidate| normalize(&candidate.answer)).collect();
+    let mut votes: HashMap<&str, usize> = HashMap::new();
+    for key in &keys {
+        *votes.entry(key.as_str()).or_insert(0) += 1;
+    }
+    let winner = (0..keys.len())
+        .max_by(|a, b| votes[keys[*a].as_str()].cmp(&votes[keys[*b].as_str()]).then(b.cmp(a)))
+        .unwrap_or_default();
+    let total = candidates.len();
+    for (candidate, key) in candidates.iter_mut().zip(&keys) {
+        candidate.score = Some(votes[key.as_str()] as f64 / total as f64);
+    }
+    AgentVote {
+        method: "majority".to_string(),
+        reason: Some(format!("{} of {} answers agree", votes[keys[winner].as_str()], total)),
+        winner: Some(winner),
+        candidates,
+    }
+}
+
+/// When a `VotingAgent` stops waiting for the remaining runs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EarlyExit {
+    /// Waits for every run.
+    #[default]
+    Never,
+    /// Stops once the quorum of answers is in.
+    Quorum,
+    /// Stops once this many answers agree after normalization.
+    Agreement(usize),
+}
+
+/// Runs its sub-agents in parallel and combines their answers.
+#[derive(Clone, Agent)]
+pub struct VotingAgent {
+    #[agent(required)]
+    aggregation: Aggregation,
+    /// Runs of each sub-agent.
+    #[agent(default = 1)]
+    samples: usize,
+    /// Answers needed to combine them. Without a quorum every run must
+    /// succeed.
+    quorum: Option<usize>,
+    early_exit: EarlyExit,
+    /// State key the combined answer is written to.
+    output_key: Option<String>,
+}
+
+impl VotingAgent {
+    pub fn aggregation(&self) -> &Aggregation {
+        &self.aggregation
+    }
+
+    pub fn samples(&self) -> usize {
+        self.samples
+    }
+
+    pub fn quorum(&self) -> Option<usize> {
+        self.quorum
+    }
+
+    pub fn early_exit(&self) -> EarlyExit {
+        self.early_exit
+    }
+
+    pub fn output_key(&self) -> Option<&str> {
+        self.output_key.as_deref()
+    }
+
+    fn enough(&self, candidates: &[VoteCandidate], quorum: usize) -> bool {
+        match self.early_exit {
+            EarlyExit::Never => false,
+            EarlyExit::Quorum => candidates.len() >= quorum,
+            EarlyExit::Agreement(agreeing) => {
+                let mut counts = HashMap::new();
+                candidates.iter().any(|candidate| {
+                    let count = counts.entry(self.aggregation.normalize(&candidate.answer)).or_insert(0);
+                    *count += 1;
+                    *count >= agreeing
+                })
+            }
+        }
+    }
+
+    async fn combine(
+        &self,
+        state: &BaseAgentState,
+        context: &InvocationContext,
+        candidates: Vec<VoteCandidate>,
+    ) -> Result<(String, AgentVote), AgentError> {
+        match &self.aggregation.kind {
+            AggregationKind::Majority(normalize) => {
+                let vote = majority_vote(normalize.as_ref(), candidates);
+                let winner = vote.winner.unwrap_or_default();
+                Ok((vote.candidates[winner].answer.clone(), vote))
+            }
+            AggregationKind::Judge(model) => self.judge(state, context, model.as_ref(), candidates).await,
+            AggregationKind::Merge(merge) => {
+                let answer = merge(&candidates);
+                let vote = AgentVote {
+                    method: "merge".to_string(),
+                    candidates,
+                    winner: None,
+                    reason: None,
+                };
+                Ok((answer, vote))
+            }
+        }
+    }
+
+    async fn judge(
+        &self,
+        state: &BaseAgentState,
+        context: &InvocationContext,
+        model: &dyn BaseLlm,
+        mut candidates: Vec<VoteCandidate>,
+    ) -> Result<(String, AgentVote), AgentError> {
+        let count = candidates.len();
+        let response_schema = json!({
+            "type": "object",
+            "properties": {
+                "best": { "type": "integer", "minimum": 0, "maximum": count - 1 },
+                "scores": { "type": "array", "items": { "type": "number" }, "minItems": count, "maxItems": count },
+                "reason": { "type": "string" }
+            },
+            "required": ["best", "scores", "reason"]
+        });
+        let listing = candidates
+            .iter()
+            .enumerate()
+            .map(|(index, candidate)| format!("[{}]\n{}", index, candidate.answer))
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        let request = LlmRequest {
+            model: model.model().to_string(),
+            system_instruction: Some(format!(
+                "Judge the candidate answers to the user's request. Score every candidate from 0 to 10, \
+                 in the order given, and pick the best one by its number.\n\n\
+                 Reply with only a JSON value that matches this JSON Schema:\n{}",
+                response_schema
+            )),
+            contents: context
+                .user_content()
+                .cloned()
+                .into_iter()
+                .chain(std::iter::once(Content::from_text("user", &format!("Candidate answers:\n\n{}", listing))))
+                .collect(),
+            tools: Vec::new(),
+            response_schema: Some(response_schema.clone()),
+        };
+        let mut context = context.clone();
+        let response = state.base.call_llm(model, request, &mut context).await?;
+        if let Some(error) = response.error_message {
+            return Err(AgentError::ModelError(error));
+        }
+        let text = response.content.map(|content| content.text()).unwrap_or_default();
+        let value = schema::parse_json_output(&text).map_err(AgentError::OutputValidationError)?;
+        let errors = schema::validate(&response_schema, &value);
+        if !errors.is_empty() {
+            return Err(AgentError::OutputValidationError(format!(
+                "Judge of {} gave an invalid verdict: {}",
+                state.base.name(),
+                errors.join("; ")
+            )));
+        }
+        let winner = match value["best"].as_f64() {
+            Some(best) if best.fract() == 0.0 && best >= 0.0 && (best as usize) < count => best as usize,
+            _ => {
+                return Err(AgentError::OutputValidationError(format!(
+                    "Judge of {} picked {} instead of a candidate number",
+                    state.base.name(),
+                    value["best"]
+                )))
+            }
+        };
+        for (candidate, score) in candidates.iter_mut().zip(value["scores"].as_array().into_iter().flatten()) {
+            candidate.score = score.as_f64();
+        }
+        let vote = AgentVote {
+            method: "judge".to_string(),
+            reason: value["reason"].as_str().map(|reason| reason.to_string()),
+            winner: Some(winner),
+            candidates,
+        };
+        Ok((vote.candidates[winner].answer.clone(), vote))
+    }
+}
+
+#[async_trait]
+impl Agent for VotingAgent {
+    async fn run_async_impl(&self, state: &BaseAgentState, context: &InvocationContext) -> Result<Vec<Event>, AgentError> {
+        let name = state.base.name();
+        let supervisor = &state.supervisor;
+        let runs = supervisor.len() * self.samples;
+        let quorum = self.quorum.unwrap_or(runs);
+        if quorum == 0 || quorum > runs {
+            return Err(AgentError::ConfigError(format!(
+                "Voting agent {} has a quorum of {} for {} runs",
+                name, quorum, runs
+            )));
+        }
+        let names: Vec<String> = supervisor
+            .children()
+            .iter()
+            .map(|child| child.get_name().unwrap_or_default())
+            .collect();
+
+        let mut fan_out = FanOut::start(supervisor, context, self.samples);
+        let mut events = Vec::new();
+        let mut candidates = Vec::new();
+        let mut failed = 0;
+        while let Some((index, result)) = fan_out.next().await {
+            let answer = result.and_then(|run_events| {
+                let answer = run_events
+                    .iter()
+                    .rev()
+                    .filter_map(|event| event.content.as_ref().map(|content| content.text()))
+                    .find(|text| !text.is_empty());
+                events.extend(run_events);
+                answer.ok_or_else(|| AgentError::AgentFailed(format!("Sub-agent {} gave no answer", names[index])))
+            });
+            match answer {
+                Ok(answer) => candidates.push(VoteCandidate {
+                    agent: names[index].clone(),
+                    answer,
+                    score: None,
+                }),
+                Err(error) if self.quorum.is_some() => {
+                    tracing::warn!("Voting agent {} dropped a run: {}", name, error);
+                    failed += 1;
+                    if runs - failed < quorum {
+                        return Err(AgentError::AgentFailed(format!(
+                            "Voting agent {} cannot reach its quorum of {}: {} of {} runs failed",
+                            name, quorum, failed, runs
+                        )));
+                    }
+                }
+                Err(error) => return Err(error),
+            }
+            if self.enough(&candidates, quorum) {
+                break;
+            }
+        }
+
+        let (answer, vote) = self.combine(state, context, candidates).await?;
+        let mut actions = EventActions::builder().build();
+        if let Some(output_key) = &self.output_key {
+            actions.state_delta().insert(output_key.clone(), serde_json::Value::String(answer.clone()));
+        }
+        let event = Event::builder()
+            .invocation_id(context.invocation_id().to_string())
+            .author(name.to_string())
+            .branch(context.branch().map(|s| s.to_string()))
+            .content(Some(Content::from_text("model", &answer)))
+            .actions(actions)
+            .final_response(true)
+            .vote(Some(vote))
+            .build();
+        context.emit_event(&event);
+        events.push(event);
+        Ok(events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{run, unique_name, Reply, ScriptedLlm};
+    use std::time::{Duration, Instant};
+
+    fn candidates(answers: &[&str]) -> Vec<VoteCandidate> {
+        answers
+            .iter()
+            .enumerate()
+            .map(|(index, answer)| VoteCandidate {
+                agent: format!("agent-{}", index),
+                answer: answer.to_string(),
+                score: None,
+            })
+            .collect()
+    }
+
+    async fn voting(builder: VotingAgentBuilder, answers: &[(&str, Option<Duration>, bool)]) -> Result<Vec<Event>, AgentError> {
+        let mut sub_agents = Vec::new();
+        for (answer, delay, fail) in answers {
+            let mut reply = Reply::builder(answer.to_string()).name(unique_name("voter")).fail(*fail);
+            if let Some(delay) = delay {
+                reply = reply.delay(*delay);
+            }
+            let (agent, _) = Reply::spawn_with(reply).await;
+            sub_agents.push(Arc::new(agent.get_cell()));
+        }
+        let agent = builder.name(unique_name("voting")).sub_agents(sub_agents).spawn().await.unwrap();
+        run(&agent, "question").await
+    }
+
+    fn vote(events: &[Event]) -> &AgentVote {
+        events.last().and_then(|event| event.vote.as_ref()).unwrap()
+    }
+
+    #[test]
+    fn normalize_answer_ignores_case_spacing_and_trailing_periods() {
+        assert_eq!(normalize_answer("  The   Answer is\n42.. "), "the answer is 42");
+        assert_eq!(normalize_answer("Paris"), normalize_answer("paris."));
+        assert_eq!(normalize_answer(""), "");
+    }
+
+    #[test]
+    fn majority_picks_the_most_common_answer() {
+        let vote = majority_vote(&normalize_answer, candidates(&["Paris", "Lyon", "paris."]));
+        assert_eq!(vote.winner, Some(0));
+        assert_eq!(vote.reason.as_deref(), Some("2 of 3 answers agree"));
+        let scores: Vec<f64> = vote.candidates.iter().filter_map(|candidate| candidate.score).collect();
+        assert_eq!(scores, vec![2.0 / 3.0, 1.0 / 3.0, 2.0 / 3.0]);
+    }
+
+    #[test]
+    fn majority_tie_goes_to_the_earliest_answer() {
+        let vote = majority_vote(&normalize_answer, candidates(&["Lyon", "Paris", "paris", "lyon"]));
+        assert_eq!(vote.winner, Some(0));
+        let vote = majority_vote(&normalize_answer, candidates(&["Nice", "Paris", "Lyon"]));
+        assert_eq!(vote.winner, Some(0));
+    }
+
+    #[test]
+    fn majority_by_uses_the_given_normalizer() {
+        let vote = majority_vote(&|answer: &str| answer.chars().take(1).collect(), candidates(&["apple", "banana", "avocado"]));
+        assert_eq!(vote.winner, Some(0));
+        assert_eq!(vote.reason.as_deref(), Some("2 of 3 answers agree"));
+    }
+
+    #[tokio::test]
+    async fn without_a_quorum_every_run_must_succeed() {
+        let result = voting(
+            VotingAgent::builder(Aggregation::majority()),
+            &[("yes", None, false), ("no", None, true)],
+        )
+        .await;
+        assert!(matches!(result, Err(AgentError::AgentFailed(_))));
+    }
+
+    #[tokio::test]
+    async fn quorum_tolerates_failed_runs() {
+        let events = voting(
+            VotingAgent::builder(Aggregation::majority()).quorum(2),
+            &[("yes", None, false), ("no", None, true), ("Yes.", None, false)],
+        )
+        .await
+        .unwrap();
+        let vote = vote(&events);
+        assert_eq!(vote.candidates.len(), 2);
+        assert_eq!(events.last().unwrap().content.as_ref().unwrap().text(), vote.candidates[vote.winner.unwrap()].answer);
+    }
+
+    #[tokio::test]
+    async fn unreachable_quorum_fails() {
+        let result = voting(
+            VotingAgent::builder(Aggregation::majority()).quorum(2),
+            &[("yes", None, false), ("no", None, true), ("maybe", None, true)],
+        )
+        .await;
+        match result {
+            Err(AgentError::AgentFailed(message)) => assert!(message.contains("cannot reach its quorum of 2"), "{}", message),
+            other => panic!("expected a quorum failure, got {:?}", other.map(|events| events.len())),
+        }
+    }
+
+    #[tokio::test]
+    async fn quorum_larger_than_the_runs_is_a_config_error() {
+        let result = voting(VotingAgent::builder(Aggregation::majority()).quorum(3), &[("yes", None, false)]).await;
+        assert!(matches!(result, Err(AgentError::ConfigError(_))));
+    }
+
+    #[tokio::test]
+    async fn early_exit_on_agreement_skips_slow_runs() {
+        let slow = Some(Duration::from_secs(30));
+        let started_at = Instant::now();
+        let events = voting(
+            VotingAgent::builder(Aggregation::majority()).early_exit(EarlyExit::Agreement(2)),
+            &[("4", None, false), ("4.", None, false), ("5", slow, false)],
+        )
+        .await
+        .unwrap();
+        assert!(started_at.elapsed() < Duration::from_secs(10));
+        let vote = vote(&events);
+        assert_eq!(vote.candidates.len(), 2);
+        assert_eq!(normalize_answer(&vote.candidates[vote.winner.unwrap()].answer), "4");
+    }
+
+    #[tokio::test]
+    async fn early_exit_on_quorum_stops_at_the_quorum() {
+        let slow = Some(Duration::from_secs(30));
+        let started_at = Instant::now();
+        let events = voting(
+            VotingAgent::builder(Aggregation::majority()).quorum(1).early_exit(EarlyExit::Quorum),
+            &[("fast", None, false), ("slow", slow, false)],
+        )
+        .await
+        .unwrap();
+        assert!(started_at.elapsed() < Duration::from_secs(10));
+        assert_eq!(vote(&events).candidates.len(), 1);
+        assert_eq!(events.last().unwrap().content.as_ref().unwrap().text(), "fast");
+    }
+
+    #[tokio::test]
+    async fn judge_accepts_whole_numbers_written_as_floats() {
+        let verdict = r#"{"best": 1.0, "scores": [2, 9], "reason": "the second one is right"}"#;
+        let judge = ScriptedLlm::new(vec![Content::from_text("model", verdict)]);
+        let events = voting(
+            VotingAgent::builder(Aggregation::judge(judge)),
+            &[("wrong", None, false), ("right", Some(Duration::from_millis(50)), false)],
+        )
+        .await
+        .unwrap();
+        let vote = vote(&events);
+        assert_eq!(vote.method, "judge");
+        assert_eq!(vote.winner, Some(1));
+        assert_eq!(events.last().unwrap().content.as_ref().unwrap().text(), "right");
+    }
+
+    #[tokio::test]
+    async fn merge_combines_every_answer() {
+        let merge = Aggregation::merge(|candidates| {
+            let mut answers: Vec<&str> = candidates.iter().map(|candidate| candidate.answer.as_str()).collect();
+            answers.sort();
+            answers.join("+")
+        });
+        let events = voting(VotingAgent::builder(merge), &[("b", None, false), ("a", None, false)]).await.unwrap();
+        assert_eq!(events.last().unwrap().content.as_ref().unwrap().text(), "a+b");
+        assert_eq!(vote(&events).winner, None);
+    }
+}
@@ -0,0 +1,7 @@
+// Lets the code generated by `coagent-macros` name this crate from inside it.
+extern crate self as coagent;
+
+pub mod agents;
+
+pub use agents::*;
+pub use ractor;
@@ -1,3 +1,265 @@
-fn main() {
-    println!("Hello, world!");
+use clap::{Parser, Subcommand};
+use coagent::agent_config::{AgentConfig, AgentRegistry};
+use coagent::common::{AgentError, Content, Event};
+use coagent::load_memory_tool::LoadMemoryTool;
+use coagent::memory_service::{BaseMemoryService, InMemoryMemoryService};
+use coagent::models::BaseLlm;
+use coagent::openai_llm::OpenAiLlm;
+use coagent::run_config::RunConfig;
+use coagent::runner::Runner;
+use coagent::session_service::{BaseSessionService, FileSessionService, InMemorySessionService};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+#[derive(Parser)]
+#[command(name = "coagent", version, about = "Run and inspect declarative coagent agent trees")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+
+    /// Base URL of an OpenAI-compatible API serving the models.
+    #[arg(long, global = true, env = "OPENAI_BASE_URL", default_value = "http://localhost:11434/v1")]
+    base_url: String,
+
+    #[arg(long, global = true, env = "OPENAI_API_KEY", hide_env_values = true)]
+    api_key: Option<String>,
+
+    /// Model for LLM agents that do not name one.
+    #[arg(long, global = true)]
+    model: Option<String>,
+
+    /// JSON file to keep sessions in. Sessions are kept in memory otherwise.
+    #[arg(long, global = true)]
+    sessions: Option<PathBuf>,
+
+    /// Maximum number of model calls per invocation, 0 for no limit.
+    #[arg(long, global = true, default_value_t = 500)]
+    max_llm_calls: i32,
+
+    #[arg(long, global = true, default_value = "user")]
+    user: String,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run the agent tree once and print its events as they arrive.
+    Run {
+        config: PathBuf,
+        /// Prompt to send; read from stdin when omitted.
+        prompt: Vec<String>,
+        /// Session to continue; a new one is created when omitted.
+        #[arg(long)]
+        session: Option<String>,
+    },
+    /// Chat with the agent tree in one session.
+    Chat {
+        config: PathBuf,
+        /// Session to continue or create.
+        #[arg(long)]
+        session: Option<String>,
+    },
+    /// Print the agent hierarchy with descriptions and tools.
+    Tree { config: PathBuf },
+    /// Serve the agent tree over HTTP until interrupted.
+    #[cfg(feature = "server")]
+    Serve {
+        config: PathBuf,
+        #[arg(long, default_value = "127.0.0.1:8000")]
+        addr: String,
+    },
+}
+
+struct App {
+    runner: Runner,
+    memory_service: Arc<dyn BaseMemoryService>,
+    run_config: RunConfig,
+    user: String,
+}
+
+impl App {
+    async fn load(cli: &Cli, config_path: &Path) -> Result<Self, AgentError> {
+        let config = AgentConfig::from_file(config_path)?;
+        let base_url = cli.base_url.clone();
+        let api_key = cli.api_key.clone();
+        let mut registry = AgentRegistry::new()
+            .tool(Arc::new(LoadMemoryTool::new()))
+            .model_provider(Arc::new(move |model: &str| -> Option<Arc<dyn BaseLlm>> {
+                Some(Arc::new(OpenAiLlm::new(base_url.clone(), api_key.clone(), model.to_string())))
+            }));
+        if let Some(model) = &cli.model {
+            registry = registry.default_model(model.clone());
+        }
+        let root = config.spawn(&registry).await?;
+
+        let session_service: Arc<dyn BaseSessionService> = match &cli.sessions {
+            Some(path) => Arc::new(FileSessionService::open(path.clone())?),
+            None => Arc::new(InMemorySessionService::new()),
+        };
+        let memory_service: Arc<dyn BaseMemoryService> = Arc::new(InMemoryMemoryService::new());
+        let runner = Runner::builder(config.name.clone(), root)
+            .session_service(session_service)
+            .memory_service(memory_service.clone())
+            .build();
+        Ok(App {
+            runner,
+            memory_service,
+            run_config: RunConfig::builder().set_max_llm_calls(cli.max_llm_calls).build(),
+            user: cli.user.clone(),
+        })
+    }
+
+    async fn session(&self, session_id: Option<String>) -> Result<String, AgentError> {
+        let session_service = self.runner.session_service();
+        if let Some(session_id) = &session_id {
+            if let Some(session) = session_service.get_session(self.runner.app_name(), &self.user, session_id).await? {
+                return Ok(session.id().to_string());
+            }
+        }
+        let session = session_service
+            .create_session(self.runner.app_name(), &self.user, None, session_id)
+            .await?;
+        Ok(session.id().to_string())
+    }
+
+    async fn send(&self, session_id: &str, prompt: &str) -> Result<(), AgentError> {
+        let mut events = self
+            .runner
+            .run_stream(&self.user, session_id, Content::from_text("user", prompt), self.run_config.clone());
+        while let Some(event) = events.recv().await {
+            print_event(&event?);
+        }
+        if let Some(session) = self
+            .runner
+            .session_service()
+            .get_session(self.runner.app_name(), &self.user, session_id)
+            .await?
+        {
+            self.memory_service.add_session_to_memory(&session).await?;
+        }
+        Ok(())
+    }
+}
+
+fn print_event(event: &Event) {
+    let content = match event.content() {
+        Some(content) => content,
+        None => return,
+    };
+    for part in &content.parts {
+        if let Some(text) = part.text.as_deref().filter(|text| !text.trim().is_empty()) {
+            match part.thought {
+                Some(thought) => println!("[{}] ({:?}) {}", event.author, thought, text.trim()),
+                None => println!("[{}] {}", event.author, text.trim()),
+            }
+        }
+        if let Some(function_call) = &part.function_call {
+            println!("[{}] -> {}({})", event.author, function_call.name, function_call.args);
+        }
+        if let Some(function_response) = &part.function_response {
+            println!("[{}] <- {}: {}", event.author, function_response.name, function_response.response);
+        }
+    }
+}
+
+fn print_tree(config: &AgentConfig, prefix: &str, connector: &str, child_prefix: &str) {
+    let mut details = vec![config.kind.as_str().to_string()];
+    if let Some(model) = &config.model {
+        details.push(format!("model: {}", model));
+    }
+    if let Some(max_iterations) = config.max_iterations {
+        details.push(format!("max_iterations: {}", max_iterations));
+    }
+    let description = if config.description.is_empty() {
+        String::new()
+    } else {
+        format!(" - {}", config.description)
+    };
+    println!("{}{}{} ({}){}", prefix, connector, config.name, details.join(", "), description);
+    let next_prefix = format!("{}{}", prefix, child_prefix);
+    if !config.tools.is_empty() {
+        let bar = if config.sub_agents.is_empty() { "   " } else { "│  " };
+        println!("{}{}tools: {}", next_prefix, bar, config.tools.join(", "));
+    }
+    for (index, sub_agent) in config.sub_agents.iter().enumerate() {
+        if index + 1 == config.sub_agents.len() {
+            print_tree(sub_agent, &next_prefix, "└─ ", "   ");
+        } else {
+            print_tree(sub_agent, &next_prefix, "├─ ", "│  ");
+        }
+    }
+}
+
+async fn read_stdin() -> Result<String, AgentError> {
+    let mut prompt = String::new();
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    while let Some(line) = lines.next_line().await.map_err(|e| AgentError::StorageError(e.to_string()))? {
+        prompt.push_str(&line);
+        prompt.push('\n');
+    }
+    Ok(prompt)
+}
+
+async fn chat(app: &App, session_id: &str) -> Result<(), AgentError> {
+    println!("Session {}. Type /exit to quit.", session_id);
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    loop {
+        print!("> ");
+        let _ = std::io::stdout().flush();
+        let line = match lines.next_line().await.map_err(|e| AgentError::StorageError(e.to_string()))? {
+            Some(line) => line,
+            None => return Ok(()),
+        };
+        match line.trim() {
+            "" => continue,
+            "/exit" | "/quit" => return Ok(()),
+            prompt => {
+                if let Err(error) = app.send(session_id, prompt).await {
+                    eprintln!("error: {}", error);
+                }
+            }
+        }
+    }
+}
+
+async fn run(cli: Cli) -> Result<(), AgentError> {
+    match &cli.command {
+        Command::Tree { config } => {
+            print_tree(&AgentConfig::from_file(config)?, "", "", "");
+            Ok(())
+        }
+        Command::Run { config, prompt, session } => {
+            let app = App::load(&cli, config).await?;
+            let prompt = if prompt.is_empty() { read_stdin().await? } else { prompt.join(" ") };
+            let session_id = app.session(session.clone()).await?;
+            app.send(&session_id, prompt.trim()).await
+        }
+        Command::Chat { config, session } => {
+            let app = App::load(&cli, config).await?;
+            let session_id = app.session(session.clone()).await?;
+            chat(&app, &session_id).await
+        }
+        #[cfg(feature = "server")]
+        Command::Serve { config, addr } => {
+            let app = App::load(&cli, config).await?;
+            let listener = tokio::net::TcpListener::bind(addr)
+                .await
+                .map_err(|e| AgentError::ConfigError(format!("Cannot listen on {}: {}", addr, e)))?;
+            eprintln!("Serving {} on http://{}", app.runner.app_name(), addr);
+            coagent::server::ApiServer::new(app.runner)
+                .serve(listener, async {
+                    let _ = tokio::signal::ctrl_c().await;
+                })
+                .await
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    if let Err(error) = run(Cli::parse()).await {
+        eprintln!("error: {}", error);
+        std::process::exit(1);
+    }
 }
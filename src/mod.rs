@@ -1 +0,0 @@
-mod agents;
\ No newline at end of file